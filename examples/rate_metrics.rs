@@ -224,6 +224,7 @@ async fn main() -> std::io::Result<()> {
                     &[1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0],
                 ),
             ],
+            ..Default::default()
         };
 
         let metrics_actix_dashboard = create_metrics_actx_scope(&dashboard_input).unwrap();
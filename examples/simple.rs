@@ -3,7 +3,8 @@ use log::info;
 use metrics::{Unit, counter, describe_counter, describe_gauge, describe_histogram, gauge};
 use metrics_exporter_prometheus::Matcher;
 use metrics_rs_dashboard_actix::{
-    DashboardInput, absolute_counter_with_rate, create_metrics_actx_scope,
+    AlertRule, DashboardInput, HistogramConfig, MetricsLogger, RelabelRule, Slo, SloIndicator,
+    SummaryConfig, absolute_counter_with_rate, create_metrics_actx_scope, histogram_with_exemplar,
 };
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
@@ -14,7 +15,12 @@ async fn hello() -> impl Responder {
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    env_logger::init();
+    // Count log records by level and target while still printing them with env_logger
+    let env_logger = env_logger::Builder::from_default_env().build();
+    let max_level = env_logger.filter();
+    MetricsLogger::new(env_logger)
+        .init(max_level)
+        .expect("Failed to install logger");
     info!("Starting Actix-Web server with metrics at /metrics");
 
     // Shared counter for demonstrating rate calculation
@@ -82,6 +88,11 @@ async fn main() -> std::io::Result<()> {
             Unit::Milliseconds,
             "Simulated latency of HTTP requests in milliseconds"
         );
+        describe_histogram!(
+            "response_size",
+            Unit::Bytes,
+            "Simulated size of HTTP responses"
+        );
         describe_gauge!(
             "request_latency_gauge",
             Unit::Milliseconds,
@@ -92,8 +103,12 @@ async fn main() -> std::io::Result<()> {
             // Simulate variable latency between 10-500ms
             let latency = rand::random::<f64>() * 490.0 + 10.0;
 
+            // Attach a fake trace id so the dashboard can show exemplars
+            let trace_id = format!("{:016x}", rand::random::<u64>());
             gauge!("request_latency_gauge").set(latency);
-            metrics::histogram!("request_latency").record(latency);
+            histogram_with_exemplar!("request_latency", latency, "trace_id", trace_id.as_str());
+            // Response sizes, exported as a summary with its own quantiles
+            metrics::histogram!("response_size").record(rand::random::<f64>() * 4096.0);
             // Occasionally simulate slower requests (simulate spikes)
             if rand::random::<f64>() < 0.1 {
                 // 10% chance of a slow request (500-2000ms)
@@ -112,6 +127,39 @@ async fn main() -> std::io::Result<()> {
                 Matcher::Prefix("request_latency".to_string()),
                 &[50.0, 100.0, 200.0, 500.0, 1000.0, 2000.0],
             )],
+            global_labels: vec![
+                ("service".to_string(), "simple".to_string()),
+                ("version".to_string(), env!("CARGO_PKG_VERSION").to_string()),
+            ],
+            relabel_rules: vec![RelabelRule::DropSeries {
+                label: "type".to_string(),
+                value: "gauge_2".to_string(),
+            }],
+            histograms: vec![(
+                Matcher::Full("response_size".to_string()),
+                HistogramConfig::Summary(SummaryConfig {
+                    quantiles: vec![0.5, 0.9, 0.99],
+                    window: std::time::Duration::from_secs(120),
+                    ..Default::default()
+                }),
+            )],
+            exemplar_link: Some("http://localhost:16686/trace/{trace_id}".to_string()),
+            alert_rules: vec![AlertRule {
+                name: "BusyApi".to_string(),
+                expr: r#"requests_with_rate_rate_per_sec{service="api"} > 3 for 10s"#.to_string(),
+                summary: Some("{value} requests/s on {service}".to_string()),
+                ..Default::default()
+            }],
+            slos: vec![Slo {
+                name: "fast-requests".to_string(),
+                indicator: SloIndicator::Latency {
+                    histogram: "request_latency".to_string(),
+                    threshold: 500.0,
+                },
+                objective: 0.85,
+                ..Default::default()
+            }],
+            ..Default::default()
         };
 
         let metrics_actix_dashboard = create_metrics_actx_scope(&dashboard_input).unwrap();
@@ -1,3 +1,5 @@
+import { seriesName } from "./metricUtils.js";
+
 /**
  * Class for buffering and managing metrics data.
  * Stores metrics in a Map with limited buffer size per metric.
@@ -19,7 +21,7 @@ class MetricBuffer {
   }
 
   /**
-   * Calculates the total buffer size based on unique label types
+   * Calculates the total buffer size based on unique label combinations
    * @private
    * @param {Array} metrics - Array of metric data points
    * @returns {number} The calculated buffer size
@@ -32,12 +34,8 @@ class MetricBuffer {
     const uniqueLabels = new Set();
     try {
       for (const sample of metrics) {
-        if (sample?.labels?.type) {
-          uniqueLabels.add(sample.labels.type);
-        } else if (sample?.labels?.phase) {
-          uniqueLabels.add(sample.labels.phase);
-        } else if (sample?.labels?.pattern) {
-          uniqueLabels.add(sample.labels.pattern);
+        if (sample?.labels) {
+          uniqueLabels.add(seriesName(sample.labels));
         }
       }
       return this.#bufferSize * (uniqueLabels.size || 1);
@@ -0,0 +1,129 @@
+/**
+ * MetricStream class for receiving live metric updates from the ./stream endpoint.
+ *
+ * The server sends Server-Sent Events with only the series that changed since
+ * the previous event, and no event while nothing changes. This class keeps the
+ * latest value of every series and hands complete metric families to the
+ * caller after each event, repeating them at the requested interval while the
+ * stream is idle so that charts keep advancing.
+ */
+class MetricStream {
+  /** @private Map of metric name to family with its series keyed by labels */
+  #families = new Map();
+  /** @private Currently open EventSource */
+  #source = null;
+  /** @private Timer repeating the metrics while no event arrives */
+  #idleTimer = null;
+
+  /**
+   * Creates a new MetricStream instance.
+   * @param {string} url - The URL of the metrics stream endpoint.
+   */
+  constructor(url) {
+    this.streamEndpoint = url;
+  }
+
+  /**
+   * Opens the stream, closing any previously open connection.
+   * @param {number} intervalMs - Requested interval between updates, the server may enforce a larger one.
+   * @param {Function} onMetrics - Called with the full list of metric families after every update.
+   * @param {Function} onError - Called when the connection fails.
+   */
+  connect(intervalMs, onMetrics, onError) {
+    this.close();
+    this.#families.clear();
+
+    const source = new EventSource(
+      `${this.streamEndpoint}?interval_ms=${encodeURIComponent(intervalMs)}`,
+    );
+
+    let lastUpdate = 0;
+    source.addEventListener("metrics", (event) => {
+      try {
+        this.applyDelta(JSON.parse(event.data));
+        lastUpdate = Date.now();
+        onMetrics(this.getMetrics());
+      } catch (error) {
+        console.warn("Failed to apply metrics update:", error);
+      }
+    });
+
+    this.#idleTimer = setInterval(() => {
+      if (lastUpdate > 0 && Date.now() - lastUpdate >= intervalMs) {
+        lastUpdate = Date.now();
+        onMetrics(this.getMetrics());
+      }
+    }, intervalMs);
+
+    source.onerror = () => {
+      onError(new Error("Metrics stream connection lost"));
+    };
+
+    this.#source = source;
+  }
+
+  /**
+   * Closes the stream if it is open.
+   */
+  close() {
+    if (this.#source) {
+      this.#source.close();
+      this.#source = null;
+    }
+    if (this.#idleTimer) {
+      clearInterval(this.#idleTimer);
+      this.#idleTimer = null;
+    }
+  }
+
+  /**
+   * Merges a delta received from the server into the current state.
+   * @param {Object} delta - The delta with changed `families` and `removed` series.
+   */
+  applyDelta(delta) {
+    for (const family of delta.families || []) {
+      let current = this.#families.get(family.name);
+      if (!current) {
+        current = { ...family, series: new Map() };
+        this.#families.set(family.name, current);
+      }
+      current.help = family.help;
+      current.type = family.type;
+      current.unit = family.unit;
+
+      for (const metric of family.metrics || []) {
+        current.series.set(JSON.stringify(metric.labels), metric);
+      }
+    }
+
+    for (const removed of delta.removed || []) {
+      const current = this.#families.get(removed.name);
+      if (!current) continue;
+
+      current.series.delete(JSON.stringify(removed.labels));
+      if (current.series.size === 0) {
+        this.#families.delete(removed.name);
+      }
+    }
+  }
+
+  /**
+   * Returns the latest state of every metric family.
+   *
+   * Metric points are copied, so callers may annotate them (e.g. with a timestamp).
+   * @returns {Array} Array of metric samples in the shape used by the charts.
+   */
+  getMetrics() {
+    return Array.from(this.#families.values()).map((family) => ({
+      name: family.name,
+      help: family.help,
+      type: family.type,
+      unit: family.unit || "count",
+      metrics: Array.from(family.series.values()).map((metric) => ({
+        ...metric,
+      })),
+    }));
+  }
+}
+
+export default MetricStream;
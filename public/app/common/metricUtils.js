@@ -1,3 +1,35 @@
+/**
+ * Builds a display name for a series from its labels.
+ *
+ * @description
+ * The `type` label is used as-is when present, keeping the grouping convention
+ * described in the README. Otherwise all label values are joined, so series
+ * such as `log_records_total{level,target}` get a distinct line per combination.
+ *
+ * @param {Object} labels - The labels of a single series.
+ * @returns {string} The series name, or "default" for series without labels.
+ *
+ * @example
+ * seriesName({ type: "cpu" }); // "cpu"
+ * seriesName({ level: "error", target: "app::db" }); // "error/app::db"
+ * seriesName({}); // "default"
+ */
+function seriesName(labels) {
+  if (!labels) {
+    return "default";
+  }
+
+  if (labels.type) {
+    return labels.type;
+  }
+
+  const values = Object.keys(labels)
+    .sort()
+    .map((key) => labels[key]);
+
+  return values.length > 0 ? values.join("/") : "default";
+}
+
 /**
  * Groups metrics data by label type to create a format compatible with ApexCharts.
  *
@@ -22,7 +54,7 @@
  */
 function groupByLabelType(metrics) {
   return metrics.reduce((acc, metric) => {
-    const label = metric?.labels?.type || "default";
+    const label = seriesName(metric?.labels);
     const current = acc.find((item) => item.name === label);
 
     if (current) {
@@ -53,4 +85,4 @@ function normalizeFloat(value) {
   return Number.parseFloat(value).toFixed(2);
 }
 
-export { groupByLabelType, normalizeFloat };
+export { groupByLabelType, normalizeFloat, seriesName };
@@ -0,0 +1,41 @@
+import { html } from "https://esm.sh/htm/preact/standalone";
+
+/**
+ * Formats the labels of an alert as `key="value"` pairs
+ * @param {Object} labels - Labels of the alert
+ * @returns {string} Comma separated label pairs
+ */
+const formatLabels = (labels) =>
+  Object.entries(labels || {})
+    .map(([key, value]) => `${key}="${value}"`)
+    .join(", ");
+
+/**
+ * AlertList component listing the active alerts above the charts
+ * @component
+ * @param {Object} props - Component props
+ * @param {Array} props.alerts - Active alerts from the ./alerts endpoint
+ * @returns {JSX.Element|null} Rendered list, or nothing without alerts
+ */
+function AlertList({ alerts }) {
+  if (!alerts || alerts.length === 0) {
+    return null;
+  }
+
+  return html`
+    <ul class="alert-list">
+      ${alerts.map(
+        (alert) => html`
+          <li class="alert-item alert-${alert.state}">
+            <strong>${alert.name}</strong>
+            <span class="alert-state">${alert.state}</span>
+            <span>${alert.summary || `${alert.metric} = ${alert.value}`}</span>
+            <small>${formatLabels(alert.labels)}</small>
+          </li>
+        `,
+      )}
+    </ul>
+  `;
+}
+
+export default AlertList;
@@ -5,11 +5,14 @@ import {
 } from "https://esm.sh/htm/preact/standalone";
 
 import MetricBuffer from "../common/MetricBuffer.js";
-import PrometheusImport from "../common/PrometheusImport.js";
+import MetricStream from "../common/MetricStream.js";
+import AlertList from "./AlertList.js";
 import CounterChart from "./CounterChart.js";
 import GaugeChart from "./GaugeChart.js";
 import HistogramChart from "./HistogramChart.js";
+import PercentileChart from "./PercentileChart.js";
 import RateChart from "./RateChart.js";
+import SloPanel from "./SloPanel.js";
 
 /**
  * Buffer for storing metric data with a default size of 10
@@ -18,17 +21,46 @@ import RateChart from "./RateChart.js";
 const metricBuffer = new MetricBuffer(10);
 
 /**
- * Importer for Prometheus metrics from the ./prometheus endpoint
- * @type {PrometheusImport}
+ * Live metric updates from the ./stream endpoint
+ * @type {MetricStream}
  */
-const prometheusImporter = new PrometheusImport("./prometheus");
+const metricStream = new MetricStream("./stream");
+
+/**
+ * Interval in milliseconds between two requests for the active alerts
+ * @constant {number}
+ */
+const ALERT_POLL_MS = 5000;
+
+/**
+ * Interval in milliseconds between two requests for the SLO statuses
+ * @constant {number}
+ */
+const SLO_POLL_MS = 10000;
+
+/**
+ * Wraps a chart in a highlighted container when alerts are active for its metric
+ * @param {JSX.Element} chart - The rendered chart
+ * @param {Array} alerts - Active alerts of the chart's metric
+ * @returns {JSX.Element} The chart, highlighted if needed
+ */
+const highlightAlerts = (chart, alerts) => {
+  if (alerts.length === 0) {
+    return chart;
+  }
+  const firing = alerts.some((alert) => alert.state === "firing");
+  return html`<div class=${firing ? "chart-alert firing" : "chart-alert pending"}>
+    ${chart}
+  </div>`;
+};
 
 /**
  * Renders the appropriate chart component based on metric type
  * @param {Object} sample - The metric sample data
+ * @param {boolean} pause - Whether updates are paused
  * @returns {JSX.Element} The rendered chart component
  */
-const renderChart = (sample) => {
+const renderChart = (sample, pause) => {
   if (!sample || !sample.type || !sample.name) {
     return html`<div class="error-chart">Invalid metric data</div>`;
   }
@@ -47,7 +79,13 @@ const renderChart = (sample) => {
         return html`<${GaugeChart} metricSample=${sample} />`;
       }
       case "HISTOGRAM": {
-        return html`<${HistogramChart} metricSample=${sample} />`;
+        return html`<div>
+          <${HistogramChart} metricSample=${sample} />
+          <${PercentileChart} metricSample=${sample} pause=${pause} />
+        </div>`;
+      }
+      case "SUMMARY": {
+        return html`<${PercentileChart} metricSample=${sample} pause=${pause} />`;
       }
       default: {
         return html`<h1>Unsupported metric type: ${sample.type}</h1>`;
@@ -66,41 +104,85 @@ const renderChart = (sample) => {
  * @component
  * @param {Object} props - Component props
  * @param {string} props.searchValue - Text to filter metrics by name
- * @param {number} props.refreshRate - How often to refresh metrics in milliseconds
+ * @param {number} props.refreshRate - Requested interval between metric updates in milliseconds
  * @param {number} props.bufferSize - Size of the metric buffer
  * @param {boolean} props.pause - Whether to pause metric updates
  * @returns {JSX.Element} Rendered grid of metric charts
  */
 function ChartGrid({ searchValue, refreshRate, bufferSize, pause }) {
   /**
-   * State for storing the filtered metrics
+   * State for storing all buffered metrics, before the search filter
    * @type {[Array, Function]}
    */
   const [metrics, setMetrics] = useState([]);
 
   /**
-   * Effect for fetching and updating metrics at the specified refresh rate
+   * State for storing the active alerts
+   * @type {[Array, Function]}
+   */
+  const [alerts, setAlerts] = useState([]);
+
+  /**
+   * Effect for polling the active alerts while updates aren't paused
    */
   useEffect(() => {
-    const interval = setInterval(async () => {
-      if (pause) {
-        return;
-      }
+    if (pause) {
+      return;
+    }
+
+    const fetchAlerts = () =>
+      fetch("./alerts")
+        .then((response) => (response.ok ? response.json() : { alerts: [] }))
+        .then((body) => setAlerts(body.alerts || []))
+        .catch((error) => console.warn("Failed to fetch alerts:", error));
+
+    fetchAlerts();
+    const timer = setInterval(fetchAlerts, ALERT_POLL_MS);
+    return () => clearInterval(timer);
+  }, [pause]);
 
-      try {
-        const metrics = await prometheusImporter.fetchMetrics();
-        if (metrics && Array.isArray(metrics)) {
-          metricBuffer.addMetrics(metrics);
-          const filteredMetrics = metricBuffer.getMetrics().filter((sample) => {
-            if (!sample || !sample.name) return false;
-            if (!searchValue) return true;
-            return sample.name
-              .toLowerCase()
-              .includes(searchValue.toLowerCase());
-          });
-          setMetrics(filteredMetrics);
-        }
-      } catch (error) {
+  /**
+   * State for storing the SLO statuses
+   * @type {[Array, Function]}
+   */
+  const [slos, setSlos] = useState([]);
+
+  /**
+   * Effect for polling the SLO statuses while updates aren't paused
+   */
+  useEffect(() => {
+    if (pause) {
+      return;
+    }
+
+    const fetchSlos = () =>
+      fetch("./slo")
+        .then((response) => (response.ok ? response.json() : { slos: [] }))
+        .then((body) => setSlos(body.slos || []))
+        .catch((error) => console.warn("Failed to fetch SLOs:", error));
+
+    fetchSlos();
+    const timer = setInterval(fetchSlos, SLO_POLL_MS);
+    return () => clearInterval(timer);
+  }, [pause]);
+
+  /**
+   * Effect for subscribing to live metric updates at the specified refresh rate
+   */
+  useEffect(() => {
+    if (pause) {
+      return;
+    }
+
+    metricStream.connect(
+      refreshRate,
+      (metrics) => {
+        metricBuffer.addMetrics(metrics);
+        setMetrics(
+          metricBuffer.getMetrics().filter((sample) => sample && sample.name),
+        );
+      },
+      (error) => {
         console.error("Error fetching metrics:", error);
         // Display error to user
         setMetrics((prevMetrics) => {
@@ -118,10 +200,10 @@ function ChartGrid({ searchValue, refreshRate, bufferSize, pause }) {
           }
           return prevMetrics;
         });
-      }
-    }, refreshRate);
-    return () => clearInterval(interval);
-  }, [refreshRate, searchValue, pause]);
+      },
+    );
+    return () => metricStream.close();
+  }, [refreshRate, pause]);
 
   /**
    * Effect for updating the buffer size when it changes
@@ -130,12 +212,35 @@ function ChartGrid({ searchValue, refreshRate, bufferSize, pause }) {
     metricBuffer.setBufferSize(bufferSize);
   }, [bufferSize]);
 
+  /**
+   * Metrics matching the search, filtered here so that typing doesn't
+   * reconnect the stream. Errors are always shown.
+   */
+  const search = searchValue ? searchValue.toLowerCase() : "";
+  const visibleMetrics = metrics.filter(
+    (sample) =>
+      !search ||
+      sample.type === "ERROR" ||
+      sample.name.toLowerCase().includes(search),
+  );
+
   return html`
+    <${AlertList} alerts=${alerts} />
+    <${SloPanel} slos=${slos} />
     <div class="responsive-grid">
-      ${metrics && metrics.length > 0
-        ? metrics.map((sample) => (sample ? renderChart(sample) : null))
+      ${visibleMetrics.length > 0
+        ? visibleMetrics.map((sample) =>
+            sample
+              ? highlightAlerts(
+                  renderChart(sample, pause),
+                  alerts.filter((alert) => alert.metric === sample.name),
+                )
+              : null,
+          )
         : html`<div class="empty-state">
-            No metrics available. Please check your configuration.
+            ${metrics.length > 0
+              ? `No metrics match "${searchValue}".`
+              : "No metrics available. Please check your configuration."}
           </div>`}
     </div>
   `;
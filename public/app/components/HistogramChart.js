@@ -26,6 +26,18 @@ function HistogramChart({ metricSample }) {
       };
     });
 
+    // Most recent exemplar of each bucket, drawn as a point on top of its bar
+    const exemplars = latestSample.exemplars || {};
+    const exemplarData = bucketKeys.map((key, index) => {
+      const exemplar = exemplars[String(key)];
+      return {
+        x: data[index].x,
+        y: exemplar ? data[index].y : null,
+        exemplar,
+      };
+    });
+    const hasExemplars = exemplarData.some((point) => point.exemplar);
+
     const options = {
       title: {
         text: metricSample.name,
@@ -52,6 +64,17 @@ function HistogramChart({ metricSample }) {
         animations: {
           enabled: false,
         },
+        events: {
+          dataPointSelection: (_event, _context, { seriesIndex, dataPointIndex }) => {
+            const exemplar = exemplarData[dataPointIndex]?.exemplar;
+            if (seriesIndex === 1 && exemplar?.link) {
+              window.open(exemplar.link, "_blank", "noopener");
+            }
+          },
+        },
+      },
+      markers: {
+        size: [0, 6],
       },
       plotOptions: {
         bar: {
@@ -64,8 +87,18 @@ function HistogramChart({ metricSample }) {
       series: [
         {
           name: "Frequency",
+          type: "bar",
           data: data,
         },
+        ...(hasExemplars
+          ? [
+              {
+                name: "Exemplar",
+                type: "scatter",
+                data: exemplarData,
+              },
+            ]
+          : []),
       ],
       xaxis: {
         title: {
@@ -81,11 +114,25 @@ function HistogramChart({ metricSample }) {
         },
       },
       tooltip: {
+        // Exemplar points can only be clicked when the tooltip follows them
+        shared: false,
+        intersect: true,
         x: {
           formatter: (val) => {
             return `Range: ${val}`;
           },
         },
+        y: {
+          formatter: (val, { seriesIndex, dataPointIndex }) => {
+            const exemplar = exemplarData[dataPointIndex]?.exemplar;
+            if (seriesIndex !== 1 || !exemplar) return val;
+
+            const labels = Object.entries(exemplar.labels)
+              .map(([key, value]) => `${key}=${value}`)
+              .join(", ");
+            return `${normalizeFloat(exemplar.value)} ${unit} (${labels})`;
+          },
+        },
       },
     };
 
@@ -0,0 +1,138 @@
+import {
+  html,
+  useEffect,
+  useRef,
+  useState,
+} from "https://esm.sh/htm/preact/standalone";
+import { normalizeFloat, seriesName } from "../common/metricUtils.js";
+
+/**
+ * Interval in milliseconds between two requests for the percentiles
+ * @constant {number}
+ */
+const PERCENTILE_POLL_MS = 10000;
+
+/**
+ * Converts the ./api/quantiles response into ApexCharts series, one line per quantile and series
+ * @param {Array} series - Series of the response with their labels and quantile values
+ * @returns {Array<Object>} Series named like `p99` or `p99 /a`
+ */
+const toChartSeries = (series) =>
+  series.flatMap(({ labels, quantiles }) =>
+    Object.entries(quantiles).map(([quantile, values]) => {
+      const percentile = `p${Number.parseFloat(quantile) * 100}`;
+      return {
+        name:
+          Object.keys(labels).length > 0
+            ? `${percentile} ${seriesName(labels)}`
+            : percentile,
+        data: values.map(([time, value]) => ({ x: time * 1000, y: value })),
+      };
+    }),
+  );
+
+/**
+ * PercentileChart component plotting server-computed p50/p90/p99 of a histogram or summary over time
+ * @component
+ * @param {Object} props - Component props
+ * @param {Object} props.metricSample - The metric sample data, providing name and unit
+ * @param {boolean} props.pause - Whether to pause refreshing the percentiles
+ * @returns {JSX.Element} Rendered percentile chart
+ */
+function PercentileChart({ metricSample, pause }) {
+  const chartRef = useRef(null);
+
+  /**
+   * Percentiles of the latest response and where they come from
+   * @type {[Object, Function]}
+   */
+  const [percentiles, setPercentiles] = useState({ source: null, series: [] });
+
+  /**
+   * Effect for polling the percentiles while updates aren't paused
+   */
+  useEffect(() => {
+    if (pause) {
+      return;
+    }
+
+    const params = new URLSearchParams({ metric: metricSample.name });
+    const fetchPercentiles = () =>
+      fetch(`./api/quantiles?${params}`)
+        .then((response) => response.json())
+        .then((body) => {
+          if (body.status === "success") {
+            setPercentiles({
+              source: body.data.source,
+              series: toChartSeries(body.data.series),
+            });
+          }
+        })
+        .catch((error) => console.warn("Failed to fetch percentiles:", error));
+
+    fetchPercentiles();
+    const timer = setInterval(fetchPercentiles, PERCENTILE_POLL_MS);
+    return () => clearInterval(timer);
+  }, [metricSample.name, pause]);
+
+  useEffect(() => {
+    const unit = metricSample.unit || "count";
+    const chart = new ApexCharts(chartRef.current, {
+      title: {
+        text: `${metricSample.name} percentiles`,
+        align: "left",
+        style: {
+          fontSize: "24px",
+          color: "#fff",
+        },
+      },
+      subtitle: {
+        text:
+          percentiles.source === "exact"
+            ? "Recorded quantiles"
+            : "Interpolated from bucket rates over 1m",
+        align: "left",
+        style: {
+          fontSize: "16px",
+          color: "#fff",
+        },
+      },
+      chart: {
+        type: "line",
+        height: 350,
+        animations: {
+          enabled: false,
+        },
+      },
+      noData: {
+        text: "Waiting for history samples",
+      },
+      series: percentiles.series,
+      xaxis: {
+        type: "datetime",
+      },
+      yaxis: {
+        title: {
+          text: unit,
+        },
+        labels: {
+          formatter: normalizeFloat,
+        },
+      },
+      tooltip: {
+        x: {
+          format: "HH:mm:ss",
+        },
+      },
+    });
+    chart.render();
+
+    return () => {
+      chart.destroy();
+    };
+  }, [metricSample.name, metricSample.unit, JSON.stringify(percentiles)]);
+
+  return html`<div ref=${chartRef}></div>`;
+}
+
+export default PercentileChart;
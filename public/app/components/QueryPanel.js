@@ -0,0 +1,178 @@
+import {
+  html,
+  useEffect,
+  useRef,
+  useState,
+} from "https://esm.sh/htm/preact/standalone";
+import { normalizeFloat, seriesName } from "../common/metricUtils.js";
+
+/**
+ * Time range shown by the query chart in seconds
+ * @constant {number}
+ */
+const QUERY_RANGE_SECONDS = 15 * 60;
+
+/**
+ * Resolution of the query chart in seconds, matching the default history interval
+ * @constant {number}
+ */
+const QUERY_STEP_SECONDS = 10;
+
+/**
+ * Converts a range query result into ApexCharts series
+ * @param {Array} result - Matrix result of the ./api/query endpoint
+ * @returns {Array<Object>} Series with a name and `{ x, y }` points
+ */
+const toSeries = (result) =>
+  result.map(({ metric, values }) => {
+    const { __name__: name, ...labels } = metric;
+    return {
+      name: Object.keys(labels).length > 0 ? seriesName(labels) : name || "value",
+      data: values.map(([time, value]) => ({
+        x: time * 1000,
+        y: Number.parseFloat(value),
+      })),
+    };
+  });
+
+/**
+ * Line chart of a query result
+ * @param {Object} props - Component props
+ * @param {string} props.query - The evaluated expression, used as the title
+ * @param {Array<Object>} props.series - Series to draw
+ * @returns {JSX.Element} Rendered chart
+ */
+function QueryChart({ query, series }) {
+  const chartRef = useRef(null);
+
+  useEffect(() => {
+    const chart = new ApexCharts(chartRef.current, {
+      title: {
+        text: query,
+        align: "left",
+        style: {
+          fontSize: "16px",
+          color: "#fff",
+        },
+      },
+      chart: {
+        type: "line",
+        height: 350,
+        animations: {
+          enabled: false,
+        },
+      },
+      series,
+      xaxis: {
+        type: "datetime",
+      },
+      yaxis: {
+        labels: {
+          formatter: normalizeFloat,
+        },
+      },
+      tooltip: {
+        x: {
+          format: "HH:mm:ss",
+        },
+      },
+    });
+    chart.render();
+
+    return () => {
+      chart.destroy();
+    };
+  }, [query, JSON.stringify(series)]);
+
+  return html`<div ref=${chartRef}></div>`;
+}
+
+/**
+ * QueryPanel component charting an expression evaluated by the server over the last minutes
+ * @component
+ * @param {Object} props - Component props
+ * @param {boolean} props.pause - Whether to pause refreshing the chart
+ * @returns {JSX.Element} Rendered query input and chart
+ */
+function QueryPanel({ pause }) {
+  /**
+   * Expression typed by the user
+   * @type {[string, Function]}
+   */
+  const [input, setInput] = useState("");
+
+  /**
+   * Expression currently charted
+   * @type {[string, Function]}
+   */
+  const [query, setQuery] = useState("");
+
+  /**
+   * Series of the latest result
+   * @type {[Array, Function]}
+   */
+  const [series, setSeries] = useState([]);
+
+  /**
+   * Error of the latest request
+   * @type {[string|null, Function]}
+   */
+  const [error, setError] = useState(null);
+
+  /**
+   * Effect for evaluating the query and refreshing it while updates aren't paused
+   */
+  useEffect(() => {
+    if (!query || pause) {
+      return;
+    }
+
+    const runQuery = () => {
+      const end = Date.now() / 1000;
+      const params = new URLSearchParams({
+        query,
+        start: end - QUERY_RANGE_SECONDS,
+        end,
+        step: QUERY_STEP_SECONDS,
+      });
+      fetch(`./api/query?${params}`)
+        .then((response) => response.json())
+        .then((body) => {
+          if (body.status === "success") {
+            setSeries(toSeries(body.data.result));
+            setError(null);
+          } else {
+            setError(body.error);
+          }
+        })
+        .catch((error) => setError(error.message));
+    };
+
+    runQuery();
+    const timer = setInterval(runQuery, QUERY_STEP_SECONDS * 1000);
+    return () => clearInterval(timer);
+  }, [query, pause]);
+
+  const handleSubmit = (e) => {
+    e.preventDefault();
+    setQuery(input.trim());
+  };
+
+  return html`
+    <form class="query-panel" onSubmit=${handleSubmit}>
+      <input
+        type="text"
+        name="query"
+        placeholder='Query, e.g. sum by (service) (rate(http_requests_total[1m]))'
+        aria-label="Query"
+        value=${input}
+        onInput=${(e) => setInput(e.target.value)}
+      />
+      <button type="submit">Run</button>
+    </form>
+    ${error && html`<div class="query-error">${error}</div>`}
+    ${query && !error && html`<${QueryChart} query=${query} series=${series} />`}
+  `;
+}
+
+export default QueryPanel;
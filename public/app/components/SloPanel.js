@@ -0,0 +1,57 @@
+import { html } from "https://esm.sh/htm/preact/standalone";
+
+/**
+ * Formats a ratio as a percentage
+ * @param {number} value - Ratio between 0 and 1
+ * @param {number} digits - Number of decimal places
+ * @returns {string} Formatted percentage
+ */
+const formatPercent = (value, digits = 3) => `${(value * 100).toFixed(digits)}%`;
+
+/**
+ * SloPanel component showing availability, error budget and burn rates of every SLO
+ * @component
+ * @param {Object} props - Component props
+ * @param {Array} props.slos - SLO statuses from the ./slo endpoint
+ * @returns {JSX.Element|null} Rendered panel, or nothing without SLOs
+ */
+function SloPanel({ slos }) {
+  if (!slos || slos.length === 0) {
+    return null;
+  }
+
+  return html`
+    <section class="slo-panel">
+      ${slos.map((slo) => {
+        const budget = Math.max(0, Math.min(1, slo.error_budget_remaining));
+        const exhausted = slo.error_budget_remaining <= 0;
+        return html`
+          <div class=${exhausted ? "slo-card exhausted" : "slo-card"}>
+            <h3>${slo.name}</h3>
+            <div class="slo-availability">
+              <strong>${formatPercent(slo.availability)}</strong>
+              <small>objective ${formatPercent(slo.objective)} over ${slo.window}</small>
+            </div>
+            <div class="slo-budget" title="Error budget remaining">
+              <div class="slo-budget-fill" style=${{ width: formatPercent(budget, 1) }}></div>
+            </div>
+            <small>
+              ${formatPercent(slo.error_budget_remaining, 1)} of the error budget left
+            </small>
+            <ul class="slo-burn-rates">
+              ${slo.burn_rates.map(
+                (burnRate) => html`
+                  <li class=${burnRate.value > 1 ? "burning" : ""}>
+                    ${burnRate.window}: ${burnRate.value.toFixed(2)}x
+                  </li>
+                `,
+              )}
+            </ul>
+          </div>
+        `;
+      })}
+    </section>
+  `;
+}
+
+export default SloPanel;
@@ -9,6 +9,7 @@ import {
 import apexDefaultTheme from "./common/apexDefaultTheme.js";
 import debounce from "./common/debounce.js";
 import ChartGrid from "./components/ChartGrid.js";
+import QueryPanel from "./components/QueryPanel.js";
 
 /**
  * Initializes the default Apex chart theme
@@ -220,6 +221,9 @@ function App(props) {
           </button>
         </label>
       </section>
+      <section>
+        <${QueryPanel} pause=${pause} />
+      </section>
       <section>
         <${ChartGrid}
           searchValue=${debouncedSearchValue}
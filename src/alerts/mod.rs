@@ -0,0 +1,658 @@
+//! # Alerting Rules
+//!
+//! Simple threshold alerts evaluated in-process, without Prometheus or
+//! Alertmanager. A rule compares every sample of a metric, optionally
+//! narrowed down by label matchers, with a threshold:
+//!
+//! ```text
+//! http_errors_total_rate_per_sec{endpoint="/api"} > 5 for 30s
+//! ```
+//!
+//! Rules are evaluated on a fixed tick against the same snapshot the
+//! dashboard shows, which includes the `_rate_per_sec` gauges maintained by
+//! the rate trackers and the series of scrape targets. A matching sample
+//! makes its alert pending; once the condition has held for the rule's
+//! `for` duration the alert fires. Alerts disappear as soon as the
+//! condition no longer holds, or once the rule's `keep_firing_for` has
+//! passed for firing alerts, which bridges series that briefly disappear
+//! or dip below the threshold. Active alerts are served at `/metrics/alerts`
+//! and, if configured, sent to notification sinks (see [`AlertNotifierConfig`]).
+
+mod notify;
+
+pub use notify::{AlertNotification, AlertNotifierConfig, AlertSink, NotificationStatus};
+
+use crate::{
+    selector::Selector,
+    snapshot::{Snapshot, series_key},
+};
+use actix_web::{HttpResponse, Responder};
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+use tokio::time::MissedTickBehavior;
+
+/// Default interval between two evaluations of the alert rules
+pub(crate) const DEFAULT_EVALUATION_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Engine evaluating the configured rules
+static ENGINE: OnceLock<AlertEngine> = OnceLock::new();
+
+/// A threshold alert on the samples of a metric
+///
+/// # Example
+///
+/// ```
+/// use metrics_rs_dashboard_actix::{AlertRule, DashboardInput};
+/// use std::time::Duration;
+///
+/// let dashboard_input = DashboardInput {
+///     alert_rules: vec![AlertRule {
+///         name: "HighErrorRate".to_string(),
+///         expr: "http_errors_total_rate_per_sec > 5".to_string(),
+///         for_duration: Duration::from_secs(30),
+///         summary: Some("{value} errors/s on {endpoint}".to_string()),
+///         ..Default::default()
+///     }],
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AlertRule {
+    /// Name of the alert, e.g. `HighErrorRate`
+    pub name: String,
+    /// Condition in the form `metric{label="value"} > threshold`
+    ///
+    /// Label matchers may use `=`, `!=`, `=~` or `!~`; the comparison may be `>`, `>=`,
+    /// `<`, `<=`, `==` or `!=`. Histogram and summary samples are addressed
+    /// by their exposition names, e.g. `latency_count`. A trailing
+    /// `for 30s` takes precedence over `for_duration`.
+    pub expr: String,
+    /// How long the condition must hold before the alert fires; written as
+    /// `for = "30s"` in TOML
+    #[serde(rename = "for", default, deserialize_with = "deserialize_duration")]
+    pub for_duration: Duration,
+    /// How long a firing alert keeps firing after its condition last held,
+    /// including while its series is missing; written as
+    /// `keep_firing_for = "1m"` in TOML
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub keep_firing_for: Duration,
+    /// Labels added to every alert of this rule, e.g. `severity`
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    /// Human readable description; `{value}` and `{label}` placeholders are
+    /// replaced with the sample's value and label values
+    #[serde(default)]
+    pub summary: Option<String>,
+}
+
+/// Top level layout of an alert rule file
+#[derive(Debug, Deserialize)]
+struct RuleFile {
+    #[serde(default)]
+    rules: Vec<AlertRule>,
+}
+
+/// Loads alert rules from a TOML file
+///
+/// The file contains a `[[rules]]` table per rule:
+///
+/// ```toml
+/// [[rules]]
+/// name = "HighErrorRate"
+/// expr = "http_errors_total_rate_per_sec > 5"
+/// for = "30s"
+/// summary = "{value} errors/s"
+/// labels = { severity = "page" }
+/// ```
+///
+/// # Errors
+///
+/// Returns an error if the file can't be read or isn't valid TOML. Rule
+/// expressions are validated when the dashboard is configured.
+pub fn load_alert_rules(path: impl AsRef<Path>) -> Result<Vec<AlertRule>> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read alert rules from {}", path.display()))?;
+    parse_alert_rules(&text)
+        .with_context(|| format!("Failed to parse alert rules from {}", path.display()))
+}
+
+fn parse_alert_rules(text: &str) -> Result<Vec<AlertRule>> {
+    Ok(toml::from_str::<RuleFile>(text)?.rules)
+}
+
+fn deserialize_duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    parse_duration(&text).map_err(serde::de::Error::custom)
+}
+
+/// Parses durations such as `500ms`, `30s`, `5m`, `1h` or `1m30s`
+pub(crate) fn parse_duration(text: &str) -> Result<Duration> {
+    let mut total = Duration::ZERO;
+    let mut rest = text.trim();
+    if rest.is_empty() {
+        bail!("empty duration");
+    }
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(|| anyhow!("missing unit in duration '{}'", text))?;
+        let amount: u32 = rest[..digits]
+            .parse()
+            .map_err(|_| anyhow!("invalid duration '{}'", text))?;
+        rest = &rest[digits..];
+        let unit_length = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let unit = match &rest[..unit_length] {
+            "ms" => Duration::from_millis(1),
+            "s" => Duration::from_secs(1),
+            "m" => Duration::from_secs(60),
+            "h" => Duration::from_secs(3600),
+            "d" => Duration::from_secs(86400),
+            other => bail!("unknown unit '{}' in duration '{}'", other, text),
+        };
+        total = unit
+            .checked_mul(amount)
+            .and_then(|amount| total.checked_add(amount))
+            .ok_or_else(|| anyhow!("duration '{}' out of range", text))?;
+        rest = &rest[unit_length..];
+    }
+    Ok(total)
+}
+
+/// Comparison between a sample and the threshold
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Comparison {
+    /// Operators ordered so that two-character operators are tried first
+    const OPERATORS: [(&'static str, Comparison); 6] = [
+        (">=", Comparison::GreaterOrEqual),
+        ("<=", Comparison::LessOrEqual),
+        ("==", Comparison::Equal),
+        ("!=", Comparison::NotEqual),
+        (">", Comparison::Greater),
+        ("<", Comparison::Less),
+    ];
+
+    fn holds(self, value: f64, threshold: f64) -> bool {
+        match self {
+            Comparison::Greater => value > threshold,
+            Comparison::GreaterOrEqual => value >= threshold,
+            Comparison::Less => value < threshold,
+            Comparison::LessOrEqual => value <= threshold,
+            Comparison::Equal => value == threshold,
+            Comparison::NotEqual => value != threshold,
+        }
+    }
+}
+
+/// Parsed form of a rule expression
+#[derive(Debug, Clone)]
+struct Condition {
+    selector: Selector,
+    comparison: Comparison,
+    threshold: f64,
+    /// Duration given with a trailing `for ...` clause
+    for_duration: Option<Duration>,
+}
+
+impl Condition {
+    /// Parses `metric{label="value",...} <op> <threshold> [for <duration>]`
+    fn parse(expr: &str) -> Result<Self> {
+        let expr = expr.trim();
+        let selector_end = match expr.find('{') {
+            Some(_) => expr
+                .rfind('}')
+                .map(|end| end + 1)
+                .ok_or_else(|| anyhow!("unclosed '{{' in '{}'", expr))?,
+            None => expr
+                .find(|c: char| c.is_whitespace() || "<>=!".contains(c))
+                .unwrap_or(expr.len()),
+        };
+        let (selector, rest) = expr.split_at(selector_end);
+        let selector = Selector::parse(selector)?;
+
+        let rest = rest.trim();
+        let (comparison, threshold) = Comparison::OPERATORS
+            .iter()
+            .find_map(|(operator, comparison)| {
+                rest.strip_prefix(operator)
+                    .map(|threshold| (*comparison, threshold.trim()))
+            })
+            .ok_or_else(|| anyhow!("missing comparison in '{}'", expr))?;
+        let (threshold, for_duration) = match threshold.split_once(" for ") {
+            Some((threshold, duration)) => (threshold.trim(), Some(parse_duration(duration)?)),
+            None => (threshold, None),
+        };
+        let threshold = threshold
+            .parse()
+            .map_err(|_| anyhow!("invalid threshold '{}' in '{}'", threshold, expr))?;
+
+        Ok(Self {
+            selector,
+            comparison,
+            threshold,
+            for_duration,
+        })
+    }
+}
+
+/// State of an alert
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertState {
+    /// The condition holds, but not yet for the rule's `for` duration
+    Pending,
+    /// The condition has held for at least the rule's `for` duration
+    Firing,
+    /// The alert was firing and its condition no longer holds; only used
+    /// in notifications
+    Resolved,
+}
+
+/// An alert of a rule for a single series
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Alert {
+    /// Name of the rule
+    pub name: String,
+    /// Family of the alerting sample, used by the dashboard to highlight its chart
+    pub metric: String,
+    /// Labels of the sample together with the labels of the rule
+    pub labels: BTreeMap<String, String>,
+    pub state: AlertState,
+    /// Last evaluated value of the sample
+    pub value: f64,
+    /// When the condition started to hold
+    pub active_since: DateTime<Utc>,
+    /// When the condition stopped holding, for resolved alerts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved_at: Option<DateTime<Utc>>,
+    /// The rule's summary with its placeholders replaced
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+}
+
+/// An active alert together with the last time its condition held
+#[derive(Debug, Clone)]
+struct ActiveAlert {
+    alert: Alert,
+    last_held: DateTime<Utc>,
+}
+
+/// Rules together with their active alerts
+pub(crate) struct AlertEngine {
+    rules: Vec<(AlertRule, Condition)>,
+    /// Active alerts keyed by rule name and series
+    active: Mutex<BTreeMap<(String, String), ActiveAlert>>,
+}
+
+impl AlertEngine {
+    /// Parses the expressions of all rules
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the first rule with an invalid expression, or
+    /// a name used by several rules, whose alerts would overwrite each other
+    pub fn new(rules: &[AlertRule]) -> Result<Self> {
+        let mut names = BTreeSet::new();
+        if let Some(rule) = rules.iter().find(|rule| !names.insert(rule.name.as_str())) {
+            bail!("Alert rule name '{}' is used more than once", rule.name);
+        }
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                Condition::parse(&rule.expr)
+                    .map(|condition| (rule.clone(), condition))
+                    .with_context(|| format!("Invalid expression of alert rule '{}'", rule.name))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            rules,
+            active: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// Evaluates every rule against a snapshot taken at `now`
+    pub fn evaluate(&self, snapshot: &Snapshot, now: DateTime<Utc>) {
+        let samples = snapshot.samples();
+        let Ok(mut active) = self.active.lock() else {
+            return;
+        };
+        let mut current = BTreeMap::new();
+
+        for (rule, condition) in &self.rules {
+            for sample in samples
+                .iter()
+                .filter(|sample| condition.selector.matches(sample))
+            {
+                if !condition
+                    .comparison
+                    .holds(sample.value, condition.threshold)
+                {
+                    continue;
+                }
+                let key = (rule.name.clone(), series_key(&sample.labels));
+                let active_since = active
+                    .get(&key)
+                    .map_or(now, |active| active.alert.active_since);
+                let held_for = (now - active_since).to_std().unwrap_or_default();
+                let mut labels = sample.labels.clone();
+                labels.extend(rule.labels.clone());
+
+                let alert = Alert {
+                    name: rule.name.clone(),
+                    metric: sample.family.clone(),
+                    summary: rule
+                        .summary
+                        .as_deref()
+                        .map(|summary| expand_summary(summary, sample.value, &labels)),
+                    labels,
+                    state: if held_for >= condition.for_duration.unwrap_or(rule.for_duration) {
+                        AlertState::Firing
+                    } else {
+                        AlertState::Pending
+                    },
+                    value: sample.value,
+                    active_since,
+                    resolved_at: None,
+                };
+                current.insert(
+                    key,
+                    ActiveAlert {
+                        alert,
+                        last_held: now,
+                    },
+                );
+            }
+        }
+
+        // Firing alerts whose condition no longer holds, or whose series is
+        // missing, keep firing with their last value for `keep_firing_for`
+        for (key, previous) in std::mem::take(&mut *active) {
+            if current.contains_key(&key) || previous.alert.state != AlertState::Firing {
+                continue;
+            }
+            let keep_firing_for = self
+                .rules
+                .iter()
+                .find(|(rule, _)| rule.name == key.0)
+                .map_or(Duration::ZERO, |(rule, _)| rule.keep_firing_for);
+            let since_held = (now - previous.last_held).to_std().unwrap_or_default();
+            if since_held < keep_firing_for {
+                current.insert(key, previous);
+            }
+        }
+
+        *active = current;
+    }
+
+    /// Returns all active alerts, firing ones first
+    pub fn alerts(&self) -> Vec<Alert> {
+        let mut alerts: Vec<Alert> = self
+            .active
+            .lock()
+            .map(|active| active.values().map(|active| active.alert.clone()).collect())
+            .unwrap_or_default();
+        alerts.sort_by_key(|alert| alert.state != AlertState::Firing);
+        alerts
+    }
+}
+
+/// Replaces `{value}` and `{label}` placeholders of a summary
+fn expand_summary(summary: &str, value: f64, labels: &BTreeMap<String, String>) -> String {
+    let mut expanded = summary.replace("{value}", &value.to_string());
+    for (key, label_value) in labels {
+        expanded = expanded.replace(&format!("{{{}}}", key), label_value);
+    }
+    expanded
+}
+
+/// Starts evaluating the rules of the engine on `interval`, sending
+/// notifications of firing and resolved alerts if a notifier is configured
+///
+/// Must be called from within a Tokio runtime.
+pub(crate) fn start_alerting(
+    engine: AlertEngine,
+    interval: Duration,
+    notifier: Option<AlertNotifierConfig>,
+) {
+    if ENGINE.set(engine).is_err() {
+        return;
+    }
+    let mut notifier = notifier.map(notify::Notifier::new);
+
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            let Some(engine) = ENGINE.get() else {
+                continue;
+            };
+            let now = Utc::now();
+            engine.evaluate(&Snapshot::capture_with_targets(), now);
+            if let Some(notifier) = &mut notifier {
+                for notification in notifier.update(&engine.alerts(), now) {
+                    notifier.send(&notification).await;
+                }
+            }
+        }
+    });
+}
+
+/// Response of the alerts endpoint
+#[derive(Serialize)]
+struct AlertsResponse {
+    alerts: Vec<Alert>,
+}
+
+/// Endpoint listing the active alerts
+///
+/// # Returns
+///
+/// JSON object with an `alerts` array, firing alerts first
+#[actix_web::get("/alerts")]
+pub(crate) async fn get_alerts() -> impl Responder {
+    let alerts = ENGINE.get().map(AlertEngine::alerts).unwrap_or_default();
+    HttpResponse::Ok().json(AlertsResponse { alerts })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPOSITION: &str = r#"# TYPE http_errors_total_rate_per_sec gauge
+http_errors_total_rate_per_sec{endpoint="/api"} 7.5
+http_errors_total_rate_per_sec{endpoint="/health"} 9
+# TYPE latency histogram
+latency_bucket{le="+Inf"} 4
+latency_sum 2
+latency_count 4
+"#;
+
+    fn rule(expr: &str, for_duration: Duration) -> AlertRule {
+        AlertRule {
+            name: "HighErrorRate".to_string(),
+            expr: expr.to_string(),
+            for_duration,
+            summary: Some("{value} errors/s on {endpoint}".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_condition() {
+        let condition =
+            Condition::parse(r#"http_errors_total{endpoint="/api", code!="404"} >= 5.5"#).unwrap();
+
+        assert_eq!(condition.selector.metric, "http_errors_total");
+        assert_eq!(condition.comparison, Comparison::GreaterOrEqual);
+        assert_eq!(condition.threshold, 5.5);
+        assert_eq!(condition.selector.matchers.len(), 2);
+        assert_eq!(condition.for_duration, None);
+
+        let condition = Condition::parse("http_errors_total_rate_per_sec > 5 for 30s").unwrap();
+        assert_eq!(condition.threshold, 5.0);
+        assert_eq!(condition.for_duration, Some(Duration::from_secs(30)));
+
+        assert_eq!(
+            Condition::parse("up==0").unwrap().comparison,
+            Comparison::Equal
+        );
+        assert!(Condition::parse("up").is_err());
+        assert!(Condition::parse("up > five").is_err());
+        assert!(Condition::parse(r#"up{job="a" > 1"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("1m30s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert!(parse_duration("30").is_err());
+        assert!(parse_duration("3w").is_err());
+    }
+
+    #[test]
+    fn test_parse_rule_file() {
+        let rules = parse_alert_rules(
+            r#"
+[[rules]]
+name = "HighErrorRate"
+expr = "http_errors_total_rate_per_sec > 5"
+for = "30s"
+keep_firing_for = "1m"
+labels = { severity = "page" }
+
+[[rules]]
+name = "SlowRequests"
+expr = "latency_sum > 100"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].for_duration, Duration::from_secs(30));
+        assert_eq!(rules[0].keep_firing_for, Duration::from_secs(60));
+        assert_eq!(rules[0].labels["severity"], "page");
+        assert_eq!(rules[1].for_duration, Duration::ZERO);
+    }
+
+    #[test]
+    fn test_alert_becomes_firing_after_for_duration() {
+        let engine = AlertEngine::new(&[rule(
+            r#"http_errors_total_rate_per_sec{endpoint!="/health"} > 5"#,
+            Duration::from_secs(30),
+        )])
+        .unwrap();
+        let snapshot = Snapshot::parse(EXPOSITION);
+        let start = Utc::now();
+
+        engine.evaluate(&snapshot, start);
+        let alerts = engine.alerts();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].state, AlertState::Pending);
+        assert_eq!(alerts[0].labels["endpoint"], "/api");
+        assert_eq!(alerts[0].summary.as_deref(), Some("7.5 errors/s on /api"));
+
+        engine.evaluate(&snapshot, start + chrono::Duration::seconds(30));
+        let alerts = engine.alerts();
+        assert_eq!(alerts[0].state, AlertState::Firing);
+        assert_eq!(alerts[0].active_since, start);
+
+        engine.evaluate(&Snapshot::default(), start + chrono::Duration::seconds(35));
+        assert!(engine.alerts().is_empty());
+    }
+
+    #[test]
+    fn test_firing_alert_survives_missing_series_for_keep_firing_for() {
+        let engine = AlertEngine::new(&[AlertRule {
+            keep_firing_for: Duration::from_secs(60),
+            ..rule(
+                r#"http_errors_total_rate_per_sec{endpoint="/api"} > 5"#,
+                Duration::from_secs(30),
+            )
+        }])
+        .unwrap();
+        let snapshot = Snapshot::parse(EXPOSITION);
+        let start = Utc::now();
+        let at = |seconds| start + chrono::Duration::seconds(seconds);
+
+        engine.evaluate(&snapshot, start);
+        engine.evaluate(&snapshot, at(30));
+        assert_eq!(engine.alerts()[0].state, AlertState::Firing);
+
+        // The series is missing from one snapshot, then comes back
+        engine.evaluate(&Snapshot::default(), at(35));
+        let alerts = engine.alerts();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].state, AlertState::Firing);
+        assert_eq!(alerts[0].value, 7.5);
+        engine.evaluate(&snapshot, at(40));
+        let alerts = engine.alerts();
+        assert_eq!(alerts[0].state, AlertState::Firing);
+        assert_eq!(alerts[0].active_since, start);
+
+        // Gone once the condition hasn't held for `keep_firing_for`
+        engine.evaluate(&Snapshot::default(), at(99));
+        assert_eq!(engine.alerts().len(), 1);
+        engine.evaluate(&Snapshot::default(), at(100));
+        assert!(engine.alerts().is_empty());
+    }
+
+    #[test]
+    fn test_pending_alert_is_not_kept() {
+        let engine = AlertEngine::new(&[AlertRule {
+            keep_firing_for: Duration::from_secs(60),
+            ..rule("latency_count > 3", Duration::from_secs(30))
+        }])
+        .unwrap();
+        let start = Utc::now();
+
+        engine.evaluate(&Snapshot::parse(EXPOSITION), start);
+        assert_eq!(engine.alerts()[0].state, AlertState::Pending);
+        engine.evaluate(&Snapshot::default(), start + chrono::Duration::seconds(5));
+        assert!(engine.alerts().is_empty());
+    }
+
+    #[test]
+    fn test_histogram_samples() {
+        let engine = AlertEngine::new(&[rule("latency_count > 3", Duration::ZERO)]).unwrap();
+
+        engine.evaluate(&Snapshot::parse(EXPOSITION), Utc::now());
+
+        let alerts = engine.alerts();
+        assert_eq!(alerts[0].state, AlertState::Firing);
+        assert_eq!(alerts[0].metric, "latency");
+    }
+
+    #[test]
+    fn test_invalid_rule_is_rejected() {
+        let error = AlertEngine::new(&[rule("> 5", Duration::ZERO)])
+            .err()
+            .unwrap();
+        assert!(format!("{:#}", error).contains("HighErrorRate"));
+
+        let duplicate = AlertEngine::new(&[
+            rule("latency_count > 3", Duration::ZERO),
+            rule("latency_count > 5", Duration::ZERO),
+        ]);
+        assert!(duplicate.is_err());
+    }
+}
@@ -0,0 +1,482 @@
+//! # Alert Notifications
+//!
+//! Sends firing and resolved alerts to webhooks, Slack or a callback.
+//! Alerts are grouped by rule name and the configured `group_by` labels,
+//! and each group is notified as a whole:
+//! - when the group starts firing or its set of firing alerts changes
+//! - again every `repeat_interval` while it keeps firing
+//! - once more when its last alert resolves, if `send_resolved` is set
+
+use super::{Alert, AlertState};
+use crate::exporters::http_client;
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::Serialize;
+use serde_json::json;
+use std::{collections::BTreeMap, fmt, sync::Arc, time::Duration};
+
+/// Destination of alert notifications
+#[derive(Clone)]
+pub enum AlertSink {
+    /// POSTs every [`AlertNotification`] as JSON
+    Webhook {
+        url: String,
+        /// Extra HTTP headers, e.g. for authentication
+        headers: Vec<(String, String)>,
+    },
+    /// POSTs a message to a Slack incoming webhook
+    Slack {
+        webhook_url: String,
+        /// Overrides the channel configured for the webhook
+        channel: Option<String>,
+    },
+    /// Calls a function with every notification
+    Callback(Arc<dyn Fn(&AlertNotification) + Send + Sync>),
+}
+
+impl AlertSink {
+    /// Creates a [`AlertSink::Callback`] from a closure
+    pub fn callback(callback: impl Fn(&AlertNotification) + Send + Sync + 'static) -> Self {
+        Self::Callback(Arc::new(callback))
+    }
+
+    fn describe(&self) -> &str {
+        match self {
+            AlertSink::Webhook { url, .. } => url,
+            AlertSink::Slack { .. } => "Slack",
+            AlertSink::Callback(_) => "callback",
+        }
+    }
+}
+
+impl fmt::Debug for AlertSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlertSink::Webhook { url, headers } => f
+                .debug_struct("Webhook")
+                .field("url", url)
+                .field("headers", &headers.len())
+                .finish(),
+            AlertSink::Slack { channel, .. } => {
+                f.debug_struct("Slack").field("channel", channel).finish()
+            }
+            AlertSink::Callback(_) => f.write_str("Callback"),
+        }
+    }
+}
+
+/// Configuration of alert notifications
+///
+/// # Example
+///
+/// ```
+/// use metrics_rs_dashboard_actix::{AlertNotifierConfig, AlertSink, DashboardInput};
+///
+/// let dashboard_input = DashboardInput {
+///     alert_notifications: Some(AlertNotifierConfig {
+///         sinks: vec![
+///             AlertSink::Slack {
+///                 webhook_url: "https://hooks.slack.com/services/T000/B000/XXXX".to_string(),
+///                 channel: None,
+///             },
+///             AlertSink::callback(|notification| {
+///                 eprintln!("{} alert(s) {:?}", notification.alerts.len(), notification.status);
+///             }),
+///         ],
+///         group_by: vec!["endpoint".to_string()],
+///         ..Default::default()
+///     }),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone)]
+pub struct AlertNotifierConfig {
+    /// Every notification is sent to all sinks
+    pub sinks: Vec<AlertSink>,
+    /// Labels, in addition to the rule name, whose values form a group
+    pub group_by: Vec<String>,
+    /// Interval after which a group that is still firing is notified again
+    pub repeat_interval: Duration,
+    /// Whether to notify when a group stops firing
+    pub send_resolved: bool,
+}
+
+impl Default for AlertNotifierConfig {
+    fn default() -> Self {
+        Self {
+            sinks: Vec::new(),
+            group_by: Vec::new(),
+            repeat_interval: Duration::from_secs(4 * 3600),
+            send_resolved: true,
+        }
+    }
+}
+
+/// Status of a notified group
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationStatus {
+    /// At least one alert of the group is firing
+    Firing,
+    /// Every alert of the group has resolved
+    Resolved,
+}
+
+/// A notification about one group of alerts, sent as JSON to webhooks
+#[derive(Debug, Clone, Serialize)]
+pub struct AlertNotification {
+    pub status: NotificationStatus,
+    /// `alertname` and the `group_by` labels shared by the alerts
+    pub group_labels: BTreeMap<String, String>,
+    /// Firing alerts of the group, followed by alerts resolved since the
+    /// previous notification if `send_resolved` is set
+    pub alerts: Vec<Alert>,
+}
+
+/// Firing alerts of a group at the time of its last notification
+struct GroupState {
+    last_sent: DateTime<Utc>,
+    alerts: BTreeMap<String, Alert>,
+}
+
+/// Decides which groups to notify and sends the notifications
+pub(super) struct Notifier {
+    config: AlertNotifierConfig,
+    groups: BTreeMap<BTreeMap<String, String>, GroupState>,
+}
+
+impl Notifier {
+    pub fn new(config: AlertNotifierConfig) -> Self {
+        Self {
+            config,
+            groups: BTreeMap::new(),
+        }
+    }
+
+    /// Compares the active alerts with the notified groups and returns the
+    /// notifications that are due at `now`
+    pub fn update(&mut self, alerts: &[Alert], now: DateTime<Utc>) -> Vec<AlertNotification> {
+        let mut firing: BTreeMap<BTreeMap<String, String>, BTreeMap<String, Alert>> =
+            BTreeMap::new();
+        for alert in alerts.iter().filter(|a| a.state == AlertState::Firing) {
+            firing
+                .entry(self.group_labels(alert))
+                .or_default()
+                .insert(alert_key(alert), alert.clone());
+        }
+
+        let mut group_keys: Vec<_> = firing.keys().cloned().collect();
+        group_keys.extend(
+            self.groups
+                .keys()
+                .filter(|key| !firing.contains_key(*key))
+                .cloned(),
+        );
+
+        let mut notifications = Vec::new();
+        for group_labels in group_keys {
+            let current = firing.remove(&group_labels).unwrap_or_default();
+            let previous = self.groups.remove(&group_labels);
+            let resolved: Vec<Alert> = previous
+                .iter()
+                .flat_map(|group| &group.alerts)
+                .filter(|(key, _)| !current.contains_key(*key))
+                .map(|(_, alert)| Alert {
+                    state: AlertState::Resolved,
+                    resolved_at: Some(now),
+                    ..alert.clone()
+                })
+                .collect();
+            let resolved = if self.config.send_resolved {
+                resolved
+            } else {
+                Vec::new()
+            };
+
+            if current.is_empty() {
+                if !resolved.is_empty() {
+                    notifications.push(AlertNotification {
+                        status: NotificationStatus::Resolved,
+                        group_labels,
+                        alerts: resolved,
+                    });
+                }
+                continue;
+            }
+
+            let changed = previous
+                .as_ref()
+                .is_none_or(|group| !group.alerts.keys().eq(current.keys()));
+            let repeat_due = previous.as_ref().is_none_or(|group| {
+                (now - group.last_sent).to_std().unwrap_or_default() >= self.config.repeat_interval
+            });
+            let last_sent = if changed || repeat_due {
+                notifications.push(AlertNotification {
+                    status: NotificationStatus::Firing,
+                    group_labels: group_labels.clone(),
+                    alerts: current.values().cloned().chain(resolved).collect(),
+                });
+                now
+            } else {
+                previous.map_or(now, |group| group.last_sent)
+            };
+
+            self.groups.insert(
+                group_labels,
+                GroupState {
+                    last_sent,
+                    alerts: current,
+                },
+            );
+        }
+        notifications
+    }
+
+    /// Sends a notification to every sink, logging failures
+    pub async fn send(&self, notification: &AlertNotification) {
+        for sink in &self.config.sinks {
+            if let Err(e) = deliver(sink, notification).await {
+                warn!("Alert notification to {} failed: {:#}", sink.describe(), e);
+            }
+        }
+    }
+
+    fn group_labels(&self, alert: &Alert) -> BTreeMap<String, String> {
+        let mut labels = BTreeMap::from([("alertname".to_string(), alert.name.clone())]);
+        for name in &self.config.group_by {
+            if let Some(value) = alert.labels.get(name) {
+                labels.insert(name.clone(), value.clone());
+            }
+        }
+        labels
+    }
+}
+
+/// Identifies an alert within its group
+fn alert_key(alert: &Alert) -> String {
+    let labels: Vec<String> = alert
+        .labels
+        .iter()
+        .map(|(k, v)| format!("{}={:?}", k, v))
+        .collect();
+    format!("{}{{{}}}", alert.name, labels.join(","))
+}
+
+async fn deliver(sink: &AlertSink, notification: &AlertNotification) -> Result<()> {
+    let request = match sink {
+        AlertSink::Callback(callback) => {
+            callback(notification);
+            return Ok(());
+        }
+        AlertSink::Webhook { url, headers } => {
+            let mut request = http_client().post(url).json(notification);
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+            request
+        }
+        AlertSink::Slack {
+            webhook_url,
+            channel,
+        } => {
+            let mut payload = json!({ "text": slack_text(notification) });
+            if let Some(channel) = channel {
+                payload["channel"] = json!(channel);
+            }
+            http_client().post(webhook_url).json(&payload)
+        }
+    };
+
+    let response = request.send().await?;
+    if !response.status().is_success() {
+        return Err(anyhow!("endpoint responded with {}", response.status()));
+    }
+    Ok(())
+}
+
+/// Formats a notification as a Slack message
+fn slack_text(notification: &AlertNotification) -> String {
+    let firing = notification
+        .alerts
+        .iter()
+        .filter(|alert| alert.state == AlertState::Firing)
+        .count();
+    let status = match notification.status {
+        NotificationStatus::Firing => format!("FIRING:{}", firing),
+        NotificationStatus::Resolved => "RESOLVED".to_string(),
+    };
+    let groups: Vec<String> = notification
+        .group_labels
+        .iter()
+        .filter(|(name, _)| name.as_str() != "alertname")
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    let alertname = notification
+        .group_labels
+        .get("alertname")
+        .map_or("", String::as_str);
+
+    let mut text = format!("*[{}] {}* {}", status, alertname, groups.join(" "))
+        .trim_end()
+        .to_string();
+    for alert in &notification.alerts {
+        let description = alert
+            .summary
+            .clone()
+            .unwrap_or_else(|| format!("{} = {}", alert.metric, alert.value));
+        let marker = if alert.state == AlertState::Resolved {
+            " (resolved)"
+        } else {
+            ""
+        };
+        text.push_str(&format!("\n• {}{}", description, marker));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporters::test_support::MockCollector;
+    use std::sync::Mutex;
+
+    fn alert(endpoint: &str, start: DateTime<Utc>) -> Alert {
+        Alert {
+            name: "HighErrorRate".to_string(),
+            metric: "errors_rate_per_sec".to_string(),
+            labels: BTreeMap::from([("endpoint".to_string(), endpoint.to_string())]),
+            state: AlertState::Firing,
+            value: 7.0,
+            active_since: start,
+            resolved_at: None,
+            summary: Some(format!("7 errors/s on {}", endpoint)),
+        }
+    }
+
+    fn notifier(group_by: &[&str]) -> Notifier {
+        Notifier::new(AlertNotifierConfig {
+            group_by: group_by.iter().map(|label| label.to_string()).collect(),
+            repeat_interval: Duration::from_secs(60),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_repeat_and_resolve() {
+        let mut notifier = notifier(&[]);
+        let start = Utc::now();
+        let a = alert("/a", start);
+
+        let sent = notifier.update(std::slice::from_ref(&a), start);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].status, NotificationStatus::Firing);
+        assert_eq!(sent[0].group_labels["alertname"], "HighErrorRate");
+
+        let later = start + chrono::Duration::seconds(30);
+        assert!(notifier.update(std::slice::from_ref(&a), later).is_empty());
+
+        let repeat = start + chrono::Duration::seconds(60);
+        assert_eq!(notifier.update(std::slice::from_ref(&a), repeat).len(), 1);
+
+        let resolved_at = start + chrono::Duration::seconds(70);
+        let sent = notifier.update(&[], resolved_at);
+        assert_eq!(sent[0].status, NotificationStatus::Resolved);
+        assert_eq!(sent[0].alerts[0].state, AlertState::Resolved);
+        assert_eq!(sent[0].alerts[0].resolved_at, Some(resolved_at));
+
+        assert!(notifier.update(&[], resolved_at).is_empty());
+    }
+
+    #[test]
+    fn test_grouping() {
+        let start = Utc::now();
+        let alerts = [alert("/a", start), alert("/b", start)];
+
+        let sent = notifier(&[]).update(&alerts, start);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].alerts.len(), 2);
+
+        let mut by_endpoint = notifier(&["endpoint"]);
+        let sent = by_endpoint.update(&alerts, start);
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[1].group_labels["endpoint"], "/b");
+
+        // A new alert joining a group is notified right away
+        let mut grouped = notifier(&[]);
+        grouped.update(&alerts[..1], start);
+        let sent = grouped.update(&alerts, start + chrono::Duration::seconds(5));
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].alerts.len(), 2);
+    }
+
+    #[test]
+    fn test_pending_alerts_are_not_notified() {
+        let start = Utc::now();
+        let pending = Alert {
+            state: AlertState::Pending,
+            ..alert("/a", start)
+        };
+
+        assert!(notifier(&[]).update(&[pending], start).is_empty());
+    }
+
+    #[test]
+    fn test_slack_text() {
+        let start = Utc::now();
+        let notification = AlertNotification {
+            status: NotificationStatus::Firing,
+            group_labels: BTreeMap::from([
+                ("alertname".to_string(), "HighErrorRate".to_string()),
+                ("endpoint".to_string(), "/a".to_string()),
+            ]),
+            alerts: vec![alert("/a", start)],
+        };
+
+        assert_eq!(
+            slack_text(&notification),
+            "*[FIRING:1] HighErrorRate* endpoint=/a\n• 7 errors/s on /a"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sinks() {
+        let webhook = MockCollector::start().await;
+        let slack = MockCollector::start().await;
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let callback_received = received.clone();
+        let mut notifier = Notifier::new(AlertNotifierConfig {
+            sinks: vec![
+                AlertSink::Webhook {
+                    url: webhook.url("/hook"),
+                    headers: vec![("x-token".to_string(), "secret".to_string())],
+                },
+                AlertSink::Slack {
+                    webhook_url: slack.url("/services/T000"),
+                    channel: Some("#alerts".to_string()),
+                },
+                AlertSink::callback(move |notification| {
+                    callback_received.lock().unwrap().push(notification.status);
+                }),
+            ],
+            ..Default::default()
+        });
+
+        let start = Utc::now();
+        for notification in notifier.update(&[alert("/a", start)], start) {
+            notifier.send(&notification).await;
+        }
+
+        let hook = &webhook.requests()[0];
+        assert_eq!(hook.headers["x-token"], "secret");
+        let body: serde_json::Value = serde_json::from_slice(&hook.body).unwrap();
+        assert_eq!(body["status"], "firing");
+        assert_eq!(body["alerts"][0]["labels"]["endpoint"], "/a");
+
+        let body: serde_json::Value = serde_json::from_slice(&slack.requests()[0].body).unwrap();
+        assert_eq!(body["channel"], "#alerts");
+        assert!(body["text"].as_str().unwrap().starts_with("*[FIRING:1]"));
+
+        assert_eq!(*received.lock().unwrap(), vec![NotificationStatus::Firing]);
+    }
+}
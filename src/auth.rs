@@ -0,0 +1,383 @@
+//! # Endpoint Authentication
+//!
+//! Optional access control for the metrics scope. The Prometheus scrape
+//! endpoint and the dashboard UI (including its stream and WebSocket
+//! endpoints) are protected separately, each with any combination of bearer
+//! tokens, HTTP basic credentials and a client IP allow-list.
+
+use actix_web::{
+    Error, HttpResponse,
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{AUTHORIZATION, WWW_AUTHENTICATE},
+    middleware::Next,
+    web,
+};
+use anyhow::Result;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+/// Paths, relative to the metrics scope, that are scraped by machines
+///
+/// Every other path belongs to the dashboard UI.
+const SCRAPE_PATHS: &[&str] = &["/prometheus", "/influx"];
+
+/// Access control settings for a group of metrics endpoints
+///
+/// All configured checks must pass:
+/// - If `allowed_networks` is not empty, the client address must be inside one of them
+/// - If any bearer token or basic credential is configured, the request must present one of them
+///
+/// An `AuthConfig` with nothing configured allows every request.
+///
+/// # Example
+///
+/// ```
+/// use metrics_rs_dashboard_actix::AuthConfig;
+///
+/// let scrape_auth = AuthConfig {
+///     bearer_tokens: vec!["scrape-secret".to_string()],
+///     allowed_networks: vec!["10.0.0.0/8".to_string(), "127.0.0.1".to_string()],
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct AuthConfig {
+    /// Tokens accepted in an `Authorization: Bearer <token>` header
+    pub bearer_tokens: Vec<String>,
+
+    /// Username and password pairs accepted with HTTP basic authentication
+    pub basic_credentials: Vec<(String, String)>,
+
+    /// Client networks in CIDR notation (e.g. `10.0.0.0/8`) or single IP addresses.
+    ///
+    /// The address of the TCP peer is checked, so when running behind a reverse
+    /// proxy this is the address of the proxy.
+    pub allowed_networks: Vec<String>,
+}
+
+/// Parsed form of an [`AuthConfig`]
+#[derive(Debug, Clone, Default)]
+struct EndpointAccess {
+    bearer_tokens: Vec<String>,
+    basic_credentials: Vec<(String, String)>,
+    allowed_networks: Vec<IpNet>,
+}
+
+/// Outcome of an access check
+#[derive(Debug, PartialEq, Eq)]
+enum AccessDecision {
+    Allow,
+    /// The client address is not allowed
+    Forbidden,
+    /// Credentials are missing or invalid; carries the `WWW-Authenticate` challenge
+    Unauthorized(&'static str),
+}
+
+/// Access control for the whole metrics scope
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AccessControl {
+    scrape: EndpointAccess,
+    dashboard: EndpointAccess,
+}
+
+impl AccessControl {
+    /// Builds the access control from the scrape and dashboard configurations
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an allowed network is neither a CIDR range nor an IP address
+    pub fn new(scrape: Option<&AuthConfig>, dashboard: Option<&AuthConfig>) -> Result<Self> {
+        Ok(Self {
+            scrape: scrape
+                .map(EndpointAccess::new)
+                .transpose()?
+                .unwrap_or_default(),
+            dashboard: dashboard
+                .map(EndpointAccess::new)
+                .transpose()?
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Returns the settings that apply to a path relative to the metrics scope
+    fn for_path(&self, path: &str) -> &EndpointAccess {
+        if SCRAPE_PATHS.contains(&path) {
+            &self.scrape
+        } else {
+            &self.dashboard
+        }
+    }
+}
+
+impl EndpointAccess {
+    fn new(config: &AuthConfig) -> Result<Self> {
+        let allowed_networks = config
+            .allowed_networks
+            .iter()
+            .map(|network| parse_network(network))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            bearer_tokens: config.bearer_tokens.clone(),
+            basic_credentials: config.basic_credentials.clone(),
+            allowed_networks,
+        })
+    }
+
+    /// Decides whether a request with the given peer address and
+    /// `Authorization` header may proceed
+    fn check(&self, peer: Option<IpAddr>, authorization: Option<&str>) -> AccessDecision {
+        if !self.allowed_networks.is_empty() {
+            let allowed = peer.is_some_and(|ip| {
+                let ip = ip.to_canonical();
+                self.allowed_networks.iter().any(|net| net.contains(&ip))
+            });
+            if !allowed {
+                return AccessDecision::Forbidden;
+            }
+        }
+
+        if self.bearer_tokens.is_empty() && self.basic_credentials.is_empty() {
+            return AccessDecision::Allow;
+        }
+
+        let authorized = authorization.is_some_and(|header| {
+            if let Some(token) = strip_scheme(header, "Bearer") {
+                self.bearer_tokens
+                    .iter()
+                    .any(|expected| constant_time_eq(expected.as_bytes(), token.as_bytes()))
+            } else if let Some(encoded) = strip_scheme(header, "Basic") {
+                decode_basic(encoded).is_some_and(|(user, password)| {
+                    self.basic_credentials.iter().any(|(u, p)| {
+                        constant_time_eq(u.as_bytes(), user.as_bytes())
+                            & constant_time_eq(p.as_bytes(), password.as_bytes())
+                    })
+                })
+            } else {
+                false
+            }
+        });
+
+        if authorized {
+            AccessDecision::Allow
+        } else if !self.basic_credentials.is_empty() {
+            // Lets browsers prompt for credentials when opening the dashboard
+            AccessDecision::Unauthorized("Basic realm=\"metrics\"")
+        } else {
+            AccessDecision::Unauthorized("Bearer realm=\"metrics\"")
+        }
+    }
+}
+
+/// Parses a CIDR range or a single IP address
+fn parse_network(network: &str) -> Result<IpNet> {
+    network
+        .parse::<IpNet>()
+        .or_else(|_| network.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|e| anyhow::anyhow!("Invalid allowed network '{}': {}", network, e))
+}
+
+/// Returns the credentials of an `Authorization` header using the given scheme
+fn strip_scheme<'a>(header: &'a str, scheme: &str) -> Option<&'a str> {
+    let (header_scheme, credentials) = header.split_once(' ')?;
+    header_scheme
+        .eq_ignore_ascii_case(scheme)
+        .then(|| credentials.trim())
+}
+
+/// Decodes base64 `user:password` basic credentials
+fn decode_basic(encoded: &str) -> Option<(String, String)> {
+    let decoded = String::from_utf8(STANDARD.decode(encoded).ok()?).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+/// Compares two byte slices without short-circuiting on the first difference
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Middleware enforcing the [`AccessControl`] registered on the metrics scope
+///
+/// Requests are rejected with `403 Forbidden` when the client address is not
+/// allowed and with `401 Unauthorized` when credentials are missing or wrong.
+pub(crate) async fn check_access(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let decision = match req.app_data::<web::Data<AccessControl>>() {
+        Some(access) => access.for_path(req.match_info().unprocessed()).check(
+            req.peer_addr().map(|addr| addr.ip()),
+            req.headers()
+                .get(AUTHORIZATION)
+                .and_then(|value| value.to_str().ok()),
+        ),
+        None => AccessDecision::Allow,
+    };
+
+    match decision {
+        AccessDecision::Allow => Ok(next.call(req).await?.map_into_left_body()),
+        AccessDecision::Forbidden => Ok(req.into_response(
+            HttpResponse::Forbidden()
+                .body("403 Forbidden")
+                .map_into_right_body(),
+        )),
+        AccessDecision::Unauthorized(challenge) => Ok(req.into_response(
+            HttpResponse::Unauthorized()
+                .insert_header((WWW_AUTHENTICATE, challenge))
+                .body("401 Unauthorized")
+                .map_into_right_body(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn basic_header(user: &str, password: &str) -> String {
+        format!(
+            "Basic {}",
+            STANDARD.encode(format!("{}:{}", user, password))
+        )
+    }
+
+    #[test]
+    fn test_empty_config_allows_everything() {
+        let access = EndpointAccess::new(&AuthConfig::default()).unwrap();
+        assert_eq!(access.check(None, None), AccessDecision::Allow);
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        let access = EndpointAccess::new(&AuthConfig {
+            bearer_tokens: vec!["secret".to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            access.check(None, Some("Bearer secret")),
+            AccessDecision::Allow
+        );
+        assert_eq!(
+            access.check(None, Some("Bearer wrong")),
+            AccessDecision::Unauthorized("Bearer realm=\"metrics\"")
+        );
+        assert!(matches!(
+            access.check(None, None),
+            AccessDecision::Unauthorized(_)
+        ));
+    }
+
+    #[test]
+    fn test_basic_credentials() {
+        let access = EndpointAccess::new(&AuthConfig {
+            basic_credentials: vec![("admin".to_string(), "p:ss".to_string())],
+            ..Default::default()
+        })
+        .unwrap();
+
+        let valid = basic_header("admin", "p:ss");
+        let invalid = basic_header("admin", "nope");
+        assert_eq!(access.check(None, Some(&valid)), AccessDecision::Allow);
+        assert_eq!(
+            access.check(None, Some(&invalid)),
+            AccessDecision::Unauthorized("Basic realm=\"metrics\"")
+        );
+    }
+
+    #[test]
+    fn test_allowed_networks() {
+        let access = EndpointAccess::new(&AuthConfig {
+            allowed_networks: vec!["10.0.0.0/8".to_string(), "::1".to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            access.check(Some("10.1.2.3".parse().unwrap()), None),
+            AccessDecision::Allow
+        );
+        assert_eq!(
+            access.check(Some("::1".parse().unwrap()), None),
+            AccessDecision::Allow
+        );
+        assert_eq!(
+            access.check(Some("192.168.0.1".parse().unwrap()), None),
+            AccessDecision::Forbidden
+        );
+        assert_eq!(access.check(None, None), AccessDecision::Forbidden);
+    }
+
+    #[test]
+    fn test_invalid_network_is_rejected() {
+        let result = EndpointAccess::new(&AuthConfig {
+            allowed_networks: vec!["not-a-network".to_string()],
+            ..Default::default()
+        });
+        assert!(result.is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_scrape_and_dashboard_are_protected_separately() {
+        use actix_web::{App, HttpResponse, http::StatusCode, test, web};
+
+        let access = AccessControl::new(
+            Some(&AuthConfig {
+                bearer_tokens: vec!["scrape".to_string()],
+                ..Default::default()
+            }),
+            Some(&AuthConfig {
+                basic_credentials: vec![("admin".to_string(), "admin".to_string())],
+                ..Default::default()
+            }),
+        )
+        .unwrap();
+
+        let app = test::init_service(
+            App::new().service(
+                web::scope("/metrics")
+                    .app_data(web::Data::new(access))
+                    .service(
+                        web::scope("")
+                            .wrap(actix_web::middleware::from_fn(check_access))
+                            .route("/prometheus", web::get().to(HttpResponse::Ok))
+                            .route("/dashboard", web::get().to(HttpResponse::Ok)),
+                    ),
+            ),
+        )
+        .await;
+
+        let scrape = test::TestRequest::get()
+            .uri("/metrics/prometheus")
+            .insert_header((AUTHORIZATION, "Bearer scrape"))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, scrape).await.status(),
+            StatusCode::OK
+        );
+
+        let dashboard_with_token = test::TestRequest::get()
+            .uri("/metrics/dashboard")
+            .insert_header((AUTHORIZATION, "Bearer scrape"))
+            .to_request();
+        let response = test::call_service(&app, dashboard_with_token).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(response.headers().contains_key(WWW_AUTHENTICATE));
+
+        let dashboard = test::TestRequest::get()
+            .uri("/metrics/dashboard")
+            .insert_header((AUTHORIZATION, basic_header("admin", "admin")))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, dashboard).await.status(),
+            StatusCode::OK
+        );
+    }
+}
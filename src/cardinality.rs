@@ -0,0 +1,559 @@
+//! # Cardinality
+//!
+//! Counts the distinct label combinations, i.e. series, of every metric
+//! name as they are registered. The counts are published as the
+//! `metrics_dashboard_series{metric}` gauge and served at
+//! `/metrics/api/cardinality`, largest first.
+//!
+//! Optionally, the number of series per metric name is capped. New label
+//! combinations of a metric at its limit are refused: they are recorded by
+//! none of the recorders, counted in
+//! `metrics_dashboard_series_refused_total{metric}` and reported once with
+//! a warning. Series registered before the limit was reached keep working.
+//! The series metrics themselves are never limited.
+//!
+//! With `DashboardInput::series_idle_timeout`, series not updated within the
+//! timeout are forgotten, in step with the Prometheus recorder removing
+//! them, which makes room for new label combinations under the limits.
+
+use crate::query::ApiResponse;
+use log::warn;
+use metrics::{
+    Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder,
+    SharedString, Unit,
+};
+use metrics_exporter_prometheus::Matcher;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, OnceLock, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tokio::time::MissedTickBehavior;
+
+/// Gauge with the number of series of every metric name
+pub const SERIES_METRIC: &str = "metrics_dashboard_series";
+
+/// Counter of the label combinations refused because of a limit
+pub const REFUSED_SERIES_METRIC: &str = "metrics_dashboard_series_refused_total";
+
+/// Interval between two updates of the published gauges
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// State of the recorder installed in front of the fanout
+static STATE: OnceLock<Arc<CardinalityState>> = OnceLock::new();
+
+/// Series seen for one metric name
+#[derive(Debug, Default)]
+struct NameSeries {
+    /// Hashes of the registered keys with the time they were last used,
+    /// in seconds of [`CardinalityState::clock`]
+    keys: HashMap<u64, Arc<AtomicU64>>,
+    refused: u64,
+}
+
+#[derive(Debug)]
+struct CardinalityState {
+    default_limit: Option<usize>,
+    limits: Vec<(Matcher, usize)>,
+    /// Series unused for this long are forgotten; never when `None`
+    idle_timeout: Option<Duration>,
+    /// Coarse clock in seconds since `started`, advanced by the publishing
+    /// task so that updates only read an atomic
+    clock: Arc<AtomicU64>,
+    started: Instant,
+    names: RwLock<HashMap<String, NameSeries>>,
+}
+
+/// Outcome of [`CardinalityState::admit`]
+enum Admission {
+    Refused,
+    /// The key may be recorded; its handles must report their use if series
+    /// are forgotten when idle
+    Admitted(Option<LastUse>),
+}
+
+impl CardinalityState {
+    fn new(
+        default_limit: Option<usize>,
+        limits: Vec<(Matcher, usize)>,
+        idle_timeout: Option<Duration>,
+    ) -> Self {
+        Self {
+            default_limit,
+            limits,
+            idle_timeout,
+            clock: Arc::new(AtomicU64::new(0)),
+            started: Instant::now(),
+            names: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn limit(&self, name: &str) -> Option<usize> {
+        // Limiting the series metrics would hide the very metrics at their limit
+        if name == SERIES_METRIC || name == REFUSED_SERIES_METRIC {
+            return None;
+        }
+        self.limits
+            .iter()
+            .find(|(matcher, _)| matcher.matches(name))
+            .map(|(_, limit)| *limit)
+            .or(self.default_limit)
+    }
+
+    /// Returns the handle updates report to, if series are forgotten when idle
+    fn last_use(&self, seen: &Arc<AtomicU64>) -> Option<LastUse> {
+        seen.store(self.clock.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.idle_timeout.map(|_| LastUse {
+            seen: seen.clone(),
+            clock: self.clock.clone(),
+        })
+    }
+
+    /// Tracks the key and returns whether it may be recorded
+    ///
+    /// Metrics are registered on every use, so known keys only take a read lock.
+    fn admit(&self, key: &Key) -> Admission {
+        let hash = key.get_hash();
+        let known = self.names.read().map(|names| {
+            names
+                .get(key.name())
+                .and_then(|series| series.keys.get(&hash))
+                .map(|seen| self.last_use(seen))
+        });
+        match known {
+            Ok(Some(last_use)) => return Admission::Admitted(last_use),
+            Err(_) => return Admission::Admitted(None),
+            Ok(None) => {}
+        }
+
+        // The warning is logged after the lock is released: a logger recording
+        // metrics, like `MetricsLogger`, registers through this recorder again
+        let (admitted, first_refusal) = {
+            let Ok(mut names) = self.names.write() else {
+                return Admission::Admitted(None);
+            };
+            let series = names.entry(key.name().to_owned()).or_default();
+            if let Some(seen) = series.keys.get(&hash) {
+                return Admission::Admitted(self.last_use(seen));
+            }
+            match self.limit(key.name()) {
+                Some(limit) if series.keys.len() >= limit => {
+                    series.refused += 1;
+                    (Admission::Refused, (series.refused == 1).then_some(limit))
+                }
+                _ => {
+                    let seen = Arc::new(AtomicU64::new(0));
+                    let admitted = Admission::Admitted(self.last_use(&seen));
+                    series.keys.insert(hash, seen);
+                    (admitted, None)
+                }
+            }
+        };
+        if let Some(limit) = first_refusal {
+            warn!(
+                "Metric '{}' reached its limit of {} series, new label combinations are dropped",
+                key.name(),
+                limit
+            );
+        }
+        admitted
+    }
+
+    /// Advances the clock and forgets the series unused for the idle timeout
+    fn forget_idle(&self) {
+        self.forget_idle_at(self.started.elapsed().as_secs());
+    }
+
+    fn forget_idle_at(&self, now: u64) {
+        self.clock.store(now, Ordering::Relaxed);
+        let Some(idle_timeout) = self.idle_timeout else {
+            return;
+        };
+        if let Ok(mut names) = self.names.write() {
+            names.retain(|_, series| {
+                series.keys.retain(|_, seen| {
+                    now.saturating_sub(seen.load(Ordering::Relaxed)) < idle_timeout.as_secs()
+                });
+                !series.keys.is_empty() || series.refused > 0
+            });
+        }
+    }
+
+    /// Series counts of all metric names, largest first
+    fn report(&self) -> CardinalityReport {
+        let mut metrics: Vec<MetricCardinality> = self
+            .names
+            .read()
+            .map(|names| {
+                names
+                    .iter()
+                    .map(|(name, series)| MetricCardinality {
+                        name: name.clone(),
+                        series: series.keys.len(),
+                        limit: self.limit(name),
+                        refused: series.refused,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        metrics.sort_by(|a, b| b.series.cmp(&a.series).then_with(|| a.name.cmp(&b.name)));
+        CardinalityReport {
+            total_series: metrics.iter().map(|metric| metric.series).sum(),
+            metrics,
+        }
+    }
+}
+
+/// Series count of one metric name
+#[derive(Debug, Serialize)]
+struct MetricCardinality {
+    name: String,
+    series: usize,
+    limit: Option<usize>,
+    /// Label combinations refused because of the limit
+    refused: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct CardinalityReport {
+    total_series: usize,
+    metrics: Vec<MetricCardinality>,
+}
+
+/// Records the time a series was last used, in seconds of the state's clock
+#[derive(Debug)]
+struct LastUse {
+    seen: Arc<AtomicU64>,
+    clock: Arc<AtomicU64>,
+}
+
+impl LastUse {
+    fn touch(&self) {
+        self.seen
+            .store(self.clock.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+}
+
+/// Handle reporting every update to the series' [`LastUse`]
+struct Tracked<H> {
+    inner: H,
+    last_use: LastUse,
+}
+
+impl CounterFn for Tracked<Counter> {
+    fn increment(&self, value: u64) {
+        self.last_use.touch();
+        self.inner.increment(value);
+    }
+
+    fn absolute(&self, value: u64) {
+        self.last_use.touch();
+        self.inner.absolute(value);
+    }
+}
+
+impl GaugeFn for Tracked<Gauge> {
+    fn increment(&self, value: f64) {
+        self.last_use.touch();
+        self.inner.increment(value);
+    }
+
+    fn decrement(&self, value: f64) {
+        self.last_use.touch();
+        self.inner.decrement(value);
+    }
+
+    fn set(&self, value: f64) {
+        self.last_use.touch();
+        self.inner.set(value);
+    }
+}
+
+impl HistogramFn for Tracked<Histogram> {
+    fn record(&self, value: f64) {
+        self.last_use.touch();
+        self.inner.record(value);
+    }
+
+    fn record_many(&self, value: f64, count: usize) {
+        self.last_use.touch();
+        self.inner.record_many(value, count);
+    }
+}
+
+/// Recorder in front of the fanout counting series and enforcing limits
+pub(crate) struct CardinalityRecorder<R> {
+    inner: R,
+    state: Arc<CardinalityState>,
+}
+
+impl<R> CardinalityRecorder<R> {
+    /// Wraps the fanout and makes the counts available to the endpoint
+    pub fn install(
+        inner: R,
+        default_limit: Option<usize>,
+        limits: &[(Matcher, usize)],
+        idle_timeout: Option<Duration>,
+    ) -> Self {
+        let state = STATE.get_or_init(|| {
+            Arc::new(CardinalityState::new(
+                default_limit,
+                limits.to_vec(),
+                idle_timeout,
+            ))
+        });
+        Self {
+            inner,
+            state: state.clone(),
+        }
+    }
+}
+
+impl<R: Recorder> Recorder for CardinalityRecorder<R> {
+    fn describe_counter(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_counter(key, unit, description);
+    }
+
+    fn describe_gauge(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_gauge(key, unit, description);
+    }
+
+    fn describe_histogram(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_histogram(key, unit, description);
+    }
+
+    fn register_counter(&self, key: &Key, metadata: &Metadata<'_>) -> Counter {
+        match self.state.admit(key) {
+            Admission::Refused => Counter::noop(),
+            Admission::Admitted(None) => self.inner.register_counter(key, metadata),
+            Admission::Admitted(Some(last_use)) => Counter::from_arc(Arc::new(Tracked {
+                inner: self.inner.register_counter(key, metadata),
+                last_use,
+            })),
+        }
+    }
+
+    fn register_gauge(&self, key: &Key, metadata: &Metadata<'_>) -> Gauge {
+        match self.state.admit(key) {
+            Admission::Refused => Gauge::noop(),
+            Admission::Admitted(None) => self.inner.register_gauge(key, metadata),
+            Admission::Admitted(Some(last_use)) => Gauge::from_arc(Arc::new(Tracked {
+                inner: self.inner.register_gauge(key, metadata),
+                last_use,
+            })),
+        }
+    }
+
+    fn register_histogram(&self, key: &Key, metadata: &Metadata<'_>) -> Histogram {
+        match self.state.admit(key) {
+            Admission::Refused => Histogram::noop(),
+            Admission::Admitted(None) => self.inner.register_histogram(key, metadata),
+            Admission::Admitted(Some(last_use)) => Histogram::from_arc(Arc::new(Tracked {
+                inner: self.inner.register_histogram(key, metadata),
+                last_use,
+            })),
+        }
+    }
+}
+
+/// Starts publishing the series counts as gauges, forgetting idle series
+/// first
+///
+/// Must be called from within a Tokio runtime.
+pub(crate) fn start_publishing() {
+    tokio::spawn(async {
+        let mut ticks = tokio::time::interval(PUBLISH_INTERVAL);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            let Some(state) = STATE.get() else {
+                continue;
+            };
+            state.forget_idle();
+            for metric in state.report().metrics {
+                metrics::gauge!(SERIES_METRIC, "metric" => metric.name.clone())
+                    .set(metric.series as f64);
+                if metric.refused > 0 {
+                    metrics::counter!(REFUSED_SERIES_METRIC, "metric" => metric.name)
+                        .absolute(metric.refused);
+                }
+            }
+        }
+    });
+}
+
+/// Endpoint listing the number of series of every metric name
+///
+/// # Returns
+///
+/// JSON with the total number of series and the series count, limit and
+/// refused label combinations of every metric name, largest first
+#[actix_web::get("/api/cardinality")]
+pub(crate) async fn get_cardinality() -> impl actix_web::Responder {
+    ApiResponse::respond(Ok(STATE
+        .get()
+        .map(|state| state.report())
+        .unwrap_or_default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metrics_util::debugging::DebuggingRecorder;
+
+    #[test]
+    fn test_series_counts_and_limits() {
+        let debugging = DebuggingRecorder::new();
+        let snapshotter = debugging.snapshotter();
+        let recorder = CardinalityRecorder {
+            inner: debugging,
+            state: Arc::new(CardinalityState::new(
+                Some(100),
+                vec![(Matcher::Prefix("user_".to_string()), 2)],
+                None,
+            )),
+        };
+        metrics::with_local_recorder(&recorder, || {
+            for user in ["a", "b", "c", "d"] {
+                metrics::counter!("user_logins", "user" => user).increment(1);
+            }
+            // Known series keep working at the limit
+            metrics::counter!("user_logins", "user" => "a").increment(1);
+            for path in ["/a", "/b", "/c"] {
+                metrics::gauge!("requests", "path" => path).set(1.0);
+            }
+        });
+
+        assert_eq!(snapshotter.snapshot().into_vec().len(), 5);
+        let report = recorder.state.report();
+        assert_eq!(report.total_series, 5);
+        assert_eq!(report.metrics[0].name, "requests");
+        assert_eq!(report.metrics[0].limit, Some(100));
+        let logins = &report.metrics[1];
+        assert_eq!(
+            (logins.series, logins.limit, logins.refused),
+            (2, Some(2), 2)
+        );
+    }
+
+    #[test]
+    fn test_idle_series_are_forgotten() {
+        let recorder = CardinalityRecorder {
+            inner: DebuggingRecorder::new(),
+            state: Arc::new(CardinalityState::new(
+                Some(2),
+                Vec::new(),
+                Some(Duration::from_secs(60)),
+            )),
+        };
+        let state = recorder.state.clone();
+        let series = |name: &str| {
+            let report = state.report();
+            let metric = report.metrics.iter().find(|metric| metric.name == name);
+            metric.map_or(0, |metric| metric.series)
+        };
+
+        let held = metrics::with_local_recorder(&recorder, || {
+            metrics::counter!("queue_jobs", "queue" => "a").increment(1);
+            metrics::counter!("queue_jobs", "queue" => "b")
+        });
+        // Updates through a held handle count as use
+        state.forget_idle_at(50);
+        held.increment(1);
+        state.forget_idle_at(100);
+        assert_eq!(series("queue_jobs"), 1);
+
+        // The forgotten series made room under the limit
+        metrics::with_local_recorder(&recorder, || {
+            metrics::counter!("queue_jobs", "queue" => "c").increment(1);
+            metrics::counter!("queue_jobs", "queue" => "d").increment(1);
+        });
+        assert_eq!(series("queue_jobs"), 2);
+        state.forget_idle_at(200);
+        assert_eq!(series("queue_jobs"), 0);
+    }
+
+    #[test]
+    fn test_series_metrics_are_never_limited() {
+        let state = CardinalityState::new(Some(1), Vec::new(), None);
+
+        for metric in ["a", "b", "c"] {
+            let key = Key::from_parts(SERIES_METRIC, vec![metrics::Label::new("metric", metric)]);
+            assert!(matches!(state.admit(&key), Admission::Admitted(_)));
+            let key = Key::from_parts(
+                REFUSED_SERIES_METRIC,
+                vec![metrics::Label::new("metric", metric)],
+            );
+            assert!(matches!(state.admit(&key), Admission::Admitted(_)));
+        }
+        assert_eq!(state.report().total_series, 6);
+    }
+
+    thread_local! {
+        static LOG_METRICS: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+    }
+
+    /// Forwards records to a `MetricsLogger` on threads that opted in
+    struct ThreadMetricsLogger(crate::MetricsLogger<Discard>);
+
+    struct Discard;
+
+    impl log::Log for Discard {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, _record: &log::Record) {}
+
+        fn flush(&self) {}
+    }
+
+    impl log::Log for ThreadMetricsLogger {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            self.0.enabled(metadata)
+        }
+
+        fn log(&self, record: &log::Record) {
+            if LOG_METRICS.get() {
+                self.0.log(record);
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn test_refusal_logged_through_metrics_logger() {
+        let logger = ThreadMetricsLogger(crate::MetricsLogger::new(Discard));
+        if log::set_boxed_logger(Box::new(logger)).is_ok() {
+            log::set_max_level(log::LevelFilter::Warn);
+        }
+        LOG_METRICS.set(true);
+
+        let recorder = CardinalityRecorder {
+            inner: DebuggingRecorder::new(),
+            state: Arc::new(CardinalityState::new(Some(1), Vec::new(), None)),
+        };
+        // The warning about the refused series records `log_records_total`
+        // through the same recorder and must not deadlock
+        metrics::with_local_recorder(&recorder, || {
+            metrics::counter!("jobs", "queue" => "a").increment(1);
+            metrics::counter!("jobs", "queue" => "b").increment(1);
+        });
+        LOG_METRICS.set(false);
+
+        let report = recorder.state.report();
+        let jobs = report.metrics.iter().find(|m| m.name == "jobs").unwrap();
+        assert_eq!((jobs.series, jobs.refused), (1, 1));
+        assert!(
+            report
+                .metrics
+                .iter()
+                .any(|m| m.name == crate::LOG_RECORDS_METRIC)
+        );
+    }
+}
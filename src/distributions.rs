@@ -0,0 +1,635 @@
+//! # Histogram Distributions
+//!
+//! Chooses per metric whether a histogram is exported with buckets or as a
+//! summary. Buckets, given explicitly or generated exponentially, are passed
+//! to the Prometheus recorder and the other bucket-aware parts of the crate.
+//!
+//! The Prometheus recorder only supports one set of quantiles and one
+//! summary window for all metrics, so histograms with their own summary
+//! configuration are kept away from it. This module records them in rolling
+//! summaries instead and appends them to the rendered exposition.
+
+use metrics::{Counter, Gauge, Histogram, Key, KeyName, Metadata, Recorder, SharedString, Unit};
+use metrics_exporter_prometheus::{Matcher, formatting};
+use metrics_util::{
+    registry::{AtomicStorage, Registry},
+    storage::Summary,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, bail};
+
+/// Most buckets an exponential configuration may generate, as every bucket
+/// is a series of its own
+const MAX_EXPONENTIAL_BUCKETS: u32 = 160;
+
+/// Histograms recorded as rolling summaries by this module
+static SUMMARIES: OnceLock<Arc<SummaryState>> = OnceLock::new();
+
+/// How a histogram is exported
+///
+/// # Example
+///
+/// ```
+/// use metrics_exporter_prometheus::Matcher;
+/// use metrics_rs_dashboard_actix::{DashboardInput, HistogramConfig, SummaryConfig};
+/// use std::time::Duration;
+///
+/// let dashboard_input = DashboardInput {
+///     histograms: vec![
+///         (
+///             Matcher::Suffix("_seconds".to_string()),
+///             HistogramConfig::ExponentialBuckets {
+///                 start: 0.001,
+///                 factor: 2.0,
+///                 count: 12,
+///             },
+///         ),
+///         (
+///             Matcher::Prefix("payload_".to_string()),
+///             HistogramConfig::Summary(SummaryConfig {
+///                 quantiles: vec![0.5, 0.99],
+///                 window: Duration::from_secs(300),
+///                 ..Default::default()
+///             }),
+///         ),
+///     ],
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone)]
+pub enum HistogramConfig {
+    /// Buckets with the given upper bounds
+    Buckets(Vec<f64>),
+    /// `count` buckets whose upper bounds start at `start` and grow by
+    /// `factor`, e.g. 1, 2, 4, 8 for a start of 1 and a factor of 2
+    ///
+    /// At most 160 buckets, all with finite bounds.
+    ExponentialBuckets { start: f64, factor: f64, count: u32 },
+    /// A summary with its own quantiles and window
+    Summary(SummaryConfig),
+}
+
+impl HistogramConfig {
+    /// Returns the bucket bounds, or `None` for summaries
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for empty buckets or invalid exponential parameters,
+    /// including too many buckets or bounds growing past the largest `f64`
+    fn bounds(&self) -> Result<Option<Vec<f64>>> {
+        Ok(Some(match self {
+            HistogramConfig::Buckets(bounds) => {
+                if bounds.is_empty() {
+                    bail!("histogram buckets must not be empty");
+                }
+                bounds.clone()
+            }
+            HistogramConfig::ExponentialBuckets {
+                start,
+                factor,
+                count,
+            } => {
+                if !(*start > 0.0 && *factor > 1.0 && *count > 0) {
+                    bail!(
+                        "exponential buckets need a positive start, a factor above 1 and at least one bucket"
+                    );
+                }
+                if *count > MAX_EXPONENTIAL_BUCKETS {
+                    bail!(
+                        "exponential buckets are limited to {} buckets, got {}",
+                        MAX_EXPONENTIAL_BUCKETS,
+                        count
+                    );
+                }
+                let bounds: Vec<f64> = (0..*count).map(|i| start * factor.powi(i as i32)).collect();
+                if bounds.iter().any(|bound| !bound.is_finite()) {
+                    bail!(
+                        "exponential buckets starting at {} with a factor of {} exceed the largest bound",
+                        start,
+                        factor
+                    );
+                }
+                bounds
+            }
+            HistogramConfig::Summary(_) => return Ok(None),
+        }))
+    }
+}
+
+/// Configuration of a rolling summary
+///
+/// Observations are kept for `window`, which is divided into `bucket_count`
+/// buckets; the oldest bucket is dropped as the window moves on.
+#[derive(Debug, Clone)]
+pub struct SummaryConfig {
+    /// Quantiles between 0 and 1
+    pub quantiles: Vec<f64>,
+    pub window: Duration,
+    pub bucket_count: u32,
+}
+
+impl Default for SummaryConfig {
+    /// The defaults of the Prometheus recorder: quantiles 0, 0.5, 0.9, 0.95,
+    /// 0.99, 0.999 and 1 over one minute in three buckets
+    fn default() -> Self {
+        Self {
+            quantiles: vec![0.0, 0.5, 0.9, 0.95, 0.99, 0.999, 1.0],
+            window: Duration::from_secs(60),
+            bucket_count: 3,
+        }
+    }
+}
+
+impl SummaryConfig {
+    fn validate(&self) -> Result<()> {
+        if self.quantiles.is_empty() || self.quantiles.iter().any(|q| !(0.0..=1.0).contains(q)) {
+            bail!("summary quantiles must be between 0 and 1");
+        }
+        if self.bucket_count == 0 || self.window.is_zero() {
+            bail!("summary window and bucket count must not be zero");
+        }
+        Ok(())
+    }
+
+    fn bucket_duration(&self) -> Duration {
+        self.window / self.bucket_count
+    }
+}
+
+/// Histogram configuration resolved from `buckets_for_metrics` and `histograms`
+#[derive(Debug, Default)]
+pub(crate) struct Distributions {
+    pub buckets: Vec<(Matcher, Vec<f64>)>,
+    pub summaries: Vec<(Matcher, SummaryConfig)>,
+}
+
+impl Distributions {
+    /// Splits the configured histograms into bucketed ones and summaries
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the matcher of an invalid configuration
+    pub fn resolve(
+        buckets_for_metrics: &[(Matcher, &[f64])],
+        histograms: &[(Matcher, HistogramConfig)],
+    ) -> Result<Self> {
+        let mut distributions = Self {
+            buckets: buckets_for_metrics
+                .iter()
+                .map(|(matcher, bounds)| (matcher.clone(), bounds.to_vec()))
+                .collect(),
+            summaries: Vec::new(),
+        };
+        for (matcher, config) in histograms {
+            let invalid = |e: anyhow::Error| e.context(format!("Invalid histogram {:?}", matcher));
+            match config.bounds().map_err(invalid)? {
+                Some(bounds) => distributions.buckets.push((matcher.clone(), bounds)),
+                None => {
+                    if let HistogramConfig::Summary(summary) = config {
+                        summary.validate().map_err(invalid)?;
+                        distributions
+                            .summaries
+                            .push((matcher.clone(), summary.clone()));
+                    }
+                }
+            }
+        }
+        Ok(distributions)
+    }
+
+    /// Bucket bounds in the form taken by the Prometheus builder
+    pub fn bucket_slices(&self) -> Vec<(Matcher, &[f64])> {
+        self.buckets
+            .iter()
+            .map(|(matcher, bounds)| (matcher.clone(), bounds.as_slice()))
+            .collect()
+    }
+}
+
+/// Summary over a sliding window, split into buckets of equal duration
+#[derive(Debug)]
+struct RollingSummary {
+    buckets: VecDeque<(Instant, Summary)>,
+    bucket_duration: Duration,
+    window: Duration,
+    count: u64,
+    sum: f64,
+}
+
+impl RollingSummary {
+    fn new(config: &SummaryConfig) -> Self {
+        Self {
+            buckets: VecDeque::new(),
+            bucket_duration: config.bucket_duration(),
+            window: config.window,
+            count: 0,
+            sum: 0.0,
+        }
+    }
+
+    fn add(&mut self, value: f64, now: Instant) {
+        self.count += 1;
+        self.sum += value;
+        self.expire(now);
+        match self.buckets.back_mut() {
+            Some((start, summary)) if now < *start + self.bucket_duration => summary.add(value),
+            _ => {
+                let mut summary = Summary::with_defaults();
+                summary.add(value);
+                self.buckets.push_back((now, summary));
+            }
+        }
+    }
+
+    /// Drops buckets that started before the window
+    fn expire(&mut self, now: Instant) {
+        while self
+            .buckets
+            .front()
+            .is_some_and(|(start, _)| *start + self.window <= now)
+        {
+            self.buckets.pop_front();
+        }
+    }
+
+    /// Merges the buckets within the window
+    fn snapshot(&mut self, now: Instant) -> Summary {
+        self.expire(now);
+        let mut merged = Summary::with_defaults();
+        for (_, summary) in &self.buckets {
+            // Summaries created with the same parameters always merge
+            let _ = merged.merge(summary);
+        }
+        merged
+    }
+}
+
+/// Index of the configuration of a summary and its values
+type SummaryEntry = (usize, RollingSummary);
+
+/// Observations and summaries of the histograms configured as summaries
+struct SummaryState {
+    configs: Vec<(Matcher, SummaryConfig)>,
+    registry: Registry<Key, AtomicStorage>,
+    summaries: Mutex<HashMap<Key, SummaryEntry>>,
+    descriptions: Mutex<HashMap<String, String>>,
+}
+
+impl SummaryState {
+    fn config_index(&self, name: &str) -> Option<usize> {
+        self.configs
+            .iter()
+            .position(|(matcher, _)| matcher.matches(name))
+    }
+
+    /// Moves new observations into the rolling summaries
+    fn drain(&self, now: Instant) {
+        let Ok(mut summaries) = self.summaries.lock() else {
+            return;
+        };
+        self.registry.visit_histograms(|key, bucket| {
+            let Some(index) = self.config_index(key.name()) else {
+                return;
+            };
+            let (_, summary) = summaries
+                .entry(key.clone())
+                .or_insert_with(|| (index, RollingSummary::new(&self.configs[index].1)));
+            bucket.clear_with(|values| {
+                for value in values {
+                    summary.add(*value, now);
+                }
+            });
+        });
+    }
+
+    /// Renders all summaries in the Prometheus text format
+    fn render(&self, output: &mut String, now: Instant) {
+        self.drain(now);
+        let (Ok(mut summaries), Ok(descriptions)) =
+            (self.summaries.lock(), self.descriptions.lock())
+        else {
+            return;
+        };
+
+        let mut by_name: HashMap<String, Vec<(Vec<String>, &mut SummaryEntry)>> = HashMap::new();
+        for (key, entry) in summaries.iter_mut() {
+            let (name, labels) = formatting::key_to_parts(key, None);
+            by_name.entry(name).or_default().push((labels, entry));
+        }
+        let mut names: Vec<_> = by_name.keys().cloned().collect();
+        names.sort();
+
+        for name in names {
+            let Some(mut series) = by_name.remove(&name) else {
+                continue;
+            };
+            series.sort_by(|a, b| a.0.cmp(&b.0));
+            if let Some(description) = descriptions.get(&name) {
+                formatting::write_help_line(output, &name, description);
+            }
+            formatting::write_type_line(output, &name, "summary");
+            for (labels, (index, summary)) in series {
+                let snapshot = summary.snapshot(now);
+                for quantile in &self.configs[*index].1.quantiles {
+                    let value = snapshot.quantile(*quantile).unwrap_or(0.0);
+                    formatting::write_metric_line(
+                        output,
+                        &name,
+                        None,
+                        &labels,
+                        Some(("quantile", quantile)),
+                        value,
+                        None,
+                    );
+                }
+                formatting::write_metric_line::<&str, f64>(
+                    output,
+                    &name,
+                    Some("sum"),
+                    &labels,
+                    None,
+                    summary.sum,
+                    None,
+                );
+                formatting::write_metric_line::<&str, u64>(
+                    output,
+                    &name,
+                    Some("count"),
+                    &labels,
+                    None,
+                    summary.count,
+                    None,
+                );
+            }
+            output.push('\n');
+        }
+    }
+}
+
+/// Recorder of the histograms configured as summaries
+pub(crate) struct SummaryRecorder {
+    state: Arc<SummaryState>,
+}
+
+impl SummaryRecorder {
+    /// Creates the recorder whose summaries [`append_summaries`] renders,
+    /// or returns `None` without summary configurations
+    pub fn install(configs: &[(Matcher, SummaryConfig)]) -> Option<Self> {
+        if configs.is_empty() {
+            return None;
+        }
+        let state = SUMMARIES.get_or_init(|| {
+            Arc::new(SummaryState {
+                configs: configs.to_vec(),
+                registry: Registry::atomic(),
+                summaries: Mutex::new(HashMap::new()),
+                descriptions: Mutex::new(HashMap::new()),
+            })
+        });
+        Some(Self {
+            state: state.clone(),
+        })
+    }
+
+    /// Returns true if the histogram is recorded by this recorder
+    pub fn handles(&self, name: &str) -> bool {
+        self.state.config_index(name).is_some()
+    }
+}
+
+impl Recorder for SummaryRecorder {
+    fn describe_counter(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+    fn describe_gauge(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+    fn describe_histogram(&self, key: KeyName, _unit: Option<Unit>, description: SharedString) {
+        if self.handles(key.as_str())
+            && let Ok(mut descriptions) = self.state.descriptions.lock()
+        {
+            descriptions.insert(
+                formatting::sanitize_metric_name(key.as_str()),
+                description.into_owned(),
+            );
+        }
+    }
+
+    fn register_counter(&self, _key: &Key, _metadata: &Metadata<'_>) -> Counter {
+        Counter::noop()
+    }
+
+    fn register_gauge(&self, _key: &Key, _metadata: &Metadata<'_>) -> Gauge {
+        Gauge::noop()
+    }
+
+    fn register_histogram(&self, key: &Key, _metadata: &Metadata<'_>) -> Histogram {
+        if !self.handles(key.name()) {
+            return Histogram::noop();
+        }
+        self.state
+            .registry
+            .get_or_create_histogram(key, |histogram| Histogram::from_arc(histogram.clone()))
+    }
+}
+
+/// Wraps a recorder, hiding the histograms recorded as summaries from it
+pub(crate) struct WithoutSummaries<R> {
+    pub inner: R,
+    pub matchers: Vec<Matcher>,
+}
+
+impl<R: Recorder> Recorder for WithoutSummaries<R> {
+    fn describe_counter(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_counter(key, unit, description);
+    }
+
+    fn describe_gauge(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_gauge(key, unit, description);
+    }
+
+    fn describe_histogram(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_histogram(key, unit, description);
+    }
+
+    fn register_counter(&self, key: &Key, metadata: &Metadata<'_>) -> Counter {
+        self.inner.register_counter(key, metadata)
+    }
+
+    fn register_gauge(&self, key: &Key, metadata: &Metadata<'_>) -> Gauge {
+        self.inner.register_gauge(key, metadata)
+    }
+
+    fn register_histogram(&self, key: &Key, metadata: &Metadata<'_>) -> Histogram {
+        if self
+            .matchers
+            .iter()
+            .any(|matcher| matcher.matches(key.name()))
+        {
+            return Histogram::noop();
+        }
+        self.inner.register_histogram(key, metadata)
+    }
+}
+
+/// Appends the histograms configured as summaries to an exposition
+pub(crate) fn append_summaries(output: &mut String) {
+    if let Some(state) = SUMMARIES.get() {
+        state.render(output, Instant::now());
+    }
+}
+
+/// Moves new observations into the summaries, so that they don't pile up
+/// between two renders
+pub(crate) fn run_upkeep() {
+    if let Some(state) = SUMMARIES.get() {
+        state.drain(Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::{SeriesValue, Snapshot};
+
+    #[test]
+    fn test_resolve_distributions() {
+        let distributions = Distributions::resolve(
+            &[(Matcher::Full("a".to_string()), &[1.0, 2.0])],
+            &[
+                (
+                    Matcher::Full("b".to_string()),
+                    HistogramConfig::ExponentialBuckets {
+                        start: 0.5,
+                        factor: 2.0,
+                        count: 4,
+                    },
+                ),
+                (
+                    Matcher::Full("c".to_string()),
+                    HistogramConfig::Summary(SummaryConfig::default()),
+                ),
+            ],
+        )
+        .unwrap();
+        assert_eq!(distributions.buckets[1].1, vec![0.5, 1.0, 2.0, 4.0]);
+        assert_eq!(distributions.bucket_slices().len(), 2);
+        assert_eq!(distributions.summaries.len(), 1);
+
+        let invalid = [
+            HistogramConfig::Buckets(Vec::new()),
+            HistogramConfig::ExponentialBuckets {
+                start: 1.0,
+                factor: 1.0,
+                count: 3,
+            },
+            HistogramConfig::ExponentialBuckets {
+                start: 1.0,
+                factor: 1.01,
+                count: MAX_EXPONENTIAL_BUCKETS + 1,
+            },
+            HistogramConfig::ExponentialBuckets {
+                start: 1.0,
+                factor: 1e300,
+                count: 3,
+            },
+            HistogramConfig::ExponentialBuckets {
+                start: f64::INFINITY,
+                factor: 2.0,
+                count: 1,
+            },
+            HistogramConfig::ExponentialBuckets {
+                start: 1.0,
+                factor: f64::NAN,
+                count: 2,
+            },
+            HistogramConfig::Summary(SummaryConfig {
+                quantiles: vec![1.5],
+                ..Default::default()
+            }),
+            HistogramConfig::Summary(SummaryConfig {
+                bucket_count: 0,
+                ..Default::default()
+            }),
+        ];
+        for config in invalid {
+            let histograms = [(Matcher::Full("d".to_string()), config)];
+            assert!(Distributions::resolve(&[], &histograms).is_err());
+        }
+    }
+
+    #[test]
+    fn test_rolling_summary_window() {
+        let config = SummaryConfig {
+            window: Duration::from_secs(30),
+            ..Default::default()
+        };
+        let mut summary = RollingSummary::new(&config);
+        let start = Instant::now();
+        summary.add(100.0, start);
+        summary.add(1.0, start + Duration::from_secs(15));
+        summary.add(2.0, start + Duration::from_secs(25));
+
+        assert_eq!(summary.snapshot(start + Duration::from_secs(26)).count(), 3);
+        // The first bucket left the window, the totals stay cumulative
+        let snapshot = summary.snapshot(start + Duration::from_secs(31));
+        assert_eq!(snapshot.count(), 2);
+        assert_eq!(snapshot.max(), 2.0);
+        assert_eq!((summary.count, summary.sum), (3, 103.0));
+    }
+
+    #[test]
+    fn test_render_summaries() {
+        let state = SummaryState {
+            configs: vec![(
+                Matcher::Prefix("payload".to_string()),
+                SummaryConfig {
+                    quantiles: vec![0.5, 1.0],
+                    ..Default::default()
+                },
+            )],
+            registry: Registry::atomic(),
+            summaries: Mutex::new(HashMap::new()),
+            descriptions: Mutex::new(HashMap::new()),
+        };
+        let recorder = SummaryRecorder {
+            state: Arc::new(state),
+        };
+        let hidden = WithoutSummaries {
+            inner: metrics_util::debugging::DebuggingRecorder::new(),
+            matchers: vec![Matcher::Prefix("payload".to_string())],
+        };
+        metrics::with_local_recorder(&recorder, || {
+            metrics::describe_histogram!("payload_size", "Size of payloads");
+            for value in [10.0, 20.0, 30.0] {
+                metrics::histogram!("payload_size", "route" => "/a").record(value);
+            }
+        });
+        let snapshotter = hidden.inner.snapshotter();
+        metrics::with_local_recorder(&hidden, || {
+            metrics::histogram!("payload_size").record(1.0);
+            metrics::histogram!("other").record(1.0);
+        });
+        assert_eq!(snapshotter.snapshot().into_vec().len(), 1);
+
+        let mut output = String::new();
+        recorder.state.render(&mut output, Instant::now());
+        assert!(
+            output
+                .starts_with("# HELP payload_size Size of payloads\n# TYPE payload_size summary\n")
+        );
+        assert!(output.contains("payload_size_count{route=\"/a\"} 3\n"));
+
+        let family = &Snapshot::parse(&output).families["payload_size"];
+        let series = family.series.values().next().unwrap();
+        let SeriesValue::Summary { quantiles, sum, .. } = &series.value else {
+            panic!("expected a summary");
+        };
+        assert_eq!(quantiles.len(), 2);
+        assert!((quantiles[0].1 - 20.0).abs() < 0.5);
+        assert_eq!(*sum, 60.0);
+    }
+}
@@ -0,0 +1,446 @@
+//! # Histogram Exemplars
+//!
+//! Exemplars link a single histogram observation to the trace that produced
+//! it. The most recent exemplar of every bucket is kept, emitted in the
+//! OpenMetrics exposition and sent to the dashboard, where it is drawn as a
+//! clickable point on top of the histogram.
+//!
+//! Exemplars can only be attached to bucketed histograms, i.e. metrics
+//! given buckets by `DashboardInput::buckets_for_metrics` or
+//! `DashboardInput::histograms`. Their series go through the same relabeling
+//! and constant labels as the histogram itself, and exemplars of series that
+//! are no longer rendered are forgotten on upkeep.
+
+use crate::{
+    labels,
+    relabel::Relabeler,
+    snapshot::{Snapshot, format_bound, series_key},
+};
+use log::debug;
+use metrics::{Key, Label};
+use metrics_exporter_prometheus::{
+    Matcher,
+    formatting::{sanitize_label_key, sanitize_label_value, sanitize_metric_name},
+};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Maximum combined length of exemplar label names and values allowed by OpenMetrics
+const MAX_EXEMPLAR_LABELS_LENGTH: usize = 128;
+
+/// An observation attached to a histogram bucket
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Exemplar {
+    /// Exemplar labels, typically a `trace_id`
+    pub labels: BTreeMap<String, String>,
+    /// The observed value
+    pub value: f64,
+    /// Unix timestamp of the observation in seconds
+    pub timestamp: f64,
+    /// Link built from `DashboardInput::exemplar_link`, opened when the
+    /// exemplar is clicked in the dashboard
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+}
+
+/// Exemplar settings taken from the dashboard configuration
+#[derive(Debug)]
+struct ExemplarSettings {
+    buckets: Vec<(Matcher, Vec<f64>)>,
+    link: Option<String>,
+    relabeler: Arc<Relabeler>,
+}
+
+/// Bucket bounds, link template and relabeling rules, set once when the
+/// recorders are configured
+static SETTINGS: OnceLock<ExemplarSettings> = OnceLock::new();
+
+/// Most recent exemplar of each bucket, keyed by metric name, then by
+/// [`series_key`], then by the formatted upper bound of the bucket
+type ExemplarStore = HashMap<String, HashMap<String, BTreeMap<String, Exemplar>>>;
+
+static EXEMPLARS: OnceLock<Mutex<ExemplarStore>> = OnceLock::new();
+
+/// Stores the histogram buckets, link template and relabeler used for exemplars
+pub(crate) fn configure(
+    buckets: &[(Matcher, &[f64])],
+    link: Option<&str>,
+    relabeler: Arc<Relabeler>,
+) {
+    let settings = ExemplarSettings {
+        buckets: buckets
+            .iter()
+            .map(|(matcher, bounds)| (matcher.clone(), bounds.to_vec()))
+            .collect(),
+        link: link.map(str::to_owned),
+        relabeler,
+    };
+    let _ = SETTINGS.set(settings);
+}
+
+/// Records an exemplar for a histogram observation
+///
+/// The observation itself must be recorded separately, which
+/// [`histogram_with_exemplar!`](crate::histogram_with_exemplar) does for you.
+/// The exemplar replaces the previous one of the bucket the value falls into.
+/// It is dropped if the histogram has no configured buckets, its series is
+/// dropped by a relabel rule or its labels are longer than the 128 characters
+/// OpenMetrics allows.
+///
+/// # Arguments
+///
+/// * `name` - Name of the histogram
+/// * `labels` - Labels of the histogram series
+/// * `value` - The observed value
+/// * `exemplar_labels` - Labels identifying the observation, e.g. `[("trace_id", id)]`
+///
+/// # Example
+///
+/// ```rust
+/// use metrics_rs_dashboard_actix::record_exemplar;
+///
+/// metrics::histogram!("request_latency", "endpoint" => "/users").record(42.0);
+/// record_exemplar("request_latency", &[("endpoint", "/users")], 42.0, &[("trace_id", "4bf92f3577b34da6")]);
+/// ```
+pub fn record_exemplar(
+    name: &str,
+    labels: &[(&str, &str)],
+    value: f64,
+    exemplar_labels: &[(&str, &str)],
+) {
+    if let Some(settings) = SETTINGS.get() {
+        store_exemplar(settings, name, labels, value, exemplar_labels);
+    }
+}
+
+/// Stores an exemplar in the bucket of `value` using the given settings
+fn store_exemplar(
+    settings: &ExemplarSettings,
+    name: &str,
+    labels: &[(&str, &str)],
+    value: f64,
+    exemplar_labels: &[(&str, &str)],
+) {
+    // Keyed like the rendered series, which are relabeled first
+    let key = Key::from_parts(
+        name.to_owned(),
+        labels
+            .iter()
+            .map(|(k, v)| Label::new(k.to_string(), v.to_string()))
+            .collect::<Vec<_>>(),
+    );
+    let Some(key) = settings.relabeler.relabel(&key) else {
+        debug!(
+            "Series of {} dropped by relabeling, dropping exemplar",
+            name
+        );
+        return;
+    };
+    let labels: Vec<(&str, &str)> = key
+        .labels()
+        .map(|label| (label.key(), label.value()))
+        .collect();
+
+    let Some((_, bounds)) = settings
+        .buckets
+        .iter()
+        .find(|(matcher, _)| matcher.matches(name))
+    else {
+        debug!("No buckets configured for {}, dropping exemplar", name);
+        return;
+    };
+
+    let exemplar_length: usize = exemplar_labels
+        .iter()
+        .map(|(k, v)| k.chars().count() + v.chars().count())
+        .sum();
+    if exemplar_length > MAX_EXEMPLAR_LABELS_LENGTH {
+        debug!(
+            "Exemplar labels of {} are too long, dropping exemplar",
+            name
+        );
+        return;
+    }
+
+    let bound = bounds
+        .iter()
+        .copied()
+        .find(|bound| value <= *bound)
+        .unwrap_or(f64::INFINITY);
+    let exemplar = Exemplar {
+        labels: exemplar_labels
+            .iter()
+            .map(|(k, v)| (sanitize_label_key(k), sanitize_label_value(v)))
+            .collect(),
+        value,
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs_f64())
+            .unwrap_or_default(),
+        link: settings
+            .link
+            .as_deref()
+            .map(|template| build_link(template, exemplar_labels)),
+    };
+
+    // The rendered series carry the constant labels as well
+    let constant = labels::missing_labels(name, &labels);
+    let series_labels: BTreeMap<String, String> = labels
+        .into_iter()
+        .chain(constant.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+        .map(|(k, v)| (sanitize_label_key(k), sanitize_label_value(v)))
+        .collect();
+    let exemplars = EXEMPLARS.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Ok(mut exemplars) = exemplars.lock() {
+        exemplars
+            .entry(sanitize_metric_name(name))
+            .or_default()
+            .entry(series_key(&series_labels))
+            .or_default()
+            .insert(format_bound(bound), exemplar);
+    }
+}
+
+/// Forgets the exemplars of series missing from `snapshot`, rendered at
+/// `rendered_at`, keeping those recorded after it was taken
+pub(crate) fn prune(snapshot: &Snapshot, rendered_at: f64) {
+    if let Some(Ok(mut exemplars)) = EXEMPLARS.get().map(Mutex::lock) {
+        retain_rendered(&mut exemplars, snapshot, rendered_at);
+    }
+}
+
+fn retain_rendered(exemplars: &mut ExemplarStore, snapshot: &Snapshot, rendered_at: f64) {
+    exemplars.retain(|name, series| {
+        let family = snapshot.families.get(name);
+        series.retain(|key, buckets| {
+            family.is_some_and(|family| family.series.contains_key(key))
+                || buckets
+                    .values()
+                    .any(|exemplar| exemplar.timestamp >= rendered_at)
+        });
+        !series.is_empty()
+    });
+}
+
+/// Returns the exemplars of a series keyed by bucket upper bound
+pub(crate) fn for_series(name: &str, key: &str) -> BTreeMap<String, Exemplar> {
+    EXEMPLARS
+        .get()
+        .and_then(|exemplars| exemplars.lock().ok()?.get(name)?.get(key).cloned())
+        .unwrap_or_default()
+}
+
+/// Characters of label values kept as they are in links, the unreserved
+/// characters of RFC 3986
+const LINK_VALUE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Replaces `{label}` placeholders in the link template with the
+/// percent-encoded exemplar label values
+fn build_link(template: &str, exemplar_labels: &[(&str, &str)]) -> String {
+    exemplar_labels
+        .iter()
+        .fold(template.to_owned(), |link, (key, value)| {
+            let value = utf8_percent_encode(value, LINK_VALUE).to_string();
+            link.replace(&format!("{{{}}}", key), &value)
+        })
+}
+
+/// Macro for recording a histogram observation together with an exemplar
+///
+/// The exemplar is kept as the most recent one of the bucket the value falls
+/// into. See [`record_exemplar`](crate::record_exemplar) for the requirements.
+///
+/// # Example
+///
+/// ```rust
+/// use metrics_rs_dashboard_actix::histogram_with_exemplar;
+///
+/// let trace_id = "4bf92f3577b34da6";
+///
+/// // Histogram with an exemplar
+/// histogram_with_exemplar!("request_latency", 42.0, "trace_id", trace_id);
+///
+/// // Histogram with labels and an exemplar
+/// histogram_with_exemplar!("request_latency", 42.0, "endpoint", "/users", "trace_id", trace_id);
+/// ```
+#[macro_export]
+macro_rules! histogram_with_exemplar {
+    ($name:expr, $value:expr, $exemplar_key:expr, $exemplar_value:expr) => {{
+        metrics::histogram!($name).record($value);
+        $crate::record_exemplar($name, &[], $value, &[($exemplar_key, $exemplar_value)]);
+    }};
+    ($name:expr, $value:expr, $label_key:expr, $label_value:expr, $exemplar_key:expr, $exemplar_value:expr) => {{
+        metrics::histogram!($name, $label_key => $label_value).record($value);
+        $crate::record_exemplar(
+            $name,
+            &[($label_key, $label_value)],
+            $value,
+            &[($exemplar_key, $exemplar_value)],
+        );
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RelabelRule;
+
+    fn test_settings() -> ExemplarSettings {
+        ExemplarSettings {
+            buckets: vec![(Matcher::Prefix("exemplar_test".to_string()), vec![0.1, 0.5])],
+            link: Some("http://traces.local/trace/{trace_id}".to_string()),
+            relabeler: Arc::new(
+                Relabeler::new(&[
+                    RelabelRule::DropSeries {
+                        label: "endpoint".to_string(),
+                        value: "/health".to_string(),
+                    },
+                    RelabelRule::RenameLabel {
+                        from: "path".to_string(),
+                        to: "endpoint".to_string(),
+                    },
+                ])
+                .unwrap(),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_most_recent_exemplar_per_bucket() {
+        let settings = test_settings();
+        let labels = [("endpoint", "/a")];
+        let name = "exemplar_test_latency";
+        store_exemplar(&settings, name, &labels, 0.05, &[("trace_id", "1")]);
+        store_exemplar(&settings, name, &labels, 0.08, &[("trace_id", "2")]);
+        store_exemplar(&settings, name, &labels, 3.0, &[("trace_id", "3")]);
+
+        let exemplars = for_series("exemplar_test_latency", "endpoint=\"/a\"");
+        assert_eq!(exemplars.len(), 2);
+        assert_eq!(exemplars["0.1"].labels["trace_id"], "2");
+        assert_eq!(exemplars["0.1"].value, 0.08);
+        assert_eq!(exemplars["+Inf"].labels["trace_id"], "3");
+    }
+
+    #[test]
+    fn test_exemplar_link_and_limits() {
+        let settings = test_settings();
+        let long_id = "x".repeat(200);
+        store_exemplar(
+            &settings,
+            "exemplar_test_link",
+            &[],
+            0.2,
+            &[("trace_id", "abc")],
+        );
+        store_exemplar(
+            &settings,
+            "exemplar_test_long",
+            &[],
+            0.2,
+            &[("trace_id", &long_id)],
+        );
+        store_exemplar(
+            &settings,
+            "unbucketed_latency",
+            &[],
+            0.2,
+            &[("trace_id", "abc")],
+        );
+
+        let exemplars = for_series("exemplar_test_link", "");
+        assert_eq!(
+            exemplars["0.5"].link.as_deref(),
+            Some("http://traces.local/trace/abc")
+        );
+        assert!(for_series("exemplar_test_long", "").is_empty());
+        assert!(for_series("unbucketed_latency", "").is_empty());
+
+        assert_eq!(
+            build_link(
+                "http://traces.local/search?q={query}",
+                &[("query", "a b&c=/d?é")]
+            ),
+            "http://traces.local/search?q=a%20b%26c%3D%2Fd%3F%C3%A9"
+        );
+    }
+
+    #[test]
+    fn test_exemplar_series_are_relabeled() {
+        let settings = test_settings();
+        let name = "exemplar_test_relabeled";
+        store_exemplar(
+            &settings,
+            name,
+            &[("path", "/a")],
+            0.05,
+            &[("trace_id", "1")],
+        );
+        store_exemplar(
+            &settings,
+            name,
+            &[("endpoint", "/health")],
+            0.05,
+            &[("trace_id", "2")],
+        );
+
+        assert_eq!(
+            for_series(name, "endpoint=\"/a\"")["0.1"].labels["trace_id"],
+            "1"
+        );
+        assert!(for_series(name, "path=\"/a\"").is_empty());
+        assert!(for_series(name, "endpoint=\"/health\"").is_empty());
+    }
+
+    #[test]
+    fn test_exemplars_of_removed_series_are_pruned() {
+        let bucket = |timestamp| {
+            BTreeMap::from([(
+                "0.1".to_string(),
+                Exemplar {
+                    labels: BTreeMap::new(),
+                    value: 0.05,
+                    timestamp,
+                    link: None,
+                },
+            )])
+        };
+        let mut exemplars: ExemplarStore = HashMap::from([
+            (
+                "latency".to_string(),
+                HashMap::from([
+                    ("endpoint=\"/a\"".to_string(), bucket(10.0)),
+                    ("endpoint=\"/b\"".to_string(), bucket(10.0)),
+                    ("endpoint=\"/c\"".to_string(), bucket(30.0)),
+                ]),
+            ),
+            (
+                "removed".to_string(),
+                HashMap::from([(String::new(), bucket(10.0))]),
+            ),
+        ]);
+        let snapshot = Snapshot::parse(
+            r#"# TYPE latency histogram
+latency_bucket{endpoint="/a",le="0.1"} 1
+latency_bucket{endpoint="/a",le="+Inf"} 1
+latency_sum{endpoint="/a"} 0.05
+latency_count{endpoint="/a"} 1
+"#,
+        );
+        retain_rendered(&mut exemplars, &snapshot, 20.0);
+
+        assert_eq!(exemplars.len(), 1);
+        let mut keys: Vec<_> = exemplars["latency"].keys().collect();
+        keys.sort();
+        // `/c` was observed after the exposition was rendered
+        assert_eq!(keys, ["endpoint=\"/a\"", "endpoint=\"/c\""]);
+    }
+}
@@ -0,0 +1,205 @@
+//! # Graphite Exporter
+//!
+//! Sends snapshots to Carbon using the Graphite plaintext protocol over TCP.
+//! Every sample becomes a `path value timestamp` line, where the path is
+//! built from the metric name and its labels according to a [`GraphitePathScheme`].
+
+use super::spawn_periodic;
+use crate::snapshot::{FlatSample, Snapshot};
+use anyhow::Result;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+
+/// How labels are turned into a Graphite metric path
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GraphitePathScheme {
+    /// `name.key1.value1.key2.value2`, labels sorted by key
+    #[default]
+    LabelPairs,
+    /// `name.value1.value2`, labels sorted by key
+    LabelValues,
+    /// Graphite 1.1 tagged series: `name;key1=value1;key2=value2`
+    Tags,
+}
+
+/// Configuration of the Graphite exporter
+///
+/// # Example
+///
+/// ```
+/// use metrics_rs_dashboard_actix::{DashboardInput, GraphiteConfig, GraphitePathScheme};
+///
+/// let dashboard_input = DashboardInput {
+///     graphite: Some(GraphiteConfig {
+///         address: "carbon:2003".to_string(),
+///         prefix: Some("servers.web01".to_string()),
+///         path_scheme: GraphitePathScheme::Tags,
+///         ..Default::default()
+///     }),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone)]
+pub struct GraphiteConfig {
+    /// `host:port` of the Carbon plaintext listener
+    pub address: String,
+    /// Dotted prefix prepended to every path
+    pub prefix: Option<String>,
+    /// How labels are encoded in the path
+    pub path_scheme: GraphitePathScheme,
+    /// Interval between two snapshots
+    pub interval: Duration,
+}
+
+impl Default for GraphiteConfig {
+    fn default() -> Self {
+        Self {
+            address: "localhost:2003".to_string(),
+            prefix: None,
+            path_scheme: GraphitePathScheme::default(),
+            interval: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Starts sending snapshots to Carbon on the configured interval
+///
+/// Must be called from within a Tokio runtime.
+pub(crate) fn start_graphite(config: GraphiteConfig) {
+    let interval = config.interval;
+    spawn_periodic("Graphite", interval, move || {
+        let config = config.clone();
+        async move { send(&config, &Snapshot::capture()).await }
+    });
+}
+
+/// Sends a snapshot over a new connection
+async fn send(config: &GraphiteConfig, snapshot: &Snapshot) -> Result<()> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    let payload = render(config, snapshot, timestamp);
+    if payload.is_empty() {
+        return Ok(());
+    }
+
+    let mut stream = TcpStream::connect(&config.address).await?;
+    stream.write_all(payload.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Renders all samples as plaintext protocol lines
+fn render(config: &GraphiteConfig, snapshot: &Snapshot, timestamp: u64) -> String {
+    snapshot
+        .samples()
+        .iter()
+        .filter(|sample| sample.value.is_finite())
+        .map(|sample| format!("{} {} {}\n", path(config, sample), sample.value, timestamp))
+        .collect()
+}
+
+/// Builds the metric path of a sample
+fn path(config: &GraphiteConfig, sample: &FlatSample) -> String {
+    let mut path = match &config.prefix {
+        Some(prefix) => format!("{}.{}", prefix, sanitize(&sample.name)),
+        None => sanitize(&sample.name),
+    };
+    for (key, value) in &sample.labels {
+        match config.path_scheme {
+            GraphitePathScheme::LabelPairs => {
+                path.push_str(&format!(".{}.{}", sanitize(key), sanitize(value)));
+            }
+            GraphitePathScheme::LabelValues => {
+                path.push_str(&format!(".{}", sanitize(value)));
+            }
+            GraphitePathScheme::Tags => {
+                path.push_str(&format!(";{}={}", sanitize(key), sanitize_tag_value(value)));
+            }
+        }
+    }
+    path
+}
+
+/// Replaces characters that would split or break a path component
+fn sanitize(component: &str) -> String {
+    component
+        .chars()
+        .map(|c| match c {
+            '.' | ' ' | '/' | ';' | '=' | '~' | '\n' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Tag values may contain dots, but no separators or whitespace
+fn sanitize_tag_value(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            ' ' | ';' | '~' | '\n' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    const EXPOSITION: &str = r#"# TYPE http_requests_total counter
+http_requests_total{method="GET",path="/api/users"} 7
+# TYPE latency histogram
+latency_bucket{le="0.5"} 1
+latency_bucket{le="+Inf"} 2
+latency_sum 0.7
+latency_count 2
+"#;
+
+    fn config(path_scheme: GraphitePathScheme) -> GraphiteConfig {
+        GraphiteConfig {
+            prefix: Some("app".to_string()),
+            path_scheme,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_path_schemes() {
+        let snapshot = Snapshot::parse(EXPOSITION);
+
+        let pairs = render(&config(GraphitePathScheme::LabelPairs), &snapshot, 100);
+        assert!(pairs.contains("app.http_requests_total.method.GET.path._api_users 7 100\n"));
+        assert!(pairs.contains("app.latency_bucket.le.0_5 1 100\n"));
+
+        let values = render(&config(GraphitePathScheme::LabelValues), &snapshot, 100);
+        assert!(values.contains("app.http_requests_total.GET._api_users 7 100\n"));
+
+        let tags = render(&config(GraphitePathScheme::Tags), &snapshot, 100);
+        assert!(tags.contains("app.http_requests_total;method=GET;path=/api/users 7 100\n"));
+        assert!(tags.contains("app.latency_bucket;le=+Inf 2 100\n"));
+    }
+
+    #[tokio::test]
+    async fn test_send_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = GraphiteConfig {
+            address: listener.local_addr().unwrap().to_string(),
+            ..Default::default()
+        };
+        let receiver = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut received = String::new();
+            socket.read_to_string(&mut received).await.unwrap();
+            received
+        });
+
+        send(&config, &Snapshot::parse(EXPOSITION)).await.unwrap();
+
+        let received = receiver.await.unwrap();
+        assert_eq!(received.lines().count(), 5);
+        assert!(received.starts_with("http_requests_total.method.GET"));
+    }
+}
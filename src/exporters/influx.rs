@@ -0,0 +1,242 @@
+//! # InfluxDB Line Protocol
+//!
+//! Renders snapshots in the InfluxDB line protocol, both for the
+//! `/metrics/influx` endpoint and for periodic pushes to an Influx-compatible
+//! write API. Every series becomes one line: the metric name is the
+//! measurement, labels are tags and values are fields. Counters and gauges
+//! have a single `value` field; histograms and summaries have `count`, `sum`
+//! and one field per bucket bound or quantile.
+
+use super::{http_client, spawn_periodic};
+use crate::snapshot::{Series, SeriesValue, Snapshot, format_bound, unescape_label_value};
+use actix_web::{HttpResponse, Responder};
+use anyhow::{Result, anyhow};
+use std::{
+    fmt::Write,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Configuration of the InfluxDB push
+///
+/// # Example
+///
+/// ```
+/// use metrics_rs_dashboard_actix::{DashboardInput, InfluxConfig};
+///
+/// let dashboard_input = DashboardInput {
+///     influx: Some(InfluxConfig {
+///         url: "http://influx:8086/api/v2/write?org=acme&bucket=metrics&precision=ns".to_string(),
+///         token: Some("my-token".to_string()),
+///         ..Default::default()
+///     }),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone)]
+pub struct InfluxConfig {
+    /// Full write URL including the query string, e.g. `org`, `bucket` and
+    /// `precision=ns` for InfluxDB 2 or `db` for InfluxDB 1
+    pub url: String,
+    /// API token sent as `Authorization: Token ...`
+    pub token: Option<String>,
+    /// Interval between two pushes
+    pub interval: Duration,
+}
+
+impl Default for InfluxConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://localhost:8086/api/v2/write?precision=ns".to_string(),
+            token: None,
+            interval: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Endpoint returning the current metrics in InfluxDB line protocol
+///
+/// # Returns
+///
+/// One line per series with nanosecond timestamps
+#[actix_web::get("/influx")]
+pub(crate) async fn get_influx_metrics() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body(render(&Snapshot::capture(), unix_nanos()))
+}
+
+/// Starts pushing to the write API on the configured interval
+///
+/// Must be called from within a Tokio runtime.
+pub(crate) fn start_influx(config: InfluxConfig) {
+    let interval = config.interval;
+    spawn_periodic("InfluxDB", interval, move || {
+        let config = config.clone();
+        async move { push(&config, render(&Snapshot::capture(), unix_nanos())).await }
+    });
+}
+
+/// Sends line protocol to the write API
+async fn push(config: &InfluxConfig, body: String) -> Result<()> {
+    if body.is_empty() {
+        return Ok(());
+    }
+    let mut request = http_client()
+        .post(&config.url)
+        .header("content-type", "text/plain; charset=utf-8")
+        .body(body);
+    if let Some(token) = &config.token {
+        request = request.header("authorization", format!("Token {}", token));
+    }
+
+    let response = request.send().await?;
+    if !response.status().is_success() {
+        return Err(anyhow!("write API responded with {}", response.status()));
+    }
+    Ok(())
+}
+
+/// Renders a snapshot as line protocol with the given timestamp
+fn render(snapshot: &Snapshot, timestamp: u128) -> String {
+    let mut output = String::new();
+    for (name, family) in &snapshot.families {
+        for series in family.series.values() {
+            let fields = fields(series);
+            if fields.is_empty() {
+                continue;
+            }
+            output.push_str(&escape(name, &[',', ' ']));
+            for (key, value) in &series.labels {
+                let value = unescape_label_value(value);
+                if value.is_empty() {
+                    continue;
+                }
+                let _ = write!(
+                    output,
+                    ",{}={}",
+                    escape(key, &[',', '=', ' ']),
+                    escape(&value, &[',', '=', ' '])
+                );
+            }
+            let _ = writeln!(output, " {} {}", fields.join(","), timestamp);
+        }
+    }
+    output
+}
+
+/// Formats the fields of a series, skipping values Influx can't store
+fn fields(series: &Series) -> Vec<String> {
+    let mut fields: Vec<(String, f64)> = Vec::new();
+    match &series.value {
+        SeriesValue::Single { value } => fields.push(("value".to_string(), *value)),
+        SeriesValue::Histogram {
+            buckets,
+            count,
+            sum,
+            ..
+        } => {
+            fields.push(("count".to_string(), *count));
+            fields.push(("sum".to_string(), *sum));
+            for (bound, bucket_count) in buckets {
+                fields.push((format_bound(*bound), *bucket_count));
+            }
+        }
+        SeriesValue::Summary {
+            quantiles,
+            count,
+            sum,
+        } => {
+            fields.push(("count".to_string(), *count));
+            fields.push(("sum".to_string(), *sum));
+            for (quantile, value) in quantiles {
+                fields.push((format_bound(*quantile), *value));
+            }
+        }
+    }
+
+    fields
+        .into_iter()
+        .filter(|(_, value)| value.is_finite())
+        .map(|(key, value)| format!("{}={:?}", escape(&key, &[',', '=', ' ']), value))
+        .collect()
+}
+
+/// Escapes the given characters with a backslash
+fn escape(text: &str, special: &[char]) -> String {
+    let mut output = String::with_capacity(text.len());
+    for c in text.chars() {
+        if special.contains(&c) {
+            output.push('\\');
+        }
+        output.push(c);
+    }
+    output
+}
+
+fn unix_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporters::test_support::MockCollector;
+
+    const EXPOSITION: &str = r#"# TYPE http_requests_total counter
+http_requests_total{method="GET",path="/a b"} 7
+# TYPE temperature gauge
+temperature NaN
+# TYPE latency histogram
+latency_bucket{le="0.5"} 1
+latency_bucket{le="+Inf"} 2
+latency_sum 0.7
+latency_count 2
+"#;
+
+    #[test]
+    fn test_render_line_protocol() {
+        let text = render(&Snapshot::parse(EXPOSITION), 1_700_000_000_000_000_000);
+
+        assert_eq!(
+            text,
+            "http_requests_total,method=GET,path=/a\\ b value=7.0 1700000000000000000\n\
+             latency count=2.0,sum=0.7,0.5=1.0,+Inf=2.0 1700000000000000000\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_push_to_write_api() {
+        let influx = MockCollector::start().await;
+        let config = InfluxConfig {
+            url: influx.url("/api/v2/write?bucket=metrics&precision=ns"),
+            token: Some("secret".to_string()),
+            ..Default::default()
+        };
+
+        push(&config, render(&Snapshot::parse(EXPOSITION), 1))
+            .await
+            .unwrap();
+
+        let requests = influx.requests();
+        assert_eq!(
+            requests[0].path,
+            "/api/v2/write?bucket=metrics&precision=ns"
+        );
+        assert_eq!(requests[0].headers["authorization"], "Token secret");
+        assert!(String::from_utf8_lossy(&requests[0].body).starts_with("http_requests_total,"));
+    }
+
+    #[actix_web::test]
+    async fn test_influx_endpoint() {
+        use actix_web::{App, test};
+
+        let app = test::init_service(App::new().service(get_influx_metrics)).await;
+        let request = test::TestRequest::get().uri("/influx").to_request();
+        let response = test::call_service(&app, request).await;
+
+        assert!(response.status().is_success());
+    }
+}
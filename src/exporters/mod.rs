@@ -0,0 +1,276 @@
+//! # Exporters
+//!
+//! Optional exporters pushing metrics to external systems next to the
+//! Prometheus scrape endpoint. Each exporter runs as a background task,
+//! the same way the Prometheus upkeep loop does, and registers a flush
+//! callback so [`flush_exporters`] can push final values before shutdown.
+
+mod graphite;
+mod influx;
+mod otlp;
+mod pushgateway;
+mod remote_write;
+mod statsd;
+
+pub(crate) use graphite::start_graphite;
+pub use graphite::{GraphiteConfig, GraphitePathScheme};
+pub use influx::InfluxConfig;
+pub(crate) use influx::{get_influx_metrics, start_influx};
+pub use otlp::OtlpConfig;
+pub(crate) use otlp::OtlpRecorder;
+pub use pushgateway::PushgatewayConfig;
+pub(crate) use pushgateway::start_pushgateway;
+pub use remote_write::RemoteWriteConfig;
+pub(crate) use remote_write::start_remote_write;
+pub use statsd::StatsdConfig;
+pub(crate) use statsd::StatsdRecorder;
+
+use crate::self_metrics;
+use anyhow::{Result, anyhow};
+use futures_util::future::BoxFuture;
+use log::warn;
+use std::{
+    future::Future,
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+
+/// Pushes the current values of one exporter
+type FlushFn = Arc<dyn Fn() -> BoxFuture<'static, Result<()>> + Send + Sync>;
+
+/// Flush callbacks of all running exporters, with the exporter name
+static FLUSHERS: OnceLock<Mutex<Vec<(&'static str, FlushFn)>>> = OnceLock::new();
+
+/// Timeout of a single export request
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Returns the HTTP client shared by all exporters
+pub(crate) fn http_client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .timeout(EXPORT_TIMEOUT)
+            .build()
+            .unwrap_or_default()
+    })
+}
+
+/// Runs `export` every `interval` on the Tokio runtime and registers it
+/// to be run by [`flush_exporters`]
+///
+/// Failures are logged and the next export is attempted on the following tick.
+pub(crate) fn spawn_periodic<F, Fut>(name: &'static str, interval: Duration, export: F)
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let export = Arc::new(export);
+    let flush = export.clone();
+    let flush: FlushFn = Arc::new(move || Box::pin(flush()));
+    let flushers = FLUSHERS.get_or_init(|| Mutex::new(Vec::new()));
+    if let Ok(mut flushers) = flushers.lock() {
+        flushers.push((name, flush));
+    }
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            if let Err(e) = export().await {
+                warn!("{} export failed: {:#}", name, e);
+                self_metrics::record_export_failure(name);
+            }
+        }
+    });
+}
+
+/// Pushes the current values from every configured exporter
+///
+/// Call this before the process exits so that short-lived jobs don't lose
+/// the values recorded since the last periodic push. Every exporter is
+/// flushed even if an earlier one fails.
+///
+/// # Errors
+///
+/// Returns an error naming every exporter whose push failed
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # async fn run() -> anyhow::Result<()> {
+/// // ... run the batch job ...
+/// metrics_rs_dashboard_actix::flush_exporters().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn flush_exporters() -> Result<()> {
+    let flushers = FLUSHERS
+        .get()
+        .and_then(|flushers| flushers.lock().ok().map(|flushers| flushers.clone()))
+        .unwrap_or_default();
+
+    let mut failures = Vec::new();
+    for (name, flush) in flushers {
+        if let Err(e) = flush().await {
+            failures.push(format!("{}: {:#}", name, e));
+        }
+    }
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Failed to flush exporters: {}",
+            failures.join("; ")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_flush_runs_registered_exporters() {
+        static PUSHES: AtomicUsize = AtomicUsize::new(0);
+        spawn_periodic("Test", Duration::from_secs(3600), || async {
+            PUSHES.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        });
+
+        flush_exporters().await.unwrap();
+        assert_eq!(PUSHES.load(Ordering::SeqCst), 1);
+    }
+}
+
+/// Minimal HTTP server standing in for collectors and gateways in tests
+#[cfg(test)]
+pub(crate) mod test_support {
+    use std::{
+        collections::HashMap,
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// A request received by the [`MockCollector`]
+    #[derive(Debug, Clone)]
+    pub struct CapturedRequest {
+        pub method: String,
+        pub path: String,
+        /// Header names are lowercase
+        pub headers: HashMap<String, String>,
+        pub body: Vec<u8>,
+    }
+
+    /// Accepts HTTP/1.1 requests, records them and answers with a fixed body
+    pub struct MockCollector {
+        addr: SocketAddr,
+        requests: Arc<Mutex<Vec<CapturedRequest>>>,
+    }
+
+    impl MockCollector {
+        /// Starts a collector answering every request with `200 OK`
+        pub async fn start() -> Self {
+            Self::with_statuses(Vec::new()).await
+        }
+
+        /// Starts a collector answering with the given statuses in order,
+        /// then with `200 OK` once they are used up
+        pub async fn with_statuses(statuses: Vec<u16>) -> Self {
+            Self::serve(statuses, String::new()).await
+        }
+
+        /// Starts a collector answering every request with `200 OK` and `body`
+        pub async fn with_body(body: &str) -> Self {
+            Self::serve(Vec::new(), body.to_owned()).await
+        }
+
+        async fn serve(statuses: Vec<u16>, body: String) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let captured = requests.clone();
+
+            tokio::spawn(async move {
+                let mut statuses = statuses.into_iter();
+                while let Ok((mut socket, _)) = listener.accept().await {
+                    let Some(request) = read_request(&mut socket).await else {
+                        continue;
+                    };
+                    captured.lock().unwrap().push(request);
+                    let status = statuses.next().unwrap_or(200);
+                    let response = format!(
+                        "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        status,
+                        body.len(),
+                        body
+                    );
+                    let _ = socket.write_all(response.as_bytes()).await;
+                    let _ = socket.shutdown().await;
+                }
+            });
+
+            Self { addr, requests }
+        }
+
+        /// Returns the URL of `path` on this collector
+        pub fn url(&self, path: &str) -> String {
+            format!("http://{}{}", self.addr, path)
+        }
+
+        /// Returns all requests received so far
+        pub fn requests(&self) -> Vec<CapturedRequest> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    /// Reads a single request with a `Content-Length` delimited body
+    async fn read_request(socket: &mut tokio::net::TcpStream) -> Option<CapturedRequest> {
+        let mut buffer = Vec::new();
+        let mut chunk = [0u8; 4096];
+        let header_end = loop {
+            let read = socket.read(&mut chunk).await.ok()?;
+            if read == 0 {
+                return None;
+            }
+            buffer.extend_from_slice(&chunk[..read]);
+            if let Some(position) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+                break position + 4;
+            }
+        };
+
+        let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+        let mut lines = head.lines();
+        let mut request_line = lines.next()?.split_whitespace();
+        let method = request_line.next()?.to_string();
+        let path = request_line.next()?.to_string();
+        let headers: HashMap<String, String> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_string()))
+            .collect();
+
+        let length: usize = headers
+            .get("content-length")
+            .and_then(|length| length.parse().ok())
+            .unwrap_or(0);
+        let mut body = buffer[header_end..].to_vec();
+        while body.len() < length {
+            let read = socket.read(&mut chunk).await.ok()?;
+            if read == 0 {
+                break;
+            }
+            body.extend_from_slice(&chunk[..read]);
+        }
+
+        Some(CapturedRequest {
+            method,
+            path,
+            headers,
+            body,
+        })
+    }
+}
@@ -0,0 +1,675 @@
+//! # OTLP Exporter
+//!
+//! Pushes counters, gauges and histograms to an OpenTelemetry collector using
+//! OTLP over HTTP with protobuf encoding. The exporter is a recorder of its
+//! own, registered in the fanout next to the Prometheus recorder, so it sees
+//! every metric without parsing the Prometheus exposition.
+//!
+//! All values are exported with cumulative temporality. Histograms use the
+//! buckets configured for the Prometheus recorder, or the default OpenTelemetry
+//! SDK boundaries for histograms without configured buckets.
+
+use super::{http_client, spawn_periodic};
+use crate::UNITS_FOR_METRICS;
+use anyhow::{Result, anyhow};
+use metrics::{Counter, Gauge, Histogram, Key, KeyName, Metadata, Recorder, SharedString, Unit};
+use metrics_exporter_prometheus::Matcher;
+use metrics_util::registry::{AtomicStorage, Registry};
+use prost::Message;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, atomic::Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Bucket boundaries used by OpenTelemetry SDKs when none are configured
+const DEFAULT_BOUNDARIES: &[f64] = &[
+    0.0, 5.0, 10.0, 25.0, 50.0, 75.0, 100.0, 250.0, 500.0, 750.0, 1000.0, 2500.0, 5000.0, 7500.0,
+    10000.0,
+];
+
+/// `AggregationTemporality::AGGREGATION_TEMPORALITY_CUMULATIVE`
+const CUMULATIVE: i32 = 2;
+
+/// Configuration of the OTLP exporter
+///
+/// # Example
+///
+/// ```
+/// use metrics_rs_dashboard_actix::{DashboardInput, OtlpConfig};
+///
+/// let dashboard_input = DashboardInput {
+///     otlp: Some(OtlpConfig {
+///         endpoint: "http://otel-collector:4318/v1/metrics".to_string(),
+///         resource_attributes: vec![("service.name".to_string(), "checkout".to_string())],
+///         ..Default::default()
+///     }),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone)]
+pub struct OtlpConfig {
+    /// Full URL of the collector's metrics endpoint, including `/v1/metrics`
+    pub endpoint: String,
+    /// Interval between two exports
+    pub interval: Duration,
+    /// Extra HTTP headers sent with every export, e.g. for authentication
+    pub headers: Vec<(String, String)>,
+    /// Attributes describing this process, such as `service.name`
+    pub resource_attributes: Vec<(String, String)>,
+}
+
+impl Default for OtlpConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: "http://localhost:4318/v1/metrics".to_string(),
+            interval: Duration::from_secs(60),
+            headers: Vec::new(),
+            resource_attributes: Vec::new(),
+        }
+    }
+}
+
+/// Cumulative state of a histogram, built from the observations drained
+/// from its bucket at every export
+#[derive(Debug, Clone)]
+struct HistogramState {
+    bounds: Vec<f64>,
+    /// Per-bucket counts, one more than there are bounds
+    bucket_counts: Vec<u64>,
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+impl HistogramState {
+    fn new(bounds: Vec<f64>) -> Self {
+        Self {
+            bucket_counts: vec![0; bounds.len() + 1],
+            bounds,
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        let index = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.bucket_counts[index] += 1;
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+}
+
+/// State shared between the recorder and its export task
+struct OtlpState {
+    config: OtlpConfig,
+    buckets: Vec<(Matcher, Vec<f64>)>,
+    registry: Registry<Key, AtomicStorage>,
+    descriptions: Mutex<HashMap<String, String>>,
+    histograms: Mutex<HashMap<Key, HistogramState>>,
+    start_time_unix_nano: u64,
+}
+
+/// Recorder collecting metrics for the OTLP exporter
+pub(crate) struct OtlpRecorder {
+    state: Arc<OtlpState>,
+}
+
+impl OtlpRecorder {
+    /// Creates the recorder without starting the export task
+    fn new(config: OtlpConfig, buckets: &[(Matcher, &[f64])]) -> Self {
+        let state = OtlpState {
+            config,
+            buckets: buckets
+                .iter()
+                .map(|(matcher, bounds)| (matcher.clone(), bounds.to_vec()))
+                .collect(),
+            registry: Registry::atomic(),
+            descriptions: Mutex::new(HashMap::new()),
+            histograms: Mutex::new(HashMap::new()),
+            start_time_unix_nano: unix_nanos(),
+        };
+        Self {
+            state: Arc::new(state),
+        }
+    }
+
+    /// Creates the recorder and starts pushing to the configured endpoint
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub(crate) fn install(config: OtlpConfig, buckets: &[(Matcher, &[f64])]) -> Self {
+        let recorder = Self::new(config, buckets);
+        let state = recorder.state.clone();
+        spawn_periodic("OTLP", state.config.interval, move || {
+            let state = state.clone();
+            async move { state.export().await }
+        });
+        recorder
+    }
+
+    fn describe(&self, key: KeyName, description: SharedString) {
+        if let Ok(mut descriptions) = self.state.descriptions.lock() {
+            descriptions.insert(key.as_str().to_owned(), description.into_owned());
+        }
+    }
+}
+
+impl Recorder for OtlpRecorder {
+    fn describe_counter(&self, key: KeyName, _unit: Option<Unit>, description: SharedString) {
+        self.describe(key, description);
+    }
+
+    fn describe_gauge(&self, key: KeyName, _unit: Option<Unit>, description: SharedString) {
+        self.describe(key, description);
+    }
+
+    fn describe_histogram(&self, key: KeyName, _unit: Option<Unit>, description: SharedString) {
+        self.describe(key, description);
+    }
+
+    fn register_counter(&self, key: &Key, _metadata: &Metadata<'_>) -> Counter {
+        self.state
+            .registry
+            .get_or_create_counter(key, |counter| Counter::from_arc(counter.clone()))
+    }
+
+    fn register_gauge(&self, key: &Key, _metadata: &Metadata<'_>) -> Gauge {
+        self.state
+            .registry
+            .get_or_create_gauge(key, |gauge| Gauge::from_arc(gauge.clone()))
+    }
+
+    fn register_histogram(&self, key: &Key, _metadata: &Metadata<'_>) -> Histogram {
+        self.state
+            .registry
+            .get_or_create_histogram(key, |histogram| Histogram::from_arc(histogram.clone()))
+    }
+}
+
+impl OtlpState {
+    /// Sends the current values of all metrics to the collector
+    async fn export(&self) -> Result<()> {
+        let request = self.build_request();
+        let mut builder = http_client()
+            .post(&self.config.endpoint)
+            .header("content-type", "application/x-protobuf")
+            .body(request.encode_to_vec());
+        for (name, value) in &self.config.headers {
+            builder = builder.header(name, value);
+        }
+
+        let response = builder.send().await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("collector responded with {}", response.status()));
+        }
+        Ok(())
+    }
+
+    /// Builds an export request from the current values of all metrics
+    fn build_request(&self) -> proto::ExportMetricsServiceRequest {
+        let now = unix_nanos();
+        let start = self.start_time_unix_nano;
+        let mut metrics: BTreeMap<String, proto::Metric> = BTreeMap::new();
+
+        self.registry.visit_counters(|key, counter| {
+            let point = number_point(key, start, now, proto::Value::AsInt(counter_value(counter)));
+            self.metric_entry(&mut metrics, key, || {
+                proto::Data::Sum(proto::Sum {
+                    data_points: Vec::new(),
+                    aggregation_temporality: CUMULATIVE,
+                    is_monotonic: true,
+                })
+            });
+            if let Some(proto::Data::Sum(sum)) = metrics
+                .get_mut(key.name())
+                .and_then(|metric| metric.data.as_mut())
+            {
+                sum.data_points.push(point);
+            }
+        });
+
+        self.registry.visit_gauges(|key, gauge| {
+            let value = f64::from_bits(gauge.load(Ordering::Relaxed));
+            let point = number_point(key, start, now, proto::Value::AsDouble(value));
+            self.metric_entry(&mut metrics, key, || {
+                proto::Data::Gauge(proto::Gauge {
+                    data_points: Vec::new(),
+                })
+            });
+            if let Some(proto::Data::Gauge(gauge)) = metrics
+                .get_mut(key.name())
+                .and_then(|metric| metric.data.as_mut())
+            {
+                gauge.data_points.push(point);
+            }
+        });
+
+        if let Ok(mut histograms) = self.histograms.lock() {
+            self.registry.visit_histograms(|key, bucket| {
+                let state = histograms
+                    .entry(key.clone())
+                    .or_insert_with(|| HistogramState::new(self.bounds_for(key.name())));
+                bucket.clear_with(|values| values.iter().for_each(|v| state.observe(*v)));
+
+                let point = proto::HistogramDataPoint {
+                    attributes: attributes(key),
+                    start_time_unix_nano: start,
+                    time_unix_nano: now,
+                    count: state.count,
+                    sum: Some(state.sum),
+                    bucket_counts: state.bucket_counts.clone(),
+                    explicit_bounds: state.bounds.clone(),
+                    min: (state.count > 0).then_some(state.min),
+                    max: (state.count > 0).then_some(state.max),
+                };
+                self.metric_entry(&mut metrics, key, || {
+                    proto::Data::Histogram(proto::Histogram {
+                        data_points: Vec::new(),
+                        aggregation_temporality: CUMULATIVE,
+                    })
+                });
+                if let Some(proto::Data::Histogram(histogram)) = metrics
+                    .get_mut(key.name())
+                    .and_then(|metric| metric.data.as_mut())
+                {
+                    histogram.data_points.push(point);
+                }
+            });
+        }
+
+        proto::ExportMetricsServiceRequest {
+            resource_metrics: vec![proto::ResourceMetrics {
+                resource: Some(proto::Resource {
+                    attributes: self
+                        .config
+                        .resource_attributes
+                        .iter()
+                        .map(|(k, v)| key_value(k, v))
+                        .collect(),
+                }),
+                scope_metrics: vec![proto::ScopeMetrics {
+                    scope: Some(proto::InstrumentationScope {
+                        name: env!("CARGO_PKG_NAME").to_string(),
+                        version: env!("CARGO_PKG_VERSION").to_string(),
+                    }),
+                    metrics: metrics.into_values().collect(),
+                }],
+            }],
+        }
+    }
+
+    /// Inserts the metric of `key` with its description and unit if it
+    /// doesn't exist yet
+    fn metric_entry(
+        &self,
+        metrics: &mut BTreeMap<String, proto::Metric>,
+        key: &Key,
+        data: impl FnOnce() -> proto::Data,
+    ) {
+        let name = key.name();
+        if metrics.contains_key(name) {
+            return;
+        }
+        let description = self
+            .descriptions
+            .lock()
+            .ok()
+            .and_then(|descriptions| descriptions.get(name).cloned())
+            .unwrap_or_default();
+        let unit = UNITS_FOR_METRICS
+            .get()
+            .and_then(|units| units.lock().ok()?.get(name).cloned())
+            .map(|unit| ucum_unit(&unit).to_string())
+            .unwrap_or_default();
+
+        metrics.insert(
+            name.to_owned(),
+            proto::Metric {
+                name: name.to_owned(),
+                description,
+                unit,
+                data: Some(data()),
+            },
+        );
+    }
+
+    /// Returns the bucket boundaries for a histogram
+    fn bounds_for(&self, name: &str) -> Vec<f64> {
+        self.buckets
+            .iter()
+            .find(|(matcher, _)| matcher.matches(name))
+            .map(|(_, bounds)| bounds.clone())
+            .unwrap_or_else(|| DEFAULT_BOUNDARIES.to_vec())
+    }
+}
+
+/// Reads a counter as the signed integer OTLP expects
+fn counter_value(counter: &std::sync::atomic::AtomicU64) -> i64 {
+    i64::try_from(counter.load(Ordering::Relaxed)).unwrap_or(i64::MAX)
+}
+
+/// Builds a number data point for a counter or gauge
+fn number_point(key: &Key, start: u64, now: u64, value: proto::Value) -> proto::NumberDataPoint {
+    proto::NumberDataPoint {
+        attributes: attributes(key),
+        start_time_unix_nano: start,
+        time_unix_nano: now,
+        value: Some(value),
+    }
+}
+
+/// Converts the labels of a key into OTLP attributes
+fn attributes(key: &Key) -> Vec<proto::KeyValue> {
+    key.labels()
+        .map(|label| key_value(label.key(), label.value()))
+        .collect()
+}
+
+fn key_value(key: &str, value: &str) -> proto::KeyValue {
+    proto::KeyValue {
+        key: key.to_owned(),
+        value: Some(proto::AnyValue {
+            value: Some(proto::any_value::Value::StringValue(value.to_owned())),
+        }),
+    }
+}
+
+/// Maps a `metrics` unit name to its UCUM code used by OpenTelemetry
+fn ucum_unit(unit: &str) -> &str {
+    match unit {
+        "count" => "1",
+        "percent" => "%",
+        "seconds" => "s",
+        "milliseconds" => "ms",
+        "microseconds" => "us",
+        "nanoseconds" => "ns",
+        "bytes" => "By",
+        "kibibytes" => "KiBy",
+        "mebibytes" => "MiBy",
+        "gibibytes" => "GiBy",
+        "tebibytes" => "TiBy",
+        "bits_per_second" => "bit/s",
+        "kilobits_per_second" => "kbit/s",
+        "megabits_per_second" => "Mbit/s",
+        "gigabits_per_second" => "Gbit/s",
+        "terabits_per_second" => "Tbit/s",
+        "count_per_second" => "1/s",
+        other => other,
+    }
+}
+
+fn unix_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+/// Subset of the OpenTelemetry protocol messages used by the exporter
+///
+/// Field numbers follow `opentelemetry/proto/collector/metrics/v1` and
+/// `opentelemetry/proto/metrics/v1`. Fields the exporter never sets are omitted.
+mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ExportMetricsServiceRequest {
+        #[prost(message, repeated, tag = "1")]
+        pub resource_metrics: Vec<ResourceMetrics>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ResourceMetrics {
+        #[prost(message, optional, tag = "1")]
+        pub resource: Option<Resource>,
+        #[prost(message, repeated, tag = "2")]
+        pub scope_metrics: Vec<ScopeMetrics>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Resource {
+        #[prost(message, repeated, tag = "1")]
+        pub attributes: Vec<KeyValue>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ScopeMetrics {
+        #[prost(message, optional, tag = "1")]
+        pub scope: Option<InstrumentationScope>,
+        #[prost(message, repeated, tag = "2")]
+        pub metrics: Vec<Metric>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct InstrumentationScope {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(string, tag = "2")]
+        pub version: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Metric {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(string, tag = "2")]
+        pub description: String,
+        #[prost(string, tag = "3")]
+        pub unit: String,
+        #[prost(oneof = "Data", tags = "5, 7, 9")]
+        pub data: Option<Data>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Data {
+        #[prost(message, tag = "5")]
+        Gauge(Gauge),
+        #[prost(message, tag = "7")]
+        Sum(Sum),
+        #[prost(message, tag = "9")]
+        Histogram(Histogram),
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Gauge {
+        #[prost(message, repeated, tag = "1")]
+        pub data_points: Vec<NumberDataPoint>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Sum {
+        #[prost(message, repeated, tag = "1")]
+        pub data_points: Vec<NumberDataPoint>,
+        #[prost(int32, tag = "2")]
+        pub aggregation_temporality: i32,
+        #[prost(bool, tag = "3")]
+        pub is_monotonic: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Histogram {
+        #[prost(message, repeated, tag = "1")]
+        pub data_points: Vec<HistogramDataPoint>,
+        #[prost(int32, tag = "2")]
+        pub aggregation_temporality: i32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct NumberDataPoint {
+        #[prost(message, repeated, tag = "7")]
+        pub attributes: Vec<KeyValue>,
+        #[prost(fixed64, tag = "2")]
+        pub start_time_unix_nano: u64,
+        #[prost(fixed64, tag = "3")]
+        pub time_unix_nano: u64,
+        #[prost(oneof = "Value", tags = "4, 6")]
+        pub value: Option<Value>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Value {
+        #[prost(double, tag = "4")]
+        AsDouble(f64),
+        #[prost(sfixed64, tag = "6")]
+        AsInt(i64),
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct HistogramDataPoint {
+        #[prost(message, repeated, tag = "9")]
+        pub attributes: Vec<KeyValue>,
+        #[prost(fixed64, tag = "2")]
+        pub start_time_unix_nano: u64,
+        #[prost(fixed64, tag = "3")]
+        pub time_unix_nano: u64,
+        #[prost(fixed64, tag = "4")]
+        pub count: u64,
+        #[prost(double, optional, tag = "5")]
+        pub sum: Option<f64>,
+        #[prost(fixed64, repeated, tag = "6")]
+        pub bucket_counts: Vec<u64>,
+        #[prost(double, repeated, tag = "7")]
+        pub explicit_bounds: Vec<f64>,
+        #[prost(double, optional, tag = "11")]
+        pub min: Option<f64>,
+        #[prost(double, optional, tag = "12")]
+        pub max: Option<f64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct KeyValue {
+        #[prost(string, tag = "1")]
+        pub key: String,
+        #[prost(message, optional, tag = "2")]
+        pub value: Option<AnyValue>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct AnyValue {
+        #[prost(oneof = "any_value::Value", tags = "1")]
+        pub value: Option<any_value::Value>,
+    }
+
+    pub mod any_value {
+        #[derive(Clone, PartialEq, prost::Oneof)]
+        pub enum Value {
+            #[prost(string, tag = "1")]
+            StringValue(String),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporters::test_support::MockCollector;
+
+    #[test]
+    fn test_histogram_state_buckets() {
+        let mut state = HistogramState::new(vec![1.0, 5.0]);
+        for value in [0.5, 1.0, 3.0, 10.0] {
+            state.observe(value);
+        }
+
+        assert_eq!(state.bucket_counts, vec![2, 1, 1]);
+        assert_eq!(state.count, 4);
+        assert_eq!(state.sum, 14.5);
+        assert_eq!((state.min, state.max), (0.5, 10.0));
+    }
+
+    #[tokio::test]
+    async fn test_export_to_mock_collector() {
+        let collector = MockCollector::start().await;
+        let recorder = OtlpRecorder::new(
+            OtlpConfig {
+                endpoint: collector.url("/v1/metrics"),
+                headers: vec![("x-api-key".to_string(), "secret".to_string())],
+                resource_attributes: vec![("service.name".to_string(), "test".to_string())],
+                ..Default::default()
+            },
+            &[(Matcher::Full("otlp_latency".to_string()), &[10.0, 100.0])],
+        );
+
+        metrics::with_local_recorder(&recorder, || {
+            metrics::describe_counter!("otlp_requests_total", "Handled requests");
+            metrics::counter!("otlp_requests_total", "endpoint" => "/a").increment(3);
+            metrics::gauge!("otlp_queue_depth").set(7.5);
+            metrics::histogram!("otlp_latency").record(42.0);
+            metrics::histogram!("otlp_latency").record(420.0);
+        });
+        recorder.state.export().await.unwrap();
+
+        let requests = collector.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].path, "/v1/metrics");
+        assert_eq!(
+            requests[0].headers["content-type"],
+            "application/x-protobuf"
+        );
+        assert_eq!(requests[0].headers["x-api-key"], "secret");
+
+        let request =
+            proto::ExportMetricsServiceRequest::decode(requests[0].body.as_slice()).unwrap();
+        let resource = &request.resource_metrics[0];
+        assert_eq!(
+            resource.resource.as_ref().unwrap().attributes[0].key,
+            "service.name"
+        );
+        let metrics = &resource.scope_metrics[0].metrics;
+        let metric = |name: &str| metrics.iter().find(|m| m.name == name).unwrap();
+
+        let requests_total = metric("otlp_requests_total");
+        assert_eq!(requests_total.description, "Handled requests");
+        let Some(proto::Data::Sum(sum)) = &requests_total.data else {
+            panic!("counter exported as {:?}", requests_total.data);
+        };
+        assert!(sum.is_monotonic);
+        assert_eq!(sum.data_points[0].value, Some(proto::Value::AsInt(3)));
+        assert_eq!(sum.data_points[0].attributes[0].key, "endpoint");
+
+        let Some(proto::Data::Gauge(gauge)) = &metric("otlp_queue_depth").data else {
+            panic!("gauge not exported as gauge");
+        };
+        assert_eq!(
+            gauge.data_points[0].value,
+            Some(proto::Value::AsDouble(7.5))
+        );
+
+        let Some(proto::Data::Histogram(histogram)) = &metric("otlp_latency").data else {
+            panic!("histogram not exported as histogram");
+        };
+        let point = &histogram.data_points[0];
+        assert_eq!(point.explicit_bounds, vec![10.0, 100.0]);
+        assert_eq!(point.bucket_counts, vec![0, 1, 1]);
+        assert_eq!(point.count, 2);
+    }
+
+    #[tokio::test]
+    async fn test_export_reports_collector_errors() {
+        let collector = MockCollector::with_statuses(vec![503]).await;
+        let recorder = OtlpRecorder::new(
+            OtlpConfig {
+                endpoint: collector.url("/v1/metrics"),
+                ..Default::default()
+            },
+            &[],
+        );
+
+        assert!(recorder.state.export().await.is_err());
+    }
+
+    #[test]
+    fn test_ucum_units() {
+        assert_eq!(ucum_unit("milliseconds"), "ms");
+        assert_eq!(ucum_unit("bytes"), "By");
+        assert_eq!(ucum_unit("count"), "1");
+    }
+}
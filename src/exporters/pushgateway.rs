@@ -0,0 +1,176 @@
+//! # Pushgateway Exporter
+//!
+//! Pushes the rendered Prometheus exposition to a Pushgateway-compatible
+//! endpoint, for batch jobs that exit before Prometheus gets to scrape them.
+//! Values are pushed on an interval and once more by
+//! [`flush_exporters`](crate::flush_exporters) before shutdown.
+
+use super::{http_client, spawn_periodic};
+use crate::render_prometheus;
+use anyhow::{Result, anyhow};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use std::time::Duration;
+
+/// Configuration of the Pushgateway exporter
+///
+/// Metrics are pushed to `{url}/metrics/job/{job}` followed by the instance
+/// and any additional grouping labels. Each push replaces all metrics of
+/// the group.
+///
+/// # Example
+///
+/// ```
+/// use metrics_rs_dashboard_actix::{DashboardInput, PushgatewayConfig};
+///
+/// let dashboard_input = DashboardInput {
+///     pushgateway: Some(PushgatewayConfig {
+///         url: "http://pushgateway:9091".to_string(),
+///         job: "nightly_import".to_string(),
+///         instance: Some("worker-1".to_string()),
+///         ..Default::default()
+///     }),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone)]
+pub struct PushgatewayConfig {
+    /// Base URL of the Pushgateway, without the `/metrics/job/...` path
+    pub url: String,
+    /// Value of the `job` grouping key
+    pub job: String,
+    /// Value of the `instance` grouping key, omitted when `None`
+    pub instance: Option<String>,
+    /// Additional grouping labels
+    pub grouping: Vec<(String, String)>,
+    /// Interval between two pushes
+    pub interval: Duration,
+    /// Basic auth credentials as `(username, password)`
+    pub basic_auth: Option<(String, String)>,
+}
+
+impl Default for PushgatewayConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://localhost:9091".to_string(),
+            job: env!("CARGO_PKG_NAME").to_string(),
+            instance: None,
+            grouping: Vec::new(),
+            interval: Duration::from_secs(10),
+            basic_auth: None,
+        }
+    }
+}
+
+/// Starts pushing to the Pushgateway on the configured interval
+///
+/// Must be called from within a Tokio runtime.
+pub(crate) fn start_pushgateway(config: PushgatewayConfig) {
+    let url = group_url(&config);
+    let interval = config.interval;
+    spawn_periodic("Pushgateway", interval, move || {
+        push(url.clone(), config.basic_auth.clone())
+    });
+}
+
+/// Sends the current exposition to the group URL
+async fn push(url: String, basic_auth: Option<(String, String)>) -> Result<()> {
+    let mut request = http_client()
+        .put(&url)
+        .header("content-type", "text/plain; version=0.0.4")
+        .body(render_prometheus());
+    if let Some((username, password)) = basic_auth {
+        request = request.basic_auth(username, Some(password));
+    }
+
+    let response = request.send().await?;
+    if !response.status().is_success() {
+        return Err(anyhow!("Pushgateway responded with {}", response.status()));
+    }
+    Ok(())
+}
+
+/// Builds the URL of the grouping key of this process
+fn group_url(config: &PushgatewayConfig) -> String {
+    let mut url = format!(
+        "{}/metrics/{}",
+        config.url.trim_end_matches('/'),
+        grouping_segment("job", &config.job)
+    );
+    let instance = config.instance.iter().map(|value| ("instance", value));
+    let grouping = config.grouping.iter().map(|(k, v)| (k.as_str(), v));
+    for (label, value) in instance.chain(grouping) {
+        url.push('/');
+        url.push_str(&grouping_segment(label, value));
+    }
+    url
+}
+
+/// Formats a `label/value` path segment pair
+///
+/// Values that aren't plain URL path characters are base64 encoded using
+/// the `label@base64` form understood by the Pushgateway.
+fn grouping_segment(label: &str, value: &str) -> String {
+    let plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '~'));
+    if plain {
+        format!("{}/{}", label, value)
+    } else if value.is_empty() {
+        format!("{}@base64/=", label)
+    } else {
+        format!("{}@base64/{}", label, URL_SAFE_NO_PAD.encode(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporters::test_support::MockCollector;
+
+    #[test]
+    fn test_group_url() {
+        let config = PushgatewayConfig {
+            url: "http://gateway:9091/".to_string(),
+            job: "import".to_string(),
+            instance: Some("worker-1".to_string()),
+            grouping: vec![
+                ("path".to_string(), "/var/data".to_string()),
+                ("shard".to_string(), String::new()),
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            group_url(&config),
+            "http://gateway:9091/metrics/job/import/instance/worker-1/path@base64/L3Zhci9kYXRh/shard@base64/="
+        );
+    }
+
+    #[tokio::test]
+    async fn test_push_to_mock_gateway() {
+        let gateway = MockCollector::start().await;
+        let url = format!("{}/metrics/job/test", gateway.url(""));
+
+        push(url, Some(("user".to_string(), "secret".to_string())))
+            .await
+            .unwrap();
+
+        let requests = gateway.requests();
+        assert_eq!(requests[0].method, "PUT");
+        assert_eq!(requests[0].path, "/metrics/job/test");
+        assert_eq!(
+            requests[0].headers["authorization"],
+            "Basic dXNlcjpzZWNyZXQ="
+        );
+        assert!(requests[0].headers["content-type"].starts_with("text/plain"));
+    }
+
+    #[tokio::test]
+    async fn test_push_reports_gateway_errors() {
+        let gateway = MockCollector::with_statuses(vec![400]).await;
+        let url = format!("{}/metrics/job/test", gateway.url(""));
+
+        assert!(push(url, None).await.is_err());
+    }
+}
@@ -0,0 +1,381 @@
+//! # Prometheus Remote-Write Exporter
+//!
+//! Sends snapshots to a Prometheus remote-write endpoint for hosts that
+//! Prometheus cannot scrape. Every interval the current snapshot is encoded
+//! as a snappy-compressed protobuf `WriteRequest` and appended to a bounded
+//! queue. Queued requests are sent oldest first; retryable failures are
+//! retried with exponential backoff and stay queued for the next interval,
+//! dropping the oldest request once the queue is full.
+
+use super::{http_client, spawn_periodic};
+use crate::snapshot::{MetricType, Snapshot};
+use anyhow::{Result, anyhow};
+use log::warn;
+use prost::Message;
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::Mutex;
+
+/// Configuration of the remote-write exporter
+///
+/// # Example
+///
+/// ```
+/// use metrics_rs_dashboard_actix::{DashboardInput, RemoteWriteConfig};
+///
+/// let dashboard_input = DashboardInput {
+///     remote_write: Some(RemoteWriteConfig {
+///         url: "https://prometheus.example.com/api/v1/write".to_string(),
+///         basic_auth: Some(("writer".to_string(), "secret".to_string())),
+///         ..Default::default()
+///     }),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone)]
+pub struct RemoteWriteConfig {
+    /// URL of the remote-write endpoint
+    pub url: String,
+    /// Interval between two snapshots
+    pub interval: Duration,
+    /// Extra HTTP headers sent with every request
+    pub headers: Vec<(String, String)>,
+    /// Basic auth credentials as `(username, password)`
+    pub basic_auth: Option<(String, String)>,
+    /// Maximum number of requests waiting to be sent; the oldest is dropped
+    /// when a new snapshot doesn't fit
+    pub queue_capacity: usize,
+    /// Number of retries of a failed request before waiting for the next interval
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled after every attempt
+    pub min_backoff: Duration,
+    /// Upper bound of the retry delay
+    pub max_backoff: Duration,
+}
+
+impl Default for RemoteWriteConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://localhost:9090/api/v1/write".to_string(),
+            interval: Duration::from_secs(15),
+            headers: Vec::new(),
+            basic_auth: None,
+            queue_capacity: 100,
+            max_retries: 3,
+            min_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Outcome of a failed send
+enum SendError {
+    /// Network errors, `429` and `5xx`; the request may succeed later
+    Retryable(anyhow::Error),
+    /// Other `4xx` responses; sending the request again won't help
+    Rejected(anyhow::Error),
+}
+
+/// Remote-write sender with its queue of compressed requests
+struct RemoteWriter {
+    config: RemoteWriteConfig,
+    queue: Mutex<VecDeque<Vec<u8>>>,
+}
+
+/// Starts the remote-write background task
+///
+/// Must be called from within a Tokio runtime.
+pub(crate) fn start_remote_write(config: RemoteWriteConfig) {
+    let interval = config.interval;
+    let writer = Arc::new(RemoteWriter::new(config));
+    spawn_periodic("Remote-write", interval, move || {
+        let writer = writer.clone();
+        async move { writer.tick(Snapshot::capture()).await }
+    });
+}
+
+impl RemoteWriter {
+    fn new(config: RemoteWriteConfig) -> Self {
+        Self {
+            config,
+            queue: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Queues a snapshot and sends everything queued
+    async fn tick(&self, snapshot: Snapshot) -> Result<()> {
+        let mut queue = self.queue.lock().await;
+        if queue.len() >= self.config.queue_capacity.max(1) {
+            warn!("Remote-write queue is full, dropping the oldest request");
+            queue.pop_front();
+        }
+        queue.push_back(compress(&write_request(&snapshot, unix_millis())));
+
+        while let Some(body) = queue.front() {
+            match self.send_with_retries(body).await {
+                Ok(()) => {}
+                Err(SendError::Rejected(e)) => warn!("Remote-write request dropped: {:#}", e),
+                Err(SendError::Retryable(e)) => {
+                    return Err(e.context(format!("{} request(s) queued", queue.len())));
+                }
+            }
+            queue.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Sends a request, retrying retryable failures with exponential backoff
+    async fn send_with_retries(&self, body: &[u8]) -> Result<(), SendError> {
+        let mut backoff = self.config.min_backoff;
+        let mut attempt = 0;
+        loop {
+            match self.send(body).await {
+                Err(SendError::Retryable(_)) if attempt < self.config.max_retries => {
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(self.config.max_backoff);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn send(&self, body: &[u8]) -> Result<(), SendError> {
+        let mut request = http_client()
+            .post(&self.config.url)
+            .header("content-type", "application/x-protobuf")
+            .header("content-encoding", "snappy")
+            .header("x-prometheus-remote-write-version", "0.1.0")
+            .body(body.to_vec());
+        for (name, value) in &self.config.headers {
+            request = request.header(name, value);
+        }
+        if let Some((username, password)) = &self.config.basic_auth {
+            request = request.basic_auth(username, Some(password));
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| SendError::Retryable(e.into()))?;
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else if status.is_server_error() || status.as_u16() == 429 {
+            Err(SendError::Retryable(anyhow!(
+                "endpoint responded with {}",
+                status
+            )))
+        } else {
+            Err(SendError::Rejected(anyhow!(
+                "endpoint responded with {}",
+                status
+            )))
+        }
+    }
+}
+
+/// Converts a snapshot into a remote-write request
+///
+/// Histograms and summaries are split into the same series Prometheus
+/// creates when scraping them (`_bucket`, `_sum`, `_count`).
+fn write_request(snapshot: &Snapshot, timestamp: i64) -> proto::WriteRequest {
+    let mut request = proto::WriteRequest::default();
+
+    for (name, family) in &snapshot.families {
+        request.metadata.push(proto::MetricMetadata {
+            r#type: match family.metric_type {
+                MetricType::Counter => 1,
+                MetricType::Gauge => 2,
+                MetricType::Histogram => 3,
+                MetricType::Summary => 5,
+                MetricType::Untyped => 0,
+            },
+            metric_family_name: name.clone(),
+            help: family.help.clone(),
+        });
+    }
+
+    for sample in snapshot.samples() {
+        let mut labels: Vec<proto::Label> = sample
+            .labels
+            .into_iter()
+            .map(|(name, value)| proto::Label { name, value })
+            .collect();
+        labels.push(proto::Label {
+            name: "__name__".to_owned(),
+            value: sample.name,
+        });
+        labels.sort_by(|a, b| a.name.cmp(&b.name));
+
+        request.timeseries.push(proto::TimeSeries {
+            labels,
+            samples: vec![proto::Sample {
+                value: sample.value,
+                timestamp,
+            }],
+        });
+    }
+
+    request
+}
+
+/// Encodes and snappy-compresses a request using the block format
+/// required by the remote-write protocol
+fn compress(request: &proto::WriteRequest) -> Vec<u8> {
+    snap::raw::Encoder::new()
+        .compress_vec(&request.encode_to_vec())
+        .unwrap_or_default()
+}
+
+fn unix_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+/// Messages of the Prometheus remote-write 1.0 protocol (`prometheus/prompb`)
+mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct WriteRequest {
+        #[prost(message, repeated, tag = "1")]
+        pub timeseries: Vec<TimeSeries>,
+        #[prost(message, repeated, tag = "3")]
+        pub metadata: Vec<MetricMetadata>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TimeSeries {
+        #[prost(message, repeated, tag = "1")]
+        pub labels: Vec<Label>,
+        #[prost(message, repeated, tag = "2")]
+        pub samples: Vec<Sample>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Label {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(string, tag = "2")]
+        pub value: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Sample {
+        #[prost(double, tag = "1")]
+        pub value: f64,
+        /// Milliseconds since the Unix epoch
+        #[prost(int64, tag = "2")]
+        pub timestamp: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct MetricMetadata {
+        /// `MetricType` enum: counter 1, gauge 2, histogram 3, summary 5
+        #[prost(int32, tag = "1")]
+        pub r#type: i32,
+        #[prost(string, tag = "2")]
+        pub metric_family_name: String,
+        #[prost(string, tag = "4")]
+        pub help: String,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporters::test_support::MockCollector;
+
+    const EXPOSITION: &str = r#"# HELP requests_total Total requests
+# TYPE requests_total counter
+requests_total{endpoint="/a"} 3
+
+# TYPE latency histogram
+latency_bucket{le="0.1"} 1
+latency_bucket{le="+Inf"} 2
+latency_sum 0.3
+latency_count 2
+"#;
+
+    fn test_config(url: String) -> RemoteWriteConfig {
+        RemoteWriteConfig {
+            url,
+            queue_capacity: 2,
+            max_retries: 1,
+            min_backoff: Duration::from_millis(1),
+            ..Default::default()
+        }
+    }
+
+    fn decode(body: &[u8]) -> proto::WriteRequest {
+        let bytes = snap::raw::Decoder::new().decompress_vec(body).unwrap();
+        proto::WriteRequest::decode(bytes.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn test_write_request_series() {
+        let request = write_request(&Snapshot::parse(EXPOSITION), 1000);
+
+        let names: Vec<(&str, &str)> = request
+            .timeseries
+            .iter()
+            .map(|series| {
+                (
+                    series.labels[0].name.as_str(),
+                    series.labels[0].value.as_str(),
+                )
+            })
+            .collect();
+        assert!(names.contains(&("__name__", "latency_bucket")));
+        assert!(names.contains(&("__name__", "latency_count")));
+
+        let requests = request
+            .timeseries
+            .iter()
+            .find(|series| series.labels[0].value == "requests_total")
+            .unwrap();
+        assert_eq!(requests.labels[1].name, "endpoint");
+        assert_eq!(requests.samples[0].value, 3.0);
+        assert_eq!(requests.samples[0].timestamp, 1000);
+        assert_eq!(request.metadata.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_retries_until_accepted() {
+        let endpoint = MockCollector::with_statuses(vec![503]).await;
+        let writer = RemoteWriter::new(test_config(endpoint.url("/api/v1/write")));
+
+        writer.tick(Snapshot::parse(EXPOSITION)).await.unwrap();
+
+        let requests = endpoint.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].headers["content-encoding"], "snappy");
+        assert!(!decode(&requests[1].body).timeseries.is_empty());
+        assert!(writer.queue.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_queue_is_bounded() {
+        let endpoint = MockCollector::with_statuses(vec![500; 20]).await;
+        let writer = RemoteWriter::new(test_config(endpoint.url("/api/v1/write")));
+
+        for _ in 0..3 {
+            assert!(writer.tick(Snapshot::parse(EXPOSITION)).await.is_err());
+        }
+        assert_eq!(writer.queue.lock().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_requests_are_dropped() {
+        let endpoint = MockCollector::with_statuses(vec![400]).await;
+        let writer = RemoteWriter::new(test_config(endpoint.url("/api/v1/write")));
+
+        writer.tick(Snapshot::parse(EXPOSITION)).await.unwrap();
+        assert_eq!(endpoint.requests().len(), 1);
+        assert!(writer.queue.lock().await.is_empty());
+    }
+}
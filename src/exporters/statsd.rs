@@ -0,0 +1,398 @@
+//! # StatsD Exporter
+//!
+//! Sends metrics over UDP in the DogStatsD line format, which plain StatsD
+//! servers such as Telegraf and `statsd_exporter` understand as well.
+//! Counters are sent as `c`, gauges as `g` and histograms as `h` lines, with
+//! labels as `#key:value` tags. Lines are batched into packets that fit the
+//! configured size and flushed when full and on a short interval.
+
+use super::spawn_periodic;
+use anyhow::{Result, anyhow};
+use log::debug;
+use metrics::{
+    Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder,
+    SharedString, Unit,
+};
+use std::{
+    collections::HashMap,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+/// Configuration of the StatsD exporter
+///
+/// # Example
+///
+/// ```
+/// use metrics_rs_dashboard_actix::{DashboardInput, StatsdConfig};
+///
+/// let dashboard_input = DashboardInput {
+///     statsd: Some(StatsdConfig {
+///         address: "127.0.0.1:8125".to_string(),
+///         prefix: Some("checkout".to_string()),
+///         ..Default::default()
+///     }),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone)]
+pub struct StatsdConfig {
+    /// `host:port` of the StatsD or DogStatsD agent
+    pub address: String,
+    /// Prefix prepended to every metric name, separated by a dot
+    pub prefix: Option<String>,
+    /// Tags added to every line, e.g. `("env", "prod")`
+    pub constant_tags: Vec<(String, String)>,
+    /// Maximum size of a UDP packet; the default fits an Ethernet MTU
+    pub max_packet_size: usize,
+    /// Interval after which partially filled packets are sent
+    pub flush_interval: Duration,
+}
+
+impl Default for StatsdConfig {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1:8125".to_string(),
+            prefix: None,
+            constant_tags: Vec::new(),
+            max_packet_size: 1432,
+            flush_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// UDP socket with the packet currently being filled
+struct StatsdSink {
+    socket: UdpSocket,
+    max_packet_size: usize,
+    buffer: Mutex<String>,
+}
+
+impl StatsdSink {
+    /// Appends a line, sending the current packet first if the line doesn't fit
+    fn push(&self, line: &str) {
+        let Ok(mut buffer) = self.buffer.lock() else {
+            return;
+        };
+        if !buffer.is_empty() && buffer.len() + 1 + line.len() > self.max_packet_size {
+            self.send(&buffer);
+            buffer.clear();
+        }
+        if !buffer.is_empty() {
+            buffer.push('\n');
+        }
+        buffer.push_str(line);
+    }
+
+    /// Sends the partially filled packet
+    fn flush(&self) {
+        if let Ok(mut buffer) = self.buffer.lock()
+            && !buffer.is_empty()
+        {
+            self.send(&buffer);
+            buffer.clear();
+        }
+    }
+
+    fn send(&self, packet: &str) {
+        // UDP is fire and forget; a missing agent must not affect the application
+        if let Err(e) = self.socket.send(packet.as_bytes()) {
+            debug!("Failed to send StatsD packet: {}", e);
+        }
+    }
+}
+
+/// Handle of a single series, formatting lines for the sink
+struct StatsdHandle {
+    sink: Arc<StatsdSink>,
+    name: String,
+    tags: String,
+    /// Last absolute counter value or current gauge value as `f64` bits
+    value: AtomicU64,
+}
+
+impl StatsdHandle {
+    fn send(&self, value: impl std::fmt::Display, kind: &str) {
+        self.sink
+            .push(&format!("{}:{}|{}{}", self.name, value, kind, self.tags));
+    }
+
+    /// Applies `update` to the gauge value and sends the result
+    fn update_gauge(&self, update: impl Fn(f64) -> f64) {
+        let previous = self
+            .value
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |bits| {
+                Some(update(f64::from_bits(bits)).to_bits())
+            })
+            .unwrap_or_default();
+        self.send(update(f64::from_bits(previous)), "g");
+    }
+}
+
+impl CounterFn for StatsdHandle {
+    fn increment(&self, value: u64) {
+        self.value.fetch_add(value, Ordering::AcqRel);
+        self.send(value, "c");
+    }
+
+    fn absolute(&self, value: u64) {
+        // StatsD counters are deltas, so send the difference to the last value
+        let previous = self.value.fetch_max(value, Ordering::AcqRel);
+        if value > previous {
+            self.send(value - previous, "c");
+        }
+    }
+}
+
+impl GaugeFn for StatsdHandle {
+    fn increment(&self, value: f64) {
+        self.update_gauge(|current| current + value);
+    }
+
+    fn decrement(&self, value: f64) {
+        self.update_gauge(|current| current - value);
+    }
+
+    fn set(&self, value: f64) {
+        self.update_gauge(|_| value);
+    }
+}
+
+impl HistogramFn for StatsdHandle {
+    fn record(&self, value: f64) {
+        self.send(value, "h");
+    }
+}
+
+/// Recorder sending every update to a StatsD agent
+pub(crate) struct StatsdRecorder {
+    sink: Arc<StatsdSink>,
+    flush_interval: Duration,
+    prefix: Option<String>,
+    constant_tags: Vec<(String, String)>,
+    counters: Mutex<HashMap<Key, Arc<StatsdHandle>>>,
+    gauges: Mutex<HashMap<Key, Arc<StatsdHandle>>>,
+}
+
+impl StatsdRecorder {
+    /// Creates the recorder without starting the flush task
+    ///
+    /// The socket is bound to the address family of the agent, so that
+    /// agents reached over IPv6 work as well.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the agent address can't be resolved or the UDP
+    /// socket can't be created
+    pub(crate) fn new(config: StatsdConfig) -> Result<Self> {
+        let agent = config
+            .address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("StatsD address '{}' doesn't resolve", config.address))?;
+        let local = match agent {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(agent)?;
+        socket.set_nonblocking(true)?;
+        let sink = StatsdSink {
+            socket,
+            max_packet_size: config.max_packet_size,
+            buffer: Mutex::new(String::new()),
+        };
+        Ok(Self {
+            sink: Arc::new(sink),
+            flush_interval: config.flush_interval,
+            prefix: config.prefix,
+            constant_tags: config.constant_tags,
+            counters: Mutex::new(HashMap::new()),
+            gauges: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Starts flushing partially filled packets
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub(crate) fn start_flushing(&self) {
+        let sink = self.sink.clone();
+        spawn_periodic("StatsD", self.flush_interval, move || {
+            sink.flush();
+            async { Ok(()) }
+        });
+    }
+
+    fn handle(&self, key: &Key) -> StatsdHandle {
+        let name = match &self.prefix {
+            Some(prefix) => format!("{}.{}", prefix, key.name()),
+            None => key.name().to_owned(),
+        };
+        let tags: Vec<String> = self
+            .constant_tags
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .chain(key.labels().map(|label| (label.key(), label.value())))
+            .map(|(k, v)| format!("{}:{}", sanitize(k), sanitize(v)))
+            .collect();
+
+        StatsdHandle {
+            sink: self.sink.clone(),
+            name: sanitize(&name),
+            tags: if tags.is_empty() {
+                String::new()
+            } else {
+                format!("|#{}", tags.join(","))
+            },
+            value: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the shared handle of a key, keeping counter and gauge state
+    /// across registrations
+    fn shared_handle(
+        &self,
+        handles: &Mutex<HashMap<Key, Arc<StatsdHandle>>>,
+        key: &Key,
+    ) -> Arc<StatsdHandle> {
+        match handles.lock() {
+            Ok(mut handles) => handles
+                .entry(key.clone())
+                .or_insert_with(|| Arc::new(self.handle(key)))
+                .clone(),
+            Err(_) => Arc::new(self.handle(key)),
+        }
+    }
+}
+
+impl Recorder for StatsdRecorder {
+    fn describe_counter(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+    fn describe_gauge(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+    fn describe_histogram(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+    fn register_counter(&self, key: &Key, _metadata: &Metadata<'_>) -> Counter {
+        Counter::from_arc(self.shared_handle(&self.counters, key))
+    }
+
+    fn register_gauge(&self, key: &Key, _metadata: &Metadata<'_>) -> Gauge {
+        // Gauges start with the last value so relative updates stay correct
+        Gauge::from_arc(self.shared_handle(&self.gauges, key))
+    }
+
+    fn register_histogram(&self, key: &Key, _metadata: &Metadata<'_>) -> Histogram {
+        Histogram::from_arc(Arc::new(self.handle(key)))
+    }
+}
+
+/// Replaces characters with a meaning in the StatsD line format
+fn sanitize(text: &str) -> String {
+    text.replace([':', '|', '@', '#', ',', '\n'], "_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agent() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        socket
+    }
+
+    fn receive(socket: &UdpSocket) -> String {
+        let mut buffer = [0u8; 2048];
+        let length = socket.recv(&mut buffer).unwrap();
+        String::from_utf8_lossy(&buffer[..length]).to_string()
+    }
+
+    #[test]
+    fn test_lines_and_tags() {
+        let agent = agent();
+        let recorder = StatsdRecorder::new(StatsdConfig {
+            address: agent.local_addr().unwrap().to_string(),
+            prefix: Some("app".to_string()),
+            constant_tags: vec![("env".to_string(), "test".to_string())],
+            ..Default::default()
+        })
+        .unwrap();
+
+        metrics::with_local_recorder(&recorder, || {
+            metrics::counter!("requests", "endpoint" => "/a").increment(2);
+            metrics::counter!("requests", "endpoint" => "/a").absolute(5);
+            metrics::gauge!("queue").set(4.0);
+            metrics::gauge!("queue").increment(1.5);
+            metrics::histogram!("latency").record(12.5);
+        });
+        recorder.sink.flush();
+
+        let lines: Vec<String> = receive(&agent).lines().map(str::to_owned).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "app.requests:2|c|#env:test,endpoint:/a",
+                "app.requests:3|c|#env:test,endpoint:/a",
+                "app.queue:4|g|#env:test",
+                "app.queue:5.5|g|#env:test",
+                "app.latency:12.5|h|#env:test",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_agent_over_ipv6() {
+        // Skipped where the loopback has no IPv6 address
+        let Ok(agent) = UdpSocket::bind("[::1]:0") else {
+            return;
+        };
+        agent
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let recorder = StatsdRecorder::new(StatsdConfig {
+            address: agent.local_addr().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        metrics::with_local_recorder(&recorder, || {
+            metrics::gauge!("queue").set(1.0);
+        });
+        recorder.sink.flush();
+        assert_eq!(receive(&agent), "queue:1|g");
+    }
+
+    #[test]
+    fn test_packets_respect_max_size() {
+        let agent = agent();
+        let recorder = StatsdRecorder::new(StatsdConfig {
+            address: agent.local_addr().unwrap().to_string(),
+            max_packet_size: 20,
+            ..Default::default()
+        })
+        .unwrap();
+
+        metrics::with_local_recorder(&recorder, || {
+            for _ in 0..3 {
+                metrics::counter!("batched").increment(1);
+            }
+        });
+        recorder.sink.flush();
+
+        // "batched:1|c" is 11 bytes, so only one line fits per packet
+        for _ in 0..3 {
+            assert_eq!(receive(&agent), "batched:1|c");
+        }
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("a:b|c#d,e@f"), "a_b_c_d_e_f");
+    }
+}
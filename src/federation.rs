@@ -0,0 +1,341 @@
+//! # Scrape Targets
+//!
+//! Pulls the Prometheus exposition of other endpoints on the host, such as
+//! sidecars or other local services, so that one dashboard shows all of
+//! them. Every target is scraped on its own interval and parsed into a
+//! snapshot whose series carry an `instance` label. The live stream and
+//! WebSocket subscriptions merge these snapshots with the local metrics,
+//! together with an `up` gauge per target. Exporters and `/prometheus` only
+//! ever see local metrics, so nothing is exported twice.
+
+use crate::exporters::http_client;
+use crate::snapshot::{Family, MetricType, Series, SeriesValue, Snapshot, series_key};
+use anyhow::{Result, anyhow};
+use log::warn;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+/// Name of the gauge reporting whether the last scrape of a target succeeded
+const UP_METRIC: &str = "up";
+
+/// Latest scrape result of every target, keyed by instance
+static TARGETS: OnceLock<Mutex<HashMap<String, TargetState>>> = OnceLock::new();
+
+/// An endpoint scraped into the dashboard
+///
+/// # Example
+///
+/// ```
+/// use metrics_rs_dashboard_actix::{DashboardInput, ScrapeTarget};
+///
+/// let dashboard_input = DashboardInput {
+///     scrape_targets: vec![ScrapeTarget {
+///         url: "http://127.0.0.1:9100/metrics".to_string(),
+///         instance: Some("node-exporter".to_string()),
+///         ..Default::default()
+///     }],
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone)]
+pub struct ScrapeTarget {
+    /// URL returning the Prometheus text exposition
+    pub url: String,
+    /// Value of the `instance` label; defaults to the `host:port` of the URL
+    pub instance: Option<String>,
+    /// Interval between two scrapes
+    pub interval: Duration,
+    /// Bearer token sent as `Authorization: Bearer ...`
+    pub bearer_token: Option<String>,
+}
+
+impl Default for ScrapeTarget {
+    fn default() -> Self {
+        Self {
+            url: "http://localhost:9100/metrics".to_string(),
+            instance: None,
+            interval: Duration::from_secs(15),
+            bearer_token: None,
+        }
+    }
+}
+
+impl ScrapeTarget {
+    /// Resolves the `instance` label of the target
+    fn instance(&self) -> String {
+        if let Some(instance) = &self.instance {
+            return instance.clone();
+        }
+        reqwest::Url::parse(&self.url)
+            .ok()
+            .and_then(|url| {
+                let host = url.host_str()?.to_owned();
+                Some(match url.port_or_known_default() {
+                    Some(port) => format!("{}:{}", host, port),
+                    None => host,
+                })
+            })
+            .unwrap_or_else(|| self.url.clone())
+    }
+}
+
+/// Outcome of the last scrape of a target
+#[derive(Debug, Clone, Default)]
+struct TargetState {
+    up: bool,
+    /// Families of the last successful scrape, already carrying the
+    /// `instance` label; empty while the target is down
+    snapshot: Snapshot,
+}
+
+/// Starts scraping every target on its interval
+///
+/// Must be called from within a Tokio runtime.
+pub(crate) fn start_scraping(targets: &[ScrapeTarget]) {
+    for target in targets {
+        let target = target.clone();
+        let instance = target.instance();
+        tokio::spawn(async move {
+            loop {
+                let state = match scrape(&target, &instance).await {
+                    Ok(snapshot) => TargetState { up: true, snapshot },
+                    Err(e) => {
+                        warn!("Scrape of {} failed: {:#}", target.url, e);
+                        TargetState::default()
+                    }
+                };
+                store(&instance, state);
+                tokio::time::sleep(target.interval).await;
+            }
+        });
+    }
+}
+
+/// Fetches and parses the exposition of a target
+async fn scrape(target: &ScrapeTarget, instance: &str) -> Result<Snapshot> {
+    let mut request = http_client()
+        .get(&target.url)
+        .header("accept", "text/plain;version=0.0.4");
+    if let Some(token) = &target.bearer_token {
+        request = request.bearer_auth(token);
+    }
+
+    let response = request.send().await?;
+    if !response.status().is_success() {
+        return Err(anyhow!("target responded with {}", response.status()));
+    }
+    let text = response.text().await?;
+    Ok(with_instance(Snapshot::parse(&text), instance))
+}
+
+fn store(instance: &str, state: TargetState) {
+    let targets = TARGETS.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Ok(mut targets) = targets.lock() {
+        targets.insert(instance.to_owned(), state);
+    }
+}
+
+/// Adds the `instance` label to every series of a scraped snapshot
+///
+/// An `instance` label exposed by the target itself is kept as
+/// `exported_instance`, the same way Prometheus handles label conflicts.
+fn with_instance(snapshot: Snapshot, instance: &str) -> Snapshot {
+    let instance = escape_label_value(instance);
+    let mut families = BTreeMap::new();
+    for (name, family) in snapshot.families {
+        let series = family
+            .series
+            .into_values()
+            .map(|mut series| {
+                if let Some(exported) = series.labels.remove("instance") {
+                    series
+                        .labels
+                        .insert("exported_instance".to_string(), exported);
+                }
+                series
+                    .labels
+                    .insert("instance".to_string(), instance.clone());
+                if let SeriesValue::Histogram { exemplars, .. } = &mut series.value {
+                    exemplars.clear();
+                }
+                (series_key(&series.labels), series)
+            })
+            .collect();
+        families.insert(name, Family { series, ..family });
+    }
+    Snapshot { families }
+}
+
+/// Merges the scraped targets into a snapshot of the local metrics
+///
+/// Families whose type differs from a local family of the same name are
+/// skipped for that target, since their series can't be shown together.
+pub(crate) fn merge_targets(snapshot: &mut Snapshot) {
+    let Some(targets) = TARGETS.get() else {
+        return;
+    };
+    let Ok(targets) = targets.lock() else {
+        return;
+    };
+    merge(snapshot, &targets);
+}
+
+fn merge(snapshot: &mut Snapshot, targets: &HashMap<String, TargetState>) {
+    for (instance, state) in targets {
+        for (name, family) in &state.snapshot.families {
+            let merged = snapshot
+                .families
+                .entry(name.clone())
+                .or_insert_with(|| Family {
+                    series: BTreeMap::new(),
+                    ..family.clone()
+                });
+            if merged.metric_type != family.metric_type {
+                continue;
+            }
+            merged.series.extend(
+                family
+                    .series
+                    .iter()
+                    .map(|(key, series)| (key.clone(), series.clone())),
+            );
+        }
+
+        let up = snapshot
+            .families
+            .entry(UP_METRIC.to_string())
+            .or_insert_with(|| Family {
+                name: UP_METRIC.to_string(),
+                help: "Whether the last scrape of the target succeeded".to_string(),
+                metric_type: MetricType::Gauge,
+                series: BTreeMap::new(),
+            });
+        if up.metric_type == MetricType::Gauge {
+            let labels = BTreeMap::from([("instance".to_string(), escape_label_value(instance))]);
+            let value = if state.up { 1.0 } else { 0.0 };
+            up.series.insert(
+                series_key(&labels),
+                Series {
+                    labels,
+                    value: SeriesValue::Single { value },
+                },
+            );
+        }
+    }
+}
+
+/// Escapes a label value the way the text exposition does, matching the
+/// escaped values kept in snapshots
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporters::test_support::MockCollector;
+
+    const LOCAL: &str = r#"# TYPE requests_total counter
+requests_total{endpoint="/a"} 3
+# TYPE queue_depth gauge
+queue_depth 4
+"#;
+
+    const SIDECAR: &str = r#"# HELP requests_total Requests handled by the sidecar
+# TYPE requests_total counter
+requests_total{endpoint="/b",instance="pod-1"} 7
+# TYPE queue_depth counter
+queue_depth 9
+"#;
+
+    #[test]
+    fn test_instance_defaults_to_host_and_port() {
+        let target = ScrapeTarget {
+            url: "http://10.0.0.5:9100/metrics".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(target.instance(), "10.0.0.5:9100");
+
+        let target = ScrapeTarget {
+            url: "https://sidecar.local/metrics".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(target.instance(), "sidecar.local:443");
+    }
+
+    #[test]
+    fn test_merge_adds_instance_and_up() {
+        let mut snapshot = Snapshot::parse(LOCAL);
+        let targets = HashMap::from([
+            (
+                "sidecar:9000".to_string(),
+                TargetState {
+                    up: true,
+                    snapshot: with_instance(Snapshot::parse(SIDECAR), "sidecar:9000"),
+                },
+            ),
+            ("down:9000".to_string(), TargetState::default()),
+        ]);
+
+        merge(&mut snapshot, &targets);
+
+        let requests = &snapshot.families["requests_total"];
+        assert_eq!(requests.series.len(), 2);
+        let scraped = requests
+            .series
+            .values()
+            .find(|series| series.labels.contains_key("instance"))
+            .unwrap();
+        assert_eq!(scraped.labels["instance"], "sidecar:9000");
+        assert_eq!(scraped.labels["exported_instance"], "pod-1");
+        assert_eq!(scraped.value, SeriesValue::Single { value: 7.0 });
+
+        // The sidecar's counter conflicts with the local gauge
+        assert_eq!(snapshot.families["queue_depth"].series.len(), 1);
+
+        let up = &snapshot.families["up"].series;
+        assert_eq!(
+            up[r#"instance="sidecar:9000""#].value,
+            SeriesValue::Single { value: 1.0 }
+        );
+        assert_eq!(
+            up[r#"instance="down:9000""#].value,
+            SeriesValue::Single { value: 0.0 }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scrape_target() {
+        let target = MockCollector::with_body(SIDECAR).await;
+        let config = ScrapeTarget {
+            url: target.url("/metrics"),
+            bearer_token: Some("secret".to_string()),
+            ..Default::default()
+        };
+
+        let snapshot = scrape(&config, "sidecar").await.unwrap();
+
+        assert_eq!(snapshot.families["requests_total"].series.len(), 1);
+        assert_eq!(
+            target.requests()[0].headers["authorization"],
+            "Bearer secret"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scrape_reports_errors() {
+        let target = MockCollector::with_statuses(vec![503]).await;
+        let config = ScrapeTarget {
+            url: target.url("/metrics"),
+            ..Default::default()
+        };
+
+        assert!(scrape(&config, "sidecar").await.is_err());
+    }
+}
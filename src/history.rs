@@ -0,0 +1,164 @@
+//! # Metric History
+//!
+//! Keeps the recent values of every series on the server, so that queries
+//! can look back in time without an external Prometheus. The metrics shown
+//! in the dashboard, including scrape targets, are sampled on a fixed
+//! interval and kept for a retention period. Histograms and summaries are
+//! stored as their flattened `_bucket`, quantile, `_sum` and `_count` samples,
+//! together with the [exact quantiles](crate::summaries) since the previous
+//! sample.
+
+use crate::{
+    snapshot::{FlatSample, Snapshot},
+    summaries,
+};
+use chrono::{DateTime, Utc};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+use tokio::time::MissedTickBehavior;
+
+/// Default interval between two samples of the history
+pub(crate) const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Default time samples are kept for
+pub(crate) const DEFAULT_RETENTION: Duration = Duration::from_secs(3600);
+
+/// History of all series, filled by [`start_recording`]
+static HISTORY: OnceLock<Mutex<History>> = OnceLock::new();
+
+/// Identity of a stored series
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct SeriesId {
+    pub name: String,
+    /// Unescaped label values
+    pub labels: BTreeMap<String, String>,
+}
+
+/// A value at a point in time
+pub(crate) type Point = (DateTime<Utc>, f64);
+
+/// Recent values of every series, oldest first
+#[derive(Debug)]
+pub(crate) struct History {
+    retention: Duration,
+    series: HashMap<SeriesId, VecDeque<Point>>,
+}
+
+impl History {
+    pub fn new(retention: Duration) -> Self {
+        Self {
+            retention,
+            series: HashMap::new(),
+        }
+    }
+
+    /// Appends the samples taken at `at` and forgets values older than the
+    /// retention, dropping series without any value left
+    pub fn record(&mut self, samples: Vec<FlatSample>, at: DateTime<Utc>) {
+        for sample in samples {
+            let id = SeriesId {
+                name: sample.name,
+                labels: sample.labels,
+            };
+            self.series
+                .entry(id)
+                .or_default()
+                .push_back((at, sample.value));
+        }
+
+        let cutoff = at - chrono::Duration::from_std(self.retention).unwrap_or_default();
+        self.series.retain(|_, points| {
+            while points.front().is_some_and(|(time, _)| *time < cutoff) {
+                points.pop_front();
+            }
+            !points.is_empty()
+        });
+    }
+
+    /// How long values are kept
+    pub fn retention(&self) -> Duration {
+        self.retention
+    }
+
+    /// Copies the series for which `keep` returns true
+    pub fn filtered(&self, keep: impl Fn(&SeriesId) -> bool) -> History {
+        History {
+            retention: self.retention,
+            series: self
+                .series
+                .iter()
+                .filter(|(id, _)| keep(id))
+                .map(|(id, points)| (id.clone(), points.clone()))
+                .collect(),
+        }
+    }
+
+    /// Iterates over every series and its values
+    pub fn series(&self) -> impl Iterator<Item = (&SeriesId, &VecDeque<Point>)> {
+        self.series.iter()
+    }
+}
+
+/// Runs `f` on the recorded history, or on an empty one if recording never
+/// started
+pub(crate) fn with_history<T>(f: impl FnOnce(&History) -> T) -> T {
+    match HISTORY.get().and_then(|history| history.lock().ok()) {
+        Some(history) => f(&history),
+        None => f(&History::new(DEFAULT_RETENTION)),
+    }
+}
+
+/// Starts sampling the dashboard's metrics into the history every
+/// `interval`, keeping them for `retention`
+///
+/// Must be called from within a Tokio runtime.
+pub(crate) fn start_recording(interval: Duration, retention: Duration) {
+    if HISTORY.set(Mutex::new(History::new(retention))).is_err() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            let mut samples = Snapshot::capture_with_targets().samples();
+            samples.extend(summaries::drain_quantiles());
+            if let Some(mut history) = HISTORY.get().and_then(|history| history.lock().ok()) {
+                history.record(samples, Utc::now());
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_retention() {
+        let mut history = History::new(Duration::from_secs(60));
+        let start = Utc::now();
+        let snapshot = Snapshot::parse(
+            "# TYPE requests counter\nrequests{path=\"/a\"} 1\nrequests{path=\"/b\"} 2\n",
+        );
+
+        history.record(snapshot.samples(), start);
+        history.record(
+            Snapshot::parse("# TYPE requests counter\nrequests{path=\"/a\"} 5\n").samples(),
+            start + chrono::Duration::seconds(30),
+        );
+        assert_eq!(history.series().count(), 2);
+
+        // Values of `/b` are older than the retention and the series is dropped
+        history.record(Vec::new(), start + chrono::Duration::seconds(61));
+        let series: Vec<_> = history.series().collect();
+        assert_eq!(series.len(), 1);
+        assert_eq!(series[0].0.labels["path"], "/a");
+        assert_eq!(series[0].1.len(), 1);
+        assert_eq!(series[0].1[0].1, 5.0);
+    }
+}
@@ -0,0 +1,308 @@
+//! # Constant Labels
+//!
+//! Adds labels such as `service`, `version` or `region` to every series.
+//! Global labels apply to all metrics, scoped labels only to the metrics
+//! matched by their `Matcher`. Labels recorded with a metric take precedence
+//! over scoped labels, which take precedence over global ones.
+//!
+//! Global labels are passed to the Prometheus recorder as its own global
+//! labels. Everything rendered from its exposition, i.e. the dashboard, the
+//! JSON APIs and the pushing exporters, carries them from there. Recorders
+//! of their own, like the OTLP and StatsD exporters, are wrapped in a
+//! [`LabelRecorder`] adding the labels to every key, and the families of
+//! the bridged `prometheus` registry get them before they are appended.
+
+use anyhow::{Result, bail};
+use metrics::{
+    Counter, Gauge, Histogram, Key, KeyName, Label, Metadata, Recorder, SharedString, Unit,
+};
+use metrics_exporter_prometheus::Matcher;
+use prometheus::proto::{LabelPair, MetricFamily};
+use std::sync::OnceLock;
+
+/// Labels configured through `DashboardInput`
+static LABELS: OnceLock<ConstantLabels> = OnceLock::new();
+
+/// Label names set by the exposition itself
+const RESERVED_LABELS: [&str; 3] = ["le", "quantile", "instance"];
+
+/// Global and scoped constant labels
+#[derive(Debug)]
+pub(crate) struct ConstantLabels {
+    global: Vec<(String, String)>,
+    scoped: Vec<(Matcher, Vec<(String, String)>)>,
+}
+
+impl ConstantLabels {
+    /// Checks the label names
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for an invalid or reserved label name
+    pub fn new(
+        global: &[(String, String)],
+        scoped: &[(Matcher, Vec<(String, String)>)],
+    ) -> Result<Self> {
+        let names = global
+            .iter()
+            .chain(scoped.iter().flat_map(|(_, labels)| labels))
+            .map(|(name, _)| name.as_str());
+        for name in names {
+            let valid = name
+                .chars()
+                .enumerate()
+                .all(|(i, c)| c == '_' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit()));
+            if name.is_empty() || !valid || name.starts_with("__") {
+                bail!("Invalid constant label name '{}'", name);
+            }
+            if RESERVED_LABELS.contains(&name) {
+                bail!("Constant label '{}' is reserved", name);
+            }
+        }
+        Ok(Self {
+            global: global.to_vec(),
+            scoped: scoped.to_vec(),
+        })
+    }
+
+    /// Labels of a metric that it doesn't already have, scoped ones first
+    fn missing<'a>(
+        &'a self,
+        name: &str,
+        include_global: bool,
+        has_label: impl Fn(&str) -> bool,
+    ) -> Vec<&'a (String, String)> {
+        let scoped = self
+            .scoped
+            .iter()
+            .filter(|(matcher, _)| matcher.matches(name))
+            .flat_map(|(_, labels)| labels);
+        let global = self.global.iter().filter(|_| include_global);
+
+        let mut missing: Vec<&(String, String)> = Vec::new();
+        for label in scoped.chain(global) {
+            if !has_label(&label.0) && missing.iter().all(|added| added.0 != label.0) {
+                missing.push(label);
+            }
+        }
+        missing
+    }
+
+    /// Returns the key with the constant labels it is missing
+    fn extend_key(&self, key: &Key, include_global: bool) -> Option<Key> {
+        let missing = self.missing(key.name(), include_global, |name| {
+            key.labels().any(|label| label.key() == name)
+        });
+        if missing.is_empty() {
+            return None;
+        }
+        let labels = key.labels().cloned().chain(
+            missing
+                .into_iter()
+                .map(|(name, value)| Label::new(name.clone(), value.clone())),
+        );
+        Some(Key::from_parts(
+            key.name().to_owned(),
+            labels.collect::<Vec<_>>(),
+        ))
+    }
+}
+
+/// Stores the constant labels used by the recorders created afterwards
+pub(crate) fn configure(labels: ConstantLabels) {
+    let _ = LABELS.set(labels);
+}
+
+/// Constant labels a series of the metric with the given labels gets
+pub(crate) fn missing_labels(name: &str, labels: &[(&str, &str)]) -> Vec<(String, String)> {
+    LABELS
+        .get()
+        .map(|constant| {
+            constant
+                .missing(name, true, |label| {
+                    labels.iter().any(|(key, _)| *key == label)
+                })
+                .into_iter()
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Adds the constant labels to the series of bridged families
+pub(crate) fn label_families(families: &mut [MetricFamily]) {
+    if let Some(labels) = LABELS.get() {
+        add_to_families(labels, families);
+    }
+}
+
+fn add_to_families(labels: &ConstantLabels, families: &mut [MetricFamily]) {
+    for family in families {
+        let name = family.name().to_owned();
+        for metric in family.mut_metric() {
+            let missing = labels.missing(&name, true, |label| {
+                metric.get_label().iter().any(|pair| pair.name() == label)
+            });
+            if missing.is_empty() {
+                continue;
+            }
+            let mut pairs = metric.take_label();
+            for (name, value) in missing {
+                let mut pair = LabelPair::default();
+                pair.set_name(name.clone());
+                pair.set_value(value.clone());
+                pairs.push(pair);
+            }
+            metric.set_label(pairs);
+        }
+    }
+}
+
+/// Wraps a recorder, adding the constant labels to the keys it registers
+///
+/// The Prometheus recorder adds the global labels itself, so it is wrapped
+/// without them.
+pub(crate) struct LabelRecorder<R> {
+    inner: R,
+    labels: &'static ConstantLabels,
+    include_global: bool,
+}
+
+impl<R> LabelRecorder<R> {
+    pub fn new(inner: R, include_global: bool) -> Self {
+        static NO_LABELS: ConstantLabels = ConstantLabels {
+            global: Vec::new(),
+            scoped: Vec::new(),
+        };
+        Self {
+            inner,
+            labels: LABELS.get().unwrap_or(&NO_LABELS),
+            include_global,
+        }
+    }
+}
+
+impl<R: Recorder> Recorder for LabelRecorder<R> {
+    fn describe_counter(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_counter(key, unit, description);
+    }
+
+    fn describe_gauge(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_gauge(key, unit, description);
+    }
+
+    fn describe_histogram(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_histogram(key, unit, description);
+    }
+
+    fn register_counter(&self, key: &Key, metadata: &Metadata<'_>) -> Counter {
+        match self.labels.extend_key(key, self.include_global) {
+            Some(key) => self.inner.register_counter(&key, metadata),
+            None => self.inner.register_counter(key, metadata),
+        }
+    }
+
+    fn register_gauge(&self, key: &Key, metadata: &Metadata<'_>) -> Gauge {
+        match self.labels.extend_key(key, self.include_global) {
+            Some(key) => self.inner.register_gauge(&key, metadata),
+            None => self.inner.register_gauge(key, metadata),
+        }
+    }
+
+    fn register_histogram(&self, key: &Key, metadata: &Metadata<'_>) -> Histogram {
+        match self.labels.extend_key(key, self.include_global) {
+            Some(key) => self.inner.register_histogram(&key, metadata),
+            None => self.inner.register_histogram(key, metadata),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+    use prometheus::{IntCounterVec, Opts, Registry};
+
+    fn labels() -> ConstantLabels {
+        ConstantLabels {
+            global: vec![
+                ("service".to_string(), "api".to_string()),
+                ("region".to_string(), "eu".to_string()),
+            ],
+            scoped: vec![(
+                Matcher::Prefix("db_".to_string()),
+                vec![("region".to_string(), "us".to_string())],
+            )],
+        }
+    }
+
+    fn label_pairs(key: &Key) -> Vec<(&str, &str)> {
+        key.labels()
+            .map(|label| (label.key(), label.value()))
+            .collect()
+    }
+
+    #[test]
+    fn test_label_precedence() {
+        let labels: &'static ConstantLabels = Box::leak(Box::new(labels()));
+        let debugging = DebuggingRecorder::new();
+        let snapshotter = debugging.snapshotter();
+        let recorder = LabelRecorder {
+            inner: debugging,
+            labels,
+            include_global: true,
+        };
+        metrics::with_local_recorder(&recorder, || {
+            metrics::counter!("requests", "service" => "web").increment(1);
+            metrics::counter!("db_queries").increment(2);
+        });
+
+        let mut keys: Vec<_> = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .map(|(key, _, _, value)| (key.key().clone(), value))
+            .collect();
+        keys.sort_by(|a, b| a.0.name().cmp(b.0.name()));
+        assert_eq!(
+            label_pairs(&keys[0].0),
+            [("region", "us"), ("service", "api")]
+        );
+        assert_eq!(keys[0].1, DebugValue::Counter(2));
+        assert_eq!(
+            label_pairs(&keys[1].0),
+            [("service", "web"), ("region", "eu")]
+        );
+
+        let scoped_only = labels.extend_key(&Key::from_name("requests"), false);
+        assert!(scoped_only.is_none());
+    }
+
+    #[test]
+    fn test_label_names_are_checked() {
+        let label = |name: &str| vec![(name.to_string(), "x".to_string())];
+        assert!(ConstantLabels::new(&label("1st"), &[]).is_err());
+        assert!(ConstantLabels::new(&label("__name__"), &[]).is_err());
+        assert!(
+            ConstantLabels::new(&[], &[(Matcher::Full("a".to_string()), label("le"))]).is_err()
+        );
+        assert!(ConstantLabels::new(&label("region"), &[]).is_ok());
+    }
+
+    #[test]
+    fn test_bridged_families_are_labelled() {
+        let registry = Registry::new();
+        let jobs = IntCounterVec::new(Opts::new("jobs", "Jobs"), &["region"]).unwrap();
+        jobs.with_label_values(&["ap"]).inc();
+        registry.register(Box::new(jobs)).unwrap();
+        let mut families = registry.gather();
+
+        add_to_families(&labels(), &mut families);
+        let pairs: Vec<_> = families[0].get_metric()[0]
+            .get_label()
+            .iter()
+            .map(|pair| (pair.name(), pair.value()))
+            .collect();
+        assert_eq!(pairs, [("region", "ap"), ("service", "api")]);
+    }
+}
@@ -6,9 +6,33 @@
 //!
 //! ## Features
 //! - **Prometheus Integration**: Full support for collecting and exposing metrics in Prometheus format
+//! - **OpenMetrics**: Negotiated via `Accept`, with units, `_created` timestamps and `# EOF`
 //! - **Interactive Dashboard**: Built-in web UI for visualizing metrics in real-time
+//! - **Live Updates**: Server-Sent Events stream pushing only changed series to the dashboard
+//! - **WebSocket Subscriptions**: Prefix or regex filtered series deltas for remote dashboards
+//! - **Authentication**: Bearer tokens, basic auth and IP allow-lists for scrape and UI endpoints
+//! - **Compression and Caching**: `Accept-Encoding` negotiation and ETags for dashboard assets
 //! - **Rate Metrics**: Automatic calculation and tracking of per-second rates from counter values
-//! - **Customizable Histograms**: Fine-grained control over histogram bucket configuration
+//! - **Customizable Histograms**: Per-metric buckets, exponential buckets or summaries with their own quantiles and window
+//! - **Exemplars**: Trace ids attached to histogram buckets, clickable in the dashboard
+//! - **OTLP Export**: Optional push of all metrics to an OpenTelemetry collector
+//! - **Pushgateway**: Periodic and on-shutdown pushes for short-lived jobs
+//! - **Remote-Write**: Queued, retried pushes to Prometheus remote-write endpoints
+//! - **StatsD**: DogStatsD lines over UDP, batched into MTU-sized packets
+//! - **Graphite**: Plaintext protocol over TCP with configurable path schemes
+//! - **InfluxDB**: Line protocol endpoint and optional push to a write API
+//! - **Scrape Targets**: Other local `/metrics` endpoints shown in the dashboard with an `instance` label
+//! - **Prometheus Crate Bridge**: Families of a `prometheus` registry merged into the same endpoint
+//! - **Alerting**: Threshold rules evaluated in-process, served at `/metrics/alerts` and shown in the dashboard
+//! - **Alert Notifications**: Grouped webhook, Slack and callback notifications with repeats and resolves
+//! - **SLOs**: Availability, error budgets and multi-window burn rates as gauges and a dashboard panel
+//! - **Query API**: PromQL-like expressions over a server-side history at `/metrics/api/query`
+//! - **Percentiles**: p50/p90/p99 of histograms over time, interpolated or exact, plotted in the dashboard
+//! - **Constant Labels**: Global and per-metric labels such as `service` or `region` on every series
+//! - **Relabeling**: Drops, renames and hashes series before they reach any recorder
+//! - **Cardinality**: Series counts per metric name at `/metrics/api/cardinality`, with optional limits
+//! - **Self Metrics**: Render cost, rate trackers, upkeep runs and export failures of the crate itself
+//! - **Log Metrics**: Counts `log` records by level and target through [`MetricsLogger`]
 //! - **Easy Integration**: Seamlessly integrates with Actix web applications via a simple API
 //! - **Thread-Safe**: Designed for concurrent access with proper synchronization
 //! - **Low Overhead**: Minimal performance impact on your application
@@ -29,12 +53,56 @@ pub use metrics_exporter_prometheus;
 /// Re-export of the `metrics_util` crate for utility functions related to metrics
 pub use metrics_util;
 
-use actix_web::{HttpResponse, Responder, Scope, web};
+mod alerts;
+mod auth;
+mod cardinality;
+mod distributions;
+mod exemplars;
+mod exporters;
+mod federation;
+mod history;
+mod labels;
+mod log_metrics;
+mod openmetrics;
+mod prometheus_bridge;
+mod query;
+mod relabel;
+mod selector;
+mod self_metrics;
+mod slo;
+mod snapshot;
+mod stream;
+mod summaries;
+mod websocket;
+
+pub use alerts::{
+    Alert, AlertNotification, AlertNotifierConfig, AlertRule, AlertSink, AlertState,
+    NotificationStatus, load_alert_rules,
+};
+pub use auth::AuthConfig;
+pub use cardinality::{REFUSED_SERIES_METRIC, SERIES_METRIC};
+pub use distributions::{HistogramConfig, SummaryConfig};
+pub use exemplars::record_exemplar;
+pub use exporters::{
+    GraphiteConfig, GraphitePathScheme, InfluxConfig, OtlpConfig, PushgatewayConfig,
+    RemoteWriteConfig, StatsdConfig, flush_exporters,
+};
+pub use federation::ScrapeTarget;
+pub use log_metrics::{LOG_RECORDS_METRIC, MetricsLogger};
+pub use relabel::RelabelRule;
+pub use self_metrics::SELF_METRICS_PREFIX;
+pub use slo::{Slo, SloIndicator};
+
+use actix_web::{
+    HttpMessage, HttpRequest, HttpResponse, Responder, Scope,
+    http::header::{self, CacheControl, CacheDirective, ETag, EntityTag, IfNoneMatch},
+    middleware, web,
+};
 use anyhow::Result;
 use log::debug;
 use log_once::debug_once;
 use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
-use metrics_util::layers::FanoutBuilder;
+use metrics_util::{MetricKindMask, layers::FanoutBuilder};
 use mime_guess::from_path;
 use rust_embed::Embed;
 use std::{
@@ -240,6 +308,175 @@ pub struct DashboardInput<'a> {
     /// let buckets = vec![(Matcher::Full("http_request_duration".to_string()), latency_buckets)];
     /// ```
     pub buckets_for_metrics: Vec<(Matcher, &'a [f64])>,
+
+    /// How histograms are exported, per metric.
+    ///
+    /// Histograms can get explicit buckets, exponential buckets or be
+    /// exported as summaries with their own quantiles and window. Histograms
+    /// matched by none of these or by `buckets_for_metrics` are exported as
+    /// summaries with the defaults of the Prometheus exporter.
+    pub histograms: Vec<(Matcher, HistogramConfig)>,
+
+    /// Labels added to every series, e.g. `service`, `version` and `region`.
+    ///
+    /// Passed to the Prometheus recorder as global labels and also applied
+    /// by the OTLP and StatsD exporters and to the bridged `prometheus`
+    /// registry. Labels recorded with a metric take precedence.
+    ///
+    /// # Example
+    /// ```
+    /// let global_labels = vec![
+    ///     ("service".to_string(), "checkout".to_string()),
+    ///     ("version".to_string(), env!("CARGO_PKG_VERSION").to_string()),
+    /// ];
+    /// ```
+    pub global_labels: Vec<(String, String)>,
+
+    /// Labels added only to the series of metrics matched by the `Matcher`,
+    /// taking precedence over `global_labels`.
+    pub scoped_labels: Vec<(Matcher, Vec<(String, String)>)>,
+
+    /// Rules dropping or rewriting series before they are recorded.
+    ///
+    /// Applied in order to every metric registered through the `metrics`
+    /// facade, so dropped series never reach the Prometheus endpoint, the
+    /// dashboard or an exporter.
+    pub relabel_rules: Vec<RelabelRule>,
+
+    /// Maximum number of series of every metric name.
+    ///
+    /// New label combinations of a metric at its limit are dropped with a
+    /// warning. Series counts are tracked and served at
+    /// `/metrics/api/cardinality` either way. No limit when `None`.
+    pub cardinality_limit: Option<usize>,
+
+    /// Limits for the metrics matched by the `Matcher`, taking precedence
+    /// over `cardinality_limit`.
+    pub cardinality_limits: Vec<(Matcher, usize)>,
+
+    /// Removes series not updated within this duration.
+    ///
+    /// Idle series disappear from `/metrics/prometheus`, and everything built
+    /// on it, until they are recorded again, and stop counting towards the
+    /// cardinality limits. Series are kept forever when `None`.
+    pub series_idle_timeout: Option<Duration>,
+
+    /// Leaves the crate's own metrics, named with [`SELF_METRICS_PREFIX`],
+    /// out of the dashboard stream and WebSocket subscriptions.
+    ///
+    /// They are still part of `/metrics/prometheus` and every exporter.
+    pub hide_self_metrics: bool,
+
+    /// Minimum interval between updates of the live dashboard stream and
+    /// WebSocket subscriptions.
+    ///
+    /// Clients may ask for a slower interval, but never a faster one.
+    /// Defaults to 100 milliseconds when `None`.
+    pub stream_interval: Option<Duration>,
+
+    /// Access control for the Prometheus and InfluxDB scrape endpoints.
+    ///
+    /// `None` leaves the endpoint open.
+    pub scrape_auth: Option<AuthConfig>,
+
+    /// Access control for the dashboard UI, including its assets, the live
+    /// stream and the WebSocket endpoint.
+    ///
+    /// `None` leaves the dashboard open.
+    pub dashboard_auth: Option<AuthConfig>,
+
+    /// Link opened when an exemplar is clicked in a dashboard histogram.
+    ///
+    /// `{label}` placeholders are replaced with the exemplar's label values,
+    /// e.g. `http://localhost:16686/trace/{trace_id}` for Jaeger.
+    /// `None` shows the exemplar without a link.
+    pub exemplar_link: Option<String>,
+
+    /// Pushes all metrics to an OpenTelemetry collector via OTLP/HTTP.
+    ///
+    /// `None` disables the OTLP exporter.
+    pub otlp: Option<OtlpConfig>,
+
+    /// Pushes the Prometheus exposition to a Pushgateway.
+    ///
+    /// Call [`flush_exporters`] before exiting to push the final values.
+    /// `None` disables the Pushgateway exporter.
+    pub pushgateway: Option<PushgatewayConfig>,
+
+    /// Sends snapshots to a Prometheus remote-write endpoint.
+    ///
+    /// `None` disables the remote-write exporter.
+    pub remote_write: Option<RemoteWriteConfig>,
+
+    /// Sends every update to a StatsD or DogStatsD agent over UDP.
+    ///
+    /// `None` disables the StatsD exporter.
+    pub statsd: Option<StatsdConfig>,
+
+    /// Sends snapshots to Carbon using the Graphite plaintext protocol.
+    ///
+    /// `None` disables the Graphite exporter.
+    pub graphite: Option<GraphiteConfig>,
+
+    /// Pushes InfluxDB line protocol to an Influx-compatible write API.
+    ///
+    /// The `/metrics/influx` endpoint is available regardless of this setting.
+    /// `None` disables the push.
+    pub influx: Option<InfluxConfig>,
+
+    /// Other Prometheus endpoints on the host to show in the dashboard.
+    ///
+    /// Each target's series get an `instance` label and an `up` gauge
+    /// reports whether its last scrape succeeded. Scraped metrics are not
+    /// re-exported through `/prometheus` or the exporters.
+    pub scrape_targets: Vec<ScrapeTarget>,
+
+    /// Registry of the `prometheus` crate whose families are merged into
+    /// `/prometheus`, the dashboard and the exporters, usually
+    /// `prometheus::default_registry().clone()`.
+    ///
+    /// Families clashing with a metric recorded through `metrics` are left
+    /// out and reported with a warning. `None` disables the bridge.
+    pub prometheus_registry: Option<prometheus::Registry>,
+
+    /// Alert rules evaluated against the metrics shown in the dashboard.
+    ///
+    /// Rules can also be loaded from a TOML file with [`load_alert_rules`].
+    /// Active alerts are served at `/metrics/alerts`.
+    pub alert_rules: Vec<AlertRule>,
+
+    /// Interval between two evaluations of the alert rules.
+    ///
+    /// Defaults to 5 seconds when `None`.
+    pub alert_interval: Option<Duration>,
+
+    /// Sends firing and resolved alerts to webhooks, Slack or a callback.
+    ///
+    /// `None` only shows alerts in the dashboard and at `/metrics/alerts`.
+    pub alert_notifications: Option<AlertNotifierConfig>,
+
+    /// Service level objectives computed from counters or histograms.
+    ///
+    /// Published as `slo_*` gauges, served at `/metrics/slo` and shown on
+    /// the SLO panel of the dashboard.
+    pub slos: Vec<Slo>,
+
+    /// Interval between two samples of the history used by the query API.
+    ///
+    /// Defaults to 10 seconds when `None`.
+    pub history_interval: Option<Duration>,
+
+    /// Time samples are kept in the history used by the query API.
+    ///
+    /// Defaults to one hour when `None`.
+    pub history_retention: Option<Duration>,
+
+    /// Histograms whose exact quantiles are computed from every observation.
+    ///
+    /// Percentiles of other histograms are interpolated from their buckets.
+    /// Exact quantiles are kept in the history and served at
+    /// `/metrics/api/quantiles`, but not exported.
+    pub exact_quantiles: Vec<Matcher>,
 }
 
 /// The UnitRecorder captures unit metadata from metrics registrations
@@ -327,6 +564,7 @@ impl Recorder for UnitRecorder {
         key: &metrics::Key,
         _metadata: &metrics::Metadata<'_>,
     ) -> metrics::Counter {
+        openmetrics::record_creation(key);
         Counter::from_arc(Arc::new(UnitRecorderHandle(key.clone())))
     }
 
@@ -335,6 +573,7 @@ impl Recorder for UnitRecorder {
         key: &metrics::Key,
         _metadata: &metrics::Metadata<'_>,
     ) -> metrics::Gauge {
+        openmetrics::record_creation(key);
         Gauge::from_arc(Arc::new(UnitRecorderHandle(key.clone())))
     }
 
@@ -343,6 +582,7 @@ impl Recorder for UnitRecorder {
         key: &metrics::Key,
         _metadata: &metrics::Metadata<'_>,
     ) -> metrics::Histogram {
+        openmetrics::record_creation(key);
         Histogram::from_arc(Arc::new(UnitRecorderHandle(key.clone())))
     }
 }
@@ -374,21 +614,54 @@ impl UnitRecorder {
 /// in the binary using rust-embed. It automatically sets the proper
 /// content type based on file extension.
 ///
+/// Every asset carries an ETag derived from its content hash together with
+/// `Cache-Control: no-cache`, so browsers revalidate instead of downloading
+/// the dashboard again. A matching `If-None-Match` yields `304 Not Modified`.
+/// The ETag is weak because the body may be compressed differently depending
+/// on the negotiated encoding.
+///
 /// # Arguments
 ///
+/// * `req` - The request, used to read the `If-None-Match` header
 /// * `path` - Path to the file within the embedded assets
 ///
 /// # Returns
 ///
 /// HttpResponse containing the file content with appropriate MIME type,
+/// `304 Not Modified` if the client's cached copy is current,
 /// or a 404 Not Found response if the asset doesn't exist
-fn handle_embedded_file(path: &str) -> HttpResponse {
-    match Asset::get(path) {
-        Some(content) => HttpResponse::Ok()
-            .content_type(from_path(path).first_or_octet_stream().as_ref())
-            .body(content.data.into_owned()),
-        None => HttpResponse::NotFound().body("404 Not Found"),
+fn handle_embedded_file(req: &HttpRequest, path: &str) -> HttpResponse {
+    let Some(content) = Asset::get(path) else {
+        return HttpResponse::NotFound().body("404 Not Found");
+    };
+
+    let hash: String = content
+        .metadata
+        .sha256_hash()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    let etag = EntityTag::new_weak(hash);
+    let cache_control = CacheControl(vec![CacheDirective::NoCache]);
+
+    let not_modified = match req.get_header::<IfNoneMatch>() {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+        None => false,
+    };
+
+    if not_modified {
+        return HttpResponse::NotModified()
+            .insert_header(ETag(etag))
+            .insert_header(cache_control)
+            .finish();
     }
+
+    HttpResponse::Ok()
+        .content_type(from_path(path).first_or_octet_stream().as_ref())
+        .insert_header(ETag(etag))
+        .insert_header(cache_control)
+        .body(content.data.into_owned())
 }
 
 /// Handler for the metrics dashboard index page
@@ -401,8 +674,8 @@ fn handle_embedded_file(path: &str) -> HttpResponse {
 ///
 /// The main dashboard HTML page
 #[actix_web::get("/dashboard")]
-async fn get_dashboard() -> impl Responder {
-    handle_embedded_file("index.html")
+async fn get_dashboard(req: HttpRequest) -> impl Responder {
+    handle_embedded_file(&req, "index.html")
 }
 
 /// Handler for serving dashboard assets (JS, CSS, etc.)
@@ -413,14 +686,15 @@ async fn get_dashboard() -> impl Responder {
 ///
 /// # Arguments
 ///
+/// * `req` - The request, used for cache validation
 /// * `path` - Path to the requested asset, extracted from the URL
 ///
 /// # Returns
 ///
 /// The requested asset file with appropriate content type
 #[actix_web::get("/dashboard/{_:.*}")]
-async fn get_dashboard_assets(path: web::Path<String>) -> impl Responder {
-    handle_embedded_file(path.as_str())
+async fn get_dashboard_assets(req: HttpRequest, path: web::Path<String>) -> impl Responder {
+    handle_embedded_file(&req, path.as_str())
 }
 
 /// Endpoint for exposing Prometheus metrics
@@ -428,7 +702,12 @@ async fn get_dashboard_assets(path: web::Path<String>) -> impl Responder {
 /// This endpoint is where Prometheus should scrape to collect metrics.
 /// It returns all application metrics in the standard Prometheus text format.
 /// Additionally, it includes unit information in a custom HTTP header for
-/// use by the dashboard.
+/// use by the dashboard. The body is gzip or zstd compressed when the client
+/// asks for it with `Accept-Encoding`.
+///
+/// Scrapers sending `Accept: application/openmetrics-text` receive the
+/// OpenMetrics format instead, including units, `_created` timestamps and
+/// the closing `# EOF` line.
 ///
 /// # Returns
 ///
@@ -436,9 +715,8 @@ async fn get_dashboard_assets(path: web::Path<String>) -> impl Responder {
 /// with an additional "x-dashboard-metrics-unit" header containing
 /// unit information for metrics
 #[actix_web::get("/prometheus")]
-async fn get_prometheus_metrics() -> impl Responder {
+async fn get_prometheus_metrics(req: HttpRequest) -> impl Responder {
     debug!("Gathering prometheus metrics...");
-    let prometheus_handle = PROMETHEUS_HANDLE.get();
     let metrics_units = UNITS_FOR_METRICS.get();
     let mut response = HttpResponse::Ok();
 
@@ -447,12 +725,105 @@ async fn get_prometheus_metrics() -> impl Responder {
         response.append_header(("x-dashboard-metrics-unit", header));
     }
 
-    if let Some(handle) = prometheus_handle {
-        let metrics = handle.render();
-        return response.body(metrics);
+    let wants_openmetrics = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(openmetrics::accepts_openmetrics);
+    let started = Instant::now();
+    if wants_openmetrics {
+        let body = openmetrics::render_openmetrics();
+        self_metrics::record_render(started.elapsed(), body.len());
+        return response
+            .content_type(openmetrics::OPENMETRICS_CONTENT_TYPE)
+            .body(body);
     }
 
-    HttpResponse::Ok().body(String::from(""))
+    let body = render_prometheus();
+    self_metrics::record_render(started.elapsed(), body.len());
+    response.body(body)
+}
+
+/// Renders all recorded metrics in the Prometheus text format
+///
+/// This is the single source of the exposition text, shared by the
+/// Prometheus endpoint and the live dashboard stream. Histograms configured
+/// as summaries and families of the bridged `prometheus` registry are
+/// appended after the recorded metrics.
+///
+/// # Returns
+///
+/// The rendered metrics, or an empty string if the recorder isn't configured yet
+pub(crate) fn render_prometheus() -> String {
+    let mut exposition = PROMETHEUS_HANDLE
+        .get()
+        .map(|handle| handle.render())
+        .unwrap_or_default();
+    distributions::append_summaries(&mut exposition);
+    prometheus_bridge::append_registry(&mut exposition);
+    exposition
+}
+
+/// The parts of a [`DashboardInput`] that can fail to build, built before
+/// anything global is touched
+struct ValidatedInput {
+    alert_engine: Option<alerts::AlertEngine>,
+    slo_tracker: Option<slo::SloTracker>,
+    statsd: Option<exporters::StatsdRecorder>,
+    distributions: distributions::Distributions,
+    constant_labels: labels::ConstantLabels,
+    relabeler: relabel::Relabeler,
+    prometheus_recorder: PrometheusBuilder,
+}
+
+/// Validates `input` and builds everything that can fail, without
+/// configuring or starting anything
+fn validate_input(input: &DashboardInput) -> Result<ValidatedInput> {
+    let alert_engine = if input.alert_rules.is_empty() {
+        None
+    } else {
+        Some(alerts::AlertEngine::new(&input.alert_rules)?)
+    };
+    let slo_tracker = if input.slos.is_empty() {
+        None
+    } else {
+        Some(slo::SloTracker::new(&input.slos)?)
+    };
+    let statsd = input
+        .statsd
+        .clone()
+        .map(exporters::StatsdRecorder::new)
+        .transpose()
+        .map_err(|e| anyhow::anyhow!("Failed to create StatsD exporter: {}", e))?;
+
+    let distributions =
+        distributions::Distributions::resolve(&input.buckets_for_metrics, &input.histograms)?;
+
+    let constant_labels = labels::ConstantLabels::new(&input.global_labels, &input.scoped_labels)?;
+    let relabeler = relabel::Relabeler::new(&input.relabel_rules)?;
+
+    let mut prometheus_recorder =
+        PrometheusBuilder::new().idle_timeout(MetricKindMask::ALL, input.series_idle_timeout);
+
+    for (name, value) in input.global_labels.iter() {
+        prometheus_recorder = prometheus_recorder.add_global_label(name, value);
+    }
+
+    for (matcher, buckets) in distributions.bucket_slices() {
+        prometheus_recorder = prometheus_recorder
+            .set_buckets_for_metric(matcher, buckets)
+            .map_err(|e| anyhow::anyhow!("Failed to set buckets for metric: {}", e))?;
+    }
+
+    Ok(ValidatedInput {
+        alert_engine,
+        slo_tracker,
+        statsd,
+        distributions,
+        constant_labels,
+        relabeler,
+        prometheus_recorder,
+    })
 }
 
 /// Configures metrics recorders if they haven't been configured yet
@@ -465,7 +836,13 @@ async fn get_prometheus_metrics() -> impl Responder {
 /// The function sets up:
 /// 1. A Prometheus recorder for actual metric values
 /// 2. A UnitRecorder to capture unit metadata
-/// 3. A FanoutBuilder to dispatch metrics to both recorders
+/// 3. OTLP and StatsD recorders pushing to their agents, if configured
+/// 4. Recorders collecting observations for exact quantiles and for
+///    histograms configured as summaries, if configured
+/// 5. A FanoutBuilder to dispatch metrics to all recorders, with the
+///    constant labels added to every key
+/// 6. A relabeling stage in front of the fanout, dropping and rewriting series,
+///    followed by a stage counting series and enforcing cardinality limits
 ///
 /// # Arguments
 ///
@@ -479,8 +856,14 @@ async fn get_prometheus_metrics() -> impl Responder {
 ///
 /// Returns an error if:
 /// - Cannot acquire the configuration lock
+/// - A histogram configuration is invalid
+/// - A constant label has an invalid or reserved name
+/// - A relabel rule has an invalid regular expression
 /// - Failed to set custom histogram buckets
 /// - Unable to set the Prometheus handle
+/// - Unable to create the StatsD socket
+/// - An alert rule has an invalid expression
+/// - An SLO has an invalid objective, window or selector
 /// - Unable to register the global recorder
 fn configure_metrics_recorders_once(input: &DashboardInput) -> Result<()> {
     // Return early if already configured, using "Acquire" ordering to ensure
@@ -490,6 +873,20 @@ fn configure_metrics_recorders_once(input: &DashboardInput) -> Result<()> {
         return Ok(());
     }
 
+    // Validate the input before claiming the configuration, so that an
+    // invalid input leaves nothing half configured
+    let ValidatedInput {
+        alert_engine,
+        slo_tracker,
+        statsd,
+        distributions,
+        constant_labels,
+        relabeler,
+        prometheus_recorder,
+    } = validate_input(input)?;
+    let buckets_for_metrics = distributions.bucket_slices();
+    let relabeler = Arc::new(relabeler);
+
     // Try to be the first thread to configure
     if IS_CONFIGURED
         .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
@@ -500,15 +897,14 @@ fn configure_metrics_recorders_once(input: &DashboardInput) -> Result<()> {
         return Ok(());
     }
 
-    let mut prometheus_recorder = PrometheusBuilder::new();
+    labels::configure(constant_labels);
 
-    if !input.buckets_for_metrics.is_empty() {
-        for (matcher, buckets) in input.buckets_for_metrics.iter() {
-            prometheus_recorder = prometheus_recorder
-                .set_buckets_for_metric(matcher.to_owned(), buckets)
-                .map_err(|e| anyhow::anyhow!("Failed to set buckets for metric: {}", e))?;
-        }
-    }
+    exemplars::configure(
+        &buckets_for_metrics,
+        input.exemplar_link.as_deref(),
+        relabeler.clone(),
+    );
+    prometheus_bridge::configure(input.prometheus_registry.as_ref());
 
     let prometheus_recorder = prometheus_recorder
         .set_enable_unit_suffix(false)
@@ -518,10 +914,99 @@ fn configure_metrics_recorders_once(input: &DashboardInput) -> Result<()> {
         .set(prometheus_recorder.handle())
         .map_err(|e| anyhow::anyhow!("Unable to set Prometheus handle: {}", e.render()))?;
 
-    let fanout = FanoutBuilder::default()
+    let mut fanout = FanoutBuilder::default()
         .add_recorder(UnitRecorder)
-        .add_recorder(prometheus_recorder)
-        .build();
+        .add_recorder(labels::LabelRecorder::new(
+            distributions::WithoutSummaries {
+                inner: prometheus_recorder,
+                matchers: distributions
+                    .summaries
+                    .iter()
+                    .map(|(matcher, _)| matcher.clone())
+                    .collect(),
+            },
+            false,
+        ));
+
+    if let Some(recorder) = distributions::SummaryRecorder::install(&distributions.summaries) {
+        fanout = fanout.add_recorder(labels::LabelRecorder::new(recorder, true));
+    }
+
+    if let Some(otlp) = &input.otlp {
+        fanout = fanout.add_recorder(labels::LabelRecorder::new(
+            exporters::OtlpRecorder::install(otlp.clone(), &buckets_for_metrics),
+            true,
+        ));
+    }
+
+    if let Some(recorder) = statsd {
+        recorder.start_flushing();
+        fanout = fanout.add_recorder(labels::LabelRecorder::new(recorder, true));
+    }
+
+    if let Some(recorder) = summaries::SummaryRecorder::install(&input.exact_quantiles) {
+        fanout = fanout.add_recorder(labels::LabelRecorder::new(recorder, true));
+    }
+
+    let fanout = fanout.build();
+
+    let recorder = relabel::RelabelRecorder {
+        inner: cardinality::CardinalityRecorder::install(
+            fanout,
+            input.cardinality_limit,
+            &input.cardinality_limits,
+            input.series_idle_timeout,
+        ),
+        relabeler,
+    };
+
+    metrics::set_global_recorder(recorder).map_err(|e| {
+        anyhow::anyhow!(
+            "Unable to register a recorder: {}. Did you call this function multiple times?",
+            e
+        )
+    })?;
+    self_metrics::describe();
+
+    // Background tasks are started once nothing can fail anymore
+    if let Some(pushgateway) = &input.pushgateway {
+        exporters::start_pushgateway(pushgateway.clone());
+    }
+
+    if let Some(remote_write) = &input.remote_write {
+        exporters::start_remote_write(remote_write.clone());
+    }
+
+    if let Some(graphite) = &input.graphite {
+        exporters::start_graphite(graphite.clone());
+    }
+
+    if let Some(influx) = &input.influx {
+        exporters::start_influx(influx.clone());
+    }
+
+    federation::start_scraping(&input.scrape_targets);
+
+    if let Some(engine) = alert_engine {
+        alerts::start_alerting(
+            engine,
+            input
+                .alert_interval
+                .unwrap_or(alerts::DEFAULT_EVALUATION_INTERVAL),
+            input.alert_notifications.clone(),
+        );
+    }
+
+    if let Some(tracker) = slo_tracker {
+        slo::start_slo_tracking(tracker);
+    }
+
+    history::start_recording(
+        input.history_interval.unwrap_or(history::DEFAULT_INTERVAL),
+        input
+            .history_retention
+            .unwrap_or(history::DEFAULT_RETENTION),
+    );
 
     tokio::spawn(async move {
         let handle = PROMETHEUS_HANDLE.get();
@@ -530,18 +1015,20 @@ fn configure_metrics_recorders_once(input: &DashboardInput) -> Result<()> {
             loop {
                 tokio::time::sleep(std::time::Duration::from_secs(30)).await;
                 handle.run_upkeep();
+                distributions::run_upkeep();
+                // Forget what belonged to the series the upkeep removed
+                let rendered_at = openmetrics::unix_now();
+                let snapshot = snapshot::Snapshot::parse(&render_prometheus());
+                openmetrics::prune_creation_times(&snapshot, rendered_at);
+                exemplars::prune(&snapshot, rendered_at);
+                self_metrics::record_upkeep();
             }
         } else {
             debug!("Prometheus handle not set. Skipping recorder cleanup.");
         }
     });
 
-    metrics::set_global_recorder(fanout).map_err(|e| {
-        anyhow::anyhow!(
-            "Unable to register a recorder: {}. Did you call this function multiple times?",
-            e
-        )
-    })?;
+    cardinality::start_publishing();
 
     Ok(())
 }
@@ -553,16 +1040,22 @@ fn configure_metrics_recorders_once(input: &DashboardInput) -> Result<()> {
 pub fn update_rate_tracker(_counter_name: &str, value: f64, tracker_key: String) -> f64 {
     let rate_trackers = RATE_TRACKERS.get_or_init(|| Mutex::new(HashMap::new()));
     if let Ok(mut trackers) = rate_trackers.lock() {
+        let count = trackers.len();
         let tracker = trackers
             .entry(tracker_key.clone())
             .or_insert_with(RateTracker::new);
 
         // Always calculate a rate, even with the same value
         // The RateTracker will handle the logic to determine the actual rate
-        tracker.update(value)
+        let rate = tracker.update(value);
+        if trackers.len() != count {
+            self_metrics::set_rate_trackers(trackers.len());
+        }
+        rate
     } else {
         // If we can't get the lock, attempt a minimal calculation
         // This is better than returning 0.0 which would indicate no activity
+        self_metrics::record_rate_tracker_fallback();
         static LAST_VALUES: OnceLock<Mutex<HashMap<String, (f64, Instant)>>> = OnceLock::new();
         let last_values = LAST_VALUES.get_or_init(|| Mutex::new(HashMap::new()));
 
@@ -734,7 +1227,7 @@ macro_rules! absolute_counter_with_rate {
 /// The function:
 /// 1. Initializes the metrics system (if not already done)
 /// 2. Creates an Actix web scope with path "/metrics"
-/// 3. Registers all necessary endpoints (/prometheus, /dashboard, etc.)
+/// 3. Registers all necessary endpoints (/prometheus, /influx, /alerts, /slo, /api/query, /api/quantiles, /dashboard, /stream, /ws, etc.)
 ///
 /// # Arguments
 ///
@@ -745,6 +1238,11 @@ macro_rules! absolute_counter_with_rate {
 /// Result containing the configured Actix web Scope that can be integrated
 /// into an Actix web application
 ///
+/// # Errors
+///
+/// Returns an error if the metrics recorders cannot be configured or an
+/// allowed network in the authentication settings is invalid
+///
 /// # Example
 ///
 /// ```rust,no_run
@@ -765,10 +1263,33 @@ macro_rules! absolute_counter_with_rate {
 /// ```
 pub fn create_metrics_actx_scope(input: &DashboardInput) -> Result<Scope> {
     configure_metrics_recorders_once(input)?;
+    let stream_settings = stream::StreamSettings {
+        min_interval: input
+            .stream_interval
+            .unwrap_or(stream::DEFAULT_STREAM_INTERVAL),
+        hide_self_metrics: input.hide_self_metrics,
+    };
+    let access_control =
+        auth::AccessControl::new(input.scrape_auth.as_ref(), input.dashboard_auth.as_ref())?;
     let scope = web::scope("/metrics")
-        .service(get_prometheus_metrics)
-        .service(get_dashboard)
-        .service(get_dashboard_assets);
+        .app_data(web::Data::new(stream_settings))
+        .app_data(web::Data::new(access_control))
+        .service(
+            web::scope("")
+                .wrap(middleware::from_fn(auth::check_access))
+                .wrap(middleware::Compress::default())
+                .service(get_prometheus_metrics)
+                .service(exporters::get_influx_metrics)
+                .service(alerts::get_alerts)
+                .service(slo::get_slo_status)
+                .service(query::get_query)
+                .service(query::get_quantiles)
+                .service(cardinality::get_cardinality)
+                .service(stream::get_metrics_stream)
+                .service(websocket::get_metrics_ws)
+                .service(get_dashboard)
+                .service(get_dashboard_assets),
+        );
     Ok(scope)
 }
 
@@ -782,7 +1303,7 @@ mod tests {
     fn test_rate_tracker_new() {
         let tracker = RateTracker::new();
         assert!(tracker.samples.is_empty());
-        assert_eq!(tracker.window_duration, Duration::from_secs(2));
+        assert_eq!(tracker.window_duration, Duration::from_secs(10));
         assert_eq!(tracker.max_samples, 200);
     }
 
@@ -790,7 +1311,7 @@ mod tests {
     fn test_rate_tracker_default() {
         let tracker = RateTracker::default();
         assert!(tracker.samples.is_empty());
-        assert_eq!(tracker.window_duration, Duration::from_secs(2));
+        assert_eq!(tracker.window_duration, Duration::from_secs(10));
         assert_eq!(tracker.max_samples, 200);
     }
 
@@ -838,9 +1359,10 @@ mod tests {
         let rate = tracker.update(10.0);
 
         // Rate should be clamped to 0.0 for counters (negative rates become 0.0)
+        // and the reset starts a fresh sample window
         assert_eq!(rate, 0.0);
-        assert_eq!(tracker.samples.len(), 2);
-        assert_eq!(tracker.samples[1].0, 10.0);
+        assert_eq!(tracker.samples.len(), 1);
+        assert_eq!(tracker.samples[0].0, 10.0);
     }
 
     #[test]
@@ -972,6 +1494,76 @@ mod tests {
     fn test_dashboard_input_default() {
         let input = DashboardInput::default();
         assert!(input.buckets_for_metrics.is_empty());
+        assert!(input.stream_interval.is_none());
+        assert!(input.scrape_auth.is_none());
+        assert!(input.dashboard_auth.is_none());
+        assert!(input.exemplar_link.is_none());
+        assert!(input.otlp.is_none());
+        assert!(input.pushgateway.is_none());
+        assert!(input.remote_write.is_none());
+        assert!(input.statsd.is_none());
+        assert!(input.graphite.is_none());
+        assert!(input.influx.is_none());
+        assert!(input.scrape_targets.is_empty());
+        assert!(input.prometheus_registry.is_none());
+        assert!(input.alert_rules.is_empty());
+        assert!(input.alert_interval.is_none());
+        assert!(input.alert_notifications.is_none());
+        assert!(input.slos.is_empty());
+        assert!(input.history_interval.is_none());
+        assert!(input.history_retention.is_none());
+        assert!(input.exact_quantiles.is_empty());
+        assert!(input.histograms.is_empty());
+        assert!(input.global_labels.is_empty());
+        assert!(input.scoped_labels.is_empty());
+        assert!(input.relabel_rules.is_empty());
+        assert!(input.cardinality_limit.is_none());
+        assert!(input.cardinality_limits.is_empty());
+        assert!(input.series_idle_timeout.is_none());
+        assert!(!input.hide_self_metrics);
+    }
+
+    #[test]
+    fn test_invalid_input_is_rejected_by_validation() {
+        let invalid_rule = DashboardInput {
+            alert_rules: vec![AlertRule {
+                name: "Broken".to_string(),
+                expr: "> 5".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert!(validate_input(&invalid_rule).is_err());
+        let invalid_slo = DashboardInput {
+            slos: vec![Slo {
+                name: "api".to_string(),
+                objective: 2.0,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert!(validate_input(&invalid_slo).is_err());
+        let invalid_histogram = DashboardInput {
+            histograms: vec![(
+                metrics_exporter_prometheus::Matcher::Full("latency".to_string()),
+                HistogramConfig::Buckets(Vec::new()),
+            )],
+            ..Default::default()
+        };
+        assert!(validate_input(&invalid_histogram).is_err());
+        let reserved_label = DashboardInput {
+            global_labels: vec![("le".to_string(), "1".to_string())],
+            ..Default::default()
+        };
+        assert!(validate_input(&reserved_label).is_err());
+        let invalid_relabel_rule = DashboardInput {
+            relabel_rules: vec![RelabelRule::DropMetric {
+                name: "(".to_string(),
+            }],
+            ..Default::default()
+        };
+        assert!(validate_input(&invalid_relabel_rule).is_err());
+        assert!(validate_input(&DashboardInput::default()).is_ok());
     }
 
     #[test]
@@ -982,6 +1574,7 @@ mod tests {
                 metrics_exporter_prometheus::Matcher::Full("test_metric".to_string()),
                 buckets,
             )],
+            ..Default::default()
         };
 
         assert_eq!(input.buckets_for_metrics.len(), 1);
@@ -1079,4 +1672,85 @@ mod tests {
         assert_eq!(tracker.samples.len(), 1);
         assert!(tracker.samples[0].1 > start_time);
     }
+
+    #[test]
+    fn test_embedded_file_etag_revalidation() {
+        use actix_web::{http::StatusCode, http::header, test::TestRequest};
+
+        let response =
+            handle_embedded_file(&TestRequest::default().to_http_request(), "index.html");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().contains_key(header::CACHE_CONTROL));
+        let etag = response.headers().get(header::ETAG).unwrap().clone();
+
+        let revalidation = TestRequest::default()
+            .insert_header((header::IF_NONE_MATCH, etag))
+            .to_http_request();
+        let response = handle_embedded_file(&revalidation, "index.html");
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let stale = TestRequest::default()
+            .insert_header((header::IF_NONE_MATCH, "W/\"outdated\""))
+            .to_http_request();
+        let response = handle_embedded_file(&stale, "index.html");
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn test_prometheus_endpoint_negotiates_compression() {
+        use actix_web::{App, http::header, test};
+
+        let app = test::init_service(
+            App::new().service(create_metrics_actx_scope(&DashboardInput::default()).unwrap()),
+        )
+        .await;
+        metrics::counter!("compression_test_total").increment(1);
+
+        for encoding in ["gzip", "zstd"] {
+            let request = test::TestRequest::get()
+                .uri("/metrics/prometheus")
+                .insert_header((header::ACCEPT_ENCODING, encoding))
+                .to_request();
+            let response = test::call_service(&app, request).await;
+            assert_eq!(
+                response.headers().get(header::CONTENT_ENCODING).unwrap(),
+                encoding
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_prometheus_endpoint_negotiates_openmetrics() {
+        use actix_web::{App, http::header, test};
+
+        let app = test::init_service(
+            App::new().service(create_metrics_actx_scope(&DashboardInput::default()).unwrap()),
+        )
+        .await;
+        metrics::counter!("openmetrics_endpoint_test_total").increment(1);
+
+        let request = test::TestRequest::get()
+            .uri("/metrics/prometheus")
+            .insert_header((
+                header::ACCEPT,
+                "application/openmetrics-text; version=1.0.0",
+            ))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert!(
+            response
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .starts_with("application/openmetrics-text")
+        );
+
+        let body = test::read_body(response).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("# TYPE openmetrics_endpoint_test counter"));
+        assert!(body.contains("openmetrics_endpoint_test_created "));
+        assert!(body.ends_with("# EOF\n"));
+    }
 }
@@ -0,0 +1,237 @@
+//! # Log Metrics
+//!
+//! Integration with the `log` crate. [`MetricsLogger`] wraps any existing
+//! `log::Log` implementation (for example `env_logger`) and counts every log
+//! record passing through it, so error spikes become visible on the dashboard
+//! next to the rest of the application metrics.
+
+use crate::update_rate_tracker;
+use anyhow::Result;
+use log::{LevelFilter, Log, Metadata, Record};
+use metrics::{Unit, describe_counter};
+use std::{
+    collections::HashMap,
+    sync::{Mutex, Once, OnceLock},
+};
+
+/// Name of the counter incremented for every log record
+pub const LOG_RECORDS_METRIC: &str = "log_records_total";
+
+/// Absolute log record counts keyed by `(level, target)`
+///
+/// The rate tracker works on absolute values, so the running total for every
+/// label combination is kept here alongside the Prometheus counter.
+static LOG_RECORD_COUNTS: OnceLock<Mutex<HashMap<(log::Level, String), f64>>> = OnceLock::new();
+
+/// Guards the one-time description of the log records counter
+static DESCRIBE_LOG_RECORDS: Once = Once::new();
+
+/// A `log::Log` wrapper that records metrics for every log record
+///
+/// Each record is forwarded unchanged to the inner logger. Before forwarding,
+/// the `log_records_total{level,target}` counter is incremented and the
+/// matching `log_records_total_rate_per_sec{level,target}` gauge is updated,
+/// exactly like [`counter_with_rate!`](crate::counter_with_rate) does for
+/// application counters.
+///
+/// Records are counted whether or not the inner logger decides to print them,
+/// so only the global max level set with [`MetricsLogger::init`] limits what
+/// is counted.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use metrics_rs_dashboard_actix::MetricsLogger;
+///
+/// let env_logger = env_logger::Builder::from_default_env().build();
+/// let max_level = env_logger.filter();
+/// MetricsLogger::new(env_logger).init(max_level).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct MetricsLogger<L> {
+    inner: L,
+}
+
+impl<L: Log + 'static> MetricsLogger<L> {
+    /// Creates a new MetricsLogger forwarding records to `inner`
+    pub fn new(inner: L) -> Self {
+        Self { inner }
+    }
+
+    /// Installs this logger as the global `log` logger
+    ///
+    /// # Arguments
+    ///
+    /// * `max_level` - Maximum level passed to `log::set_max_level`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a global logger has already been set
+    pub fn init(self, max_level: LevelFilter) -> Result<()> {
+        log::set_boxed_logger(Box::new(self))
+            .map_err(|e| anyhow::anyhow!("Unable to set the global logger: {}", e))?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+}
+
+impl<L: Log> Log for MetricsLogger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        // Records filtered out by the inner logger aren't counted
+        if self.inner.enabled(record.metadata()) {
+            record_log_metrics(record.level(), record.target());
+        }
+        self.inner.log(record);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Increments the log record counter and updates its rate gauge
+///
+/// # Arguments
+///
+/// * `level` - Level of the log record
+/// * `target` - Target of the log record, usually the module path
+fn record_log_metrics(level: log::Level, target: &str) {
+    DESCRIBE_LOG_RECORDS.call_once(|| {
+        describe_counter!(
+            LOG_RECORDS_METRIC,
+            Unit::Count,
+            "Number of log records by level and target"
+        );
+    });
+
+    let level_label = level.as_str().to_lowercase();
+    let target_label = target.to_owned();
+
+    metrics::counter!(
+        LOG_RECORDS_METRIC,
+        "level" => level_label.clone(),
+        "target" => target_label.clone()
+    )
+    .increment(1);
+
+    let counts = LOG_RECORD_COUNTS.get_or_init(|| Mutex::new(HashMap::new()));
+    let absolute_value = if let Ok(mut counts) = counts.lock() {
+        let current = counts.entry((level, target_label.clone())).or_insert(0.0);
+        *current += 1.0;
+        *current
+    } else {
+        1.0
+    };
+
+    let tracker_key = format!("{}_{}_{}", LOG_RECORDS_METRIC, level_label, target_label);
+    let rate = update_rate_tracker(LOG_RECORDS_METRIC, absolute_value, tracker_key);
+    let display_rate = if rate < 0.001 { 0.001 } else { rate };
+
+    metrics::gauge!(
+        format!("{}_rate_per_sec", LOG_RECORDS_METRIC),
+        "level" => level_label,
+        "target" => target_label
+    )
+    .set(display_rate);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// Inner logger collecting the messages it receives
+    #[derive(Default)]
+    struct CollectingLogger {
+        messages: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Log for CollectingLogger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= log::Level::Info
+        }
+
+        fn log(&self, record: &Record) {
+            self.messages
+                .lock()
+                .unwrap()
+                .push(format!("{}", record.args()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn test_metrics_logger_forwards_records() {
+        let inner = CollectingLogger::default();
+        let messages = inner.messages.clone();
+        let logger = MetricsLogger::new(inner);
+
+        logger.log(
+            &Record::builder()
+                .args(format_args!("something failed"))
+                .level(log::Level::Error)
+                .target("log_metrics_test")
+                .build(),
+        );
+
+        assert_eq!(*messages.lock().unwrap(), vec!["something failed"]);
+    }
+
+    #[test]
+    fn test_metrics_logger_delegates_enabled() {
+        let logger = MetricsLogger::new(CollectingLogger::default());
+
+        let info = Metadata::builder().level(log::Level::Info).build();
+        let trace = Metadata::builder().level(log::Level::Trace).build();
+
+        assert!(logger.enabled(&info));
+        assert!(!logger.enabled(&trace));
+    }
+
+    #[test]
+    fn test_metrics_logger_skips_disabled_records() {
+        let logger = MetricsLogger::new(CollectingLogger::default());
+
+        for level in [log::Level::Info, log::Level::Trace] {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("filtered"))
+                    .level(level)
+                    .target("log_metrics_disabled_test")
+                    .build(),
+            );
+        }
+
+        let counts = LOG_RECORD_COUNTS.get().unwrap().lock().unwrap();
+        assert_eq!(
+            counts.get(&(log::Level::Info, "log_metrics_disabled_test".to_string())),
+            Some(&1.0)
+        );
+        assert_eq!(
+            counts.get(&(log::Level::Trace, "log_metrics_disabled_test".to_string())),
+            None
+        );
+    }
+
+    #[test]
+    fn test_record_log_metrics_counts_per_level_and_target() {
+        record_log_metrics(log::Level::Warn, "log_metrics_count_test");
+        record_log_metrics(log::Level::Warn, "log_metrics_count_test");
+        record_log_metrics(log::Level::Error, "log_metrics_count_test");
+
+        let counts = LOG_RECORD_COUNTS.get().unwrap().lock().unwrap();
+        assert_eq!(
+            counts.get(&(log::Level::Warn, "log_metrics_count_test".to_string())),
+            Some(&2.0)
+        );
+        assert_eq!(
+            counts.get(&(log::Level::Error, "log_metrics_count_test".to_string())),
+            Some(&1.0)
+        );
+    }
+}
@@ -0,0 +1,465 @@
+//! # OpenMetrics Exposition
+//!
+//! Renders the current metrics in the OpenMetrics text format for scrapers
+//! that send `Accept: application/openmetrics-text`. On top of the Prometheus
+//! text format it carries `# UNIT` metadata, `_created` timestamps for
+//! counters, histograms and summaries, exemplars of histogram buckets and
+//! the mandatory `# EOF` terminator.
+
+use crate::{
+    UNITS_FOR_METRICS,
+    exemplars::Exemplar,
+    labels,
+    snapshot::{MetricType, Series, SeriesValue, Snapshot, series_key},
+};
+use metrics::Key;
+use metrics_exporter_prometheus::formatting::{
+    sanitize_label_key, sanitize_label_value, sanitize_metric_name,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    sync::{Mutex, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Content type of OpenMetrics responses
+pub(crate) const OPENMETRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Creation timestamps of series keyed by [`series_key`]
+type SeriesCreation = HashMap<String, f64>;
+
+/// Unix timestamps of the first registration of each series, keyed by
+/// metric name and then by [`series_key`]
+static CREATED_AT: OnceLock<Mutex<HashMap<String, SeriesCreation>>> = OnceLock::new();
+
+/// Returns true if the `Accept` header prefers the OpenMetrics format over
+/// the classic text format
+///
+/// OpenMetrics has to be named explicitly and must not have `q=0`. Its
+/// quality is compared with the one of `text/plain`, taken from the most
+/// specific of `text/plain`, `text/*` and `*/*`; ties go to OpenMetrics.
+pub(crate) fn accepts_openmetrics(accept: &str) -> bool {
+    let mut openmetrics: Option<f64> = None;
+    // Specificity and quality of the best range matching `text/plain`
+    let mut text: Option<(u8, f64)> = None;
+    for media in accept.split(',') {
+        let mut parameters = media.split(';').map(str::trim);
+        let media_type = parameters.next().unwrap_or_default();
+        let quality = parameters
+            .filter_map(|parameter| parameter.strip_prefix("q="))
+            .find_map(|quality| quality.parse::<f64>().ok())
+            .unwrap_or(1.0);
+        let specificity = if media_type.eq_ignore_ascii_case("application/openmetrics-text") {
+            openmetrics = Some(openmetrics.map_or(quality, |best| best.max(quality)));
+            continue;
+        } else if media_type.eq_ignore_ascii_case("text/plain") {
+            2
+        } else if media_type.eq_ignore_ascii_case("text/*") {
+            1
+        } else if media_type == "*/*" {
+            0
+        } else {
+            continue;
+        };
+        text = match text {
+            Some((best, _)) if best > specificity => text,
+            Some((best, best_quality)) if best == specificity => {
+                Some((best, best_quality.max(quality)))
+            }
+            _ => Some((specificity, quality)),
+        };
+    }
+    let text = text.map_or(0.0, |(_, quality)| quality);
+    openmetrics.is_some_and(|quality| quality > 0.0 && quality >= text)
+}
+
+pub(crate) fn unix_now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs_f64())
+        .unwrap_or_default()
+}
+
+/// Remembers when a series was first registered
+///
+/// Called for every registration, so only the first call for a key stores
+/// a timestamp. Names and labels are sanitized the same way the Prometheus
+/// exporter renders them so the timestamp can be found again from a snapshot.
+pub(crate) fn record_creation(key: &Key) {
+    let labels: Vec<(&str, &str)> = key
+        .labels()
+        .map(|label| (label.key(), label.value()))
+        .collect();
+    // The rendered series carry the constant labels as well
+    let constant = labels::missing_labels(key.name(), &labels);
+    let labels: BTreeMap<String, String> = labels
+        .into_iter()
+        .chain(constant.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+        .map(|(k, v)| (sanitize_label_key(k), sanitize_label_value(v)))
+        .collect();
+    let created = CREATED_AT.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Ok(mut created) = created.lock() {
+        created
+            .entry(sanitize_metric_name(key.name()))
+            .or_default()
+            .entry(series_key(&labels))
+            .or_insert_with(unix_now);
+    }
+}
+
+/// Forgets the creation timestamps of series missing from `snapshot`,
+/// rendered at `rendered_at`, so that they don't outlive the series removed
+/// by the Prometheus recorder
+pub(crate) fn prune_creation_times(snapshot: &Snapshot, rendered_at: f64) {
+    if let Some(Ok(mut created)) = CREATED_AT.get().map(Mutex::lock) {
+        retain_rendered(&mut created, snapshot, rendered_at);
+    }
+}
+
+/// Keeps the series of the snapshot and those registered after it was taken
+fn retain_rendered(
+    created: &mut HashMap<String, SeriesCreation>,
+    snapshot: &Snapshot,
+    rendered_at: f64,
+) {
+    created.retain(|name, series| {
+        let family = snapshot.families.get(name);
+        series.retain(|key, created| {
+            *created >= rendered_at || family.is_some_and(|family| family.series.contains_key(key))
+        });
+        !series.is_empty()
+    });
+}
+
+/// Looks up the creation timestamp of a series
+fn created_at(name: &str, key: &str) -> Option<f64> {
+    let created = CREATED_AT.get()?.lock().ok()?;
+    created.get(name)?.get(key).copied()
+}
+
+/// Renders all recorded metrics in the OpenMetrics text format
+pub(crate) fn render_openmetrics() -> String {
+    let units = UNITS_FOR_METRICS
+        .get()
+        .and_then(|units| units.lock().ok().map(|units| units.clone()))
+        .unwrap_or_default();
+    render(&Snapshot::capture(), &units)
+}
+
+/// Renders a snapshot in the OpenMetrics text format
+///
+/// `# UNIT` is only emitted when the metric name ends with the unit, as the
+/// specification requires; units are kept out of names by default.
+fn render(snapshot: &Snapshot, units: &HashMap<String, String>) -> String {
+    let mut output = String::new();
+
+    for (name, family) in &snapshot.families {
+        // Counter families are named without the `_total` suffix of their samples
+        let family_name = match family.metric_type {
+            MetricType::Counter => name.strip_suffix("_total").unwrap_or(name),
+            _ => name.as_str(),
+        };
+        let metric_type = match family.metric_type {
+            MetricType::Counter => "counter",
+            MetricType::Gauge => "gauge",
+            MetricType::Histogram => "histogram",
+            MetricType::Summary => "summary",
+            MetricType::Untyped => "unknown",
+        };
+
+        let _ = writeln!(output, "# TYPE {} {}", family_name, metric_type);
+        if let Some(unit) = units.get(name)
+            && unit != "count"
+            && family_name.ends_with(&format!("_{}", unit))
+        {
+            let _ = writeln!(output, "# UNIT {} {}", family_name, unit);
+        }
+        if !family.help.is_empty() {
+            let _ = writeln!(
+                output,
+                "# HELP {} {}",
+                family_name,
+                escape_help(&family.help)
+            );
+        }
+
+        for (key, series) in &family.series {
+            let created = created_at(name, key);
+            write_series(
+                &mut output,
+                family_name,
+                family.metric_type,
+                series,
+                created,
+            );
+        }
+    }
+
+    output.push_str("# EOF\n");
+    output
+}
+
+/// Writes every sample line of a single series
+fn write_series(
+    output: &mut String,
+    family_name: &str,
+    metric_type: MetricType,
+    series: &Series,
+    created: Option<f64>,
+) {
+    let labels = &series.labels;
+    match &series.value {
+        SeriesValue::Single { value } => {
+            if metric_type == MetricType::Counter {
+                write_sample(output, family_name, "_total", labels, None, *value);
+            } else {
+                write_sample(output, family_name, "", labels, None, *value);
+                return;
+            }
+        }
+        SeriesValue::Histogram {
+            buckets,
+            count,
+            sum,
+            exemplars,
+        } => {
+            for (bound, bucket_count) in buckets {
+                let le = format_value(*bound);
+                let exemplar = exemplars.get(&le);
+                let le = Some(("le", le));
+                write_sample(output, family_name, "_bucket", labels, le, *bucket_count);
+                if let Some(exemplar) = exemplar {
+                    write_exemplar(output, exemplar);
+                }
+            }
+            write_sample(output, family_name, "_count", labels, None, *count);
+            write_sample(output, family_name, "_sum", labels, None, *sum);
+        }
+        SeriesValue::Summary {
+            quantiles,
+            count,
+            sum,
+        } => {
+            for (quantile, value) in quantiles {
+                let quantile = ("quantile", format_value(*quantile));
+                write_sample(output, family_name, "", labels, Some(quantile), *value);
+            }
+            write_sample(output, family_name, "_count", labels, None, *count);
+            write_sample(output, family_name, "_sum", labels, None, *sum);
+        }
+    }
+
+    if let Some(created) = created {
+        write_sample(output, family_name, "_created", labels, None, created);
+    }
+}
+
+/// Writes a single sample line, appending an extra label such as `le`
+fn write_sample(
+    output: &mut String,
+    family_name: &str,
+    suffix: &str,
+    labels: &BTreeMap<String, String>,
+    extra: Option<(&str, String)>,
+    value: f64,
+) {
+    // Label values come from the rendered exposition and are already escaped
+    let mut pairs: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, v))
+        .collect();
+    if let Some((k, v)) = extra {
+        pairs.push(format!("{}=\"{}\"", k, v));
+    }
+
+    output.push_str(family_name);
+    output.push_str(suffix);
+    if !pairs.is_empty() {
+        let _ = write!(output, "{{{}}}", pairs.join(","));
+    }
+    let _ = writeln!(output, " {}", format_value(value));
+}
+
+/// Appends an exemplar to the sample line that was just written
+fn write_exemplar(output: &mut String, exemplar: &Exemplar) {
+    let labels: Vec<String> = exemplar
+        .labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, v))
+        .collect();
+    output.pop();
+    let _ = writeln!(
+        output,
+        " # {{{}}} {} {}",
+        labels.join(","),
+        format_value(exemplar.value),
+        exemplar.timestamp
+    );
+}
+
+/// Formats a number using the OpenMetrics spelling of special values
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Escapes double quotes in help text
+///
+/// Backslashes and line feeds are already escaped in the Prometheus
+/// exposition, OpenMetrics additionally requires escaping double quotes.
+fn escape_help(help: &str) -> String {
+    help.replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPOSITION: &str = r#"# HELP requests_total Total requests
+# TYPE requests_total counter
+requests_total{endpoint="/a"} 3
+
+# TYPE response_size_bytes gauge
+response_size_bytes 512
+
+# TYPE latency histogram
+latency_bucket{le="0.1"} 1
+latency_bucket{le="+Inf"} 2
+latency_sum 0.3
+latency_count 2
+"#;
+
+    #[test]
+    fn test_accepts_openmetrics() {
+        assert!(accepts_openmetrics(
+            "application/openmetrics-text;version=1.0.0,text/plain;version=0.0.4;q=0.5"
+        ));
+        assert!(!accepts_openmetrics("text/plain;version=0.0.4"));
+        assert!(!accepts_openmetrics(
+            "application/openmetrics-text; q=0, text/plain;version=0.0.4"
+        ));
+        assert!(accepts_openmetrics(
+            "text/plain;q=0.5, application/openmetrics-text;version=1.0.0;q=0.9"
+        ));
+
+        // The preferred format wins, ties go to OpenMetrics
+        assert!(!accepts_openmetrics(
+            "application/openmetrics-text;q=0.1, text/plain;q=1"
+        ));
+        assert!(accepts_openmetrics(
+            "application/openmetrics-text;q=0.5, text/plain;q=0.5"
+        ));
+        assert!(!accepts_openmetrics(
+            "application/openmetrics-text;q=0.5, */*"
+        ));
+        assert!(accepts_openmetrics(
+            "application/openmetrics-text;q=0.5, text/plain;q=0.2, */*"
+        ));
+        assert!(!accepts_openmetrics("*/*"));
+        assert!(accepts_openmetrics(
+            "application/openmetrics-text;version=1.0.0,application/openmetrics-text;version=0.0.1;q=0.75,text/plain;version=0.0.4;q=0.5,*/*;q=0.1"
+        ));
+    }
+
+    #[test]
+    fn test_creation_times_of_removed_series_are_pruned() {
+        let mut created = HashMap::from([
+            (
+                "requests_total".to_string(),
+                HashMap::from([
+                    ("endpoint=\"/a\"".to_string(), 10.0),
+                    ("endpoint=\"/b\"".to_string(), 10.0),
+                    ("endpoint=\"/c\"".to_string(), 30.0),
+                ]),
+            ),
+            (
+                "removed_total".to_string(),
+                HashMap::from([(String::new(), 10.0)]),
+            ),
+        ]);
+        retain_rendered(&mut created, &Snapshot::parse(EXPOSITION), 20.0);
+
+        assert_eq!(created.len(), 1);
+        let mut keys: Vec<_> = created["requests_total"].keys().collect();
+        keys.sort();
+        // `/c` was registered after the exposition was rendered
+        assert_eq!(keys, ["endpoint=\"/a\"", "endpoint=\"/c\""]);
+    }
+
+    #[test]
+    fn test_render_families_and_eof() {
+        let units = HashMap::from([
+            ("response_size_bytes".to_string(), "bytes".to_string()),
+            ("latency".to_string(), "seconds".to_string()),
+        ]);
+        let text = render(&Snapshot::parse(EXPOSITION), &units);
+
+        assert!(text.contains("# TYPE requests counter\n"));
+        assert!(text.contains("# HELP requests Total requests\n"));
+        assert!(text.contains("requests_total{endpoint=\"/a\"} 3\n"));
+        assert!(text.contains("# UNIT response_size_bytes bytes\n"));
+        assert!(!text.contains("# UNIT latency"));
+        assert!(text.contains("latency_bucket{le=\"+Inf\"} 2\n"));
+        assert!(text.contains("latency_count 2\n"));
+        assert!(text.ends_with("# EOF\n"));
+    }
+
+    #[test]
+    fn test_render_created_timestamps() {
+        record_creation(&Key::from_parts(
+            "openmetrics_created_total",
+            vec![metrics::Label::new("kind", "test")],
+        ));
+        let snapshot = Snapshot::parse(
+            "# TYPE openmetrics_created_total counter\nopenmetrics_created_total{kind=\"test\"} 1\n",
+        );
+        let text = render(&snapshot, &HashMap::new());
+
+        let created = text
+            .lines()
+            .find_map(|line| line.strip_prefix("openmetrics_created_created{kind=\"test\"} "))
+            .unwrap();
+        assert!(created.parse::<f64>().unwrap() > 0.0);
+    }
+
+    #[test]
+    fn test_render_bucket_exemplars() {
+        let mut snapshot = Snapshot::parse(EXPOSITION);
+        let series = snapshot
+            .families
+            .get_mut("latency")
+            .and_then(|family| family.series.get_mut(""))
+            .unwrap();
+        if let SeriesValue::Histogram { exemplars, .. } = &mut series.value {
+            exemplars.insert(
+                "0.1".to_string(),
+                Exemplar {
+                    labels: BTreeMap::from([("trace_id".to_string(), "abc".to_string())]),
+                    value: 0.07,
+                    timestamp: 1700000000.5,
+                    link: None,
+                },
+            );
+        }
+        let text = render(&snapshot, &HashMap::new());
+
+        assert!(
+            text.contains("latency_bucket{le=\"0.1\"} 1 # {trace_id=\"abc\"} 0.07 1700000000.5\n")
+        );
+        assert!(text.contains("latency_bucket{le=\"+Inf\"} 2\n"));
+    }
+
+    #[test]
+    fn test_escape_help() {
+        assert_eq!(escape_help(r#"say "hi"\n"#), r#"say \"hi\"\n"#);
+    }
+}
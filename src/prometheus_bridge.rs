@@ -0,0 +1,182 @@
+//! # Prometheus Registry Bridge
+//!
+//! Appends the families of a `prometheus` crate registry, usually
+//! `prometheus::default_registry()`, to the rendered exposition. Libraries
+//! instrumented with the `prometheus` crate then show up on the same
+//! `/prometheus` endpoint, in the dashboard and in every exporter.
+//!
+//! Metrics recorded through the `metrics` facade take precedence: a bridged
+//! family whose name, or one of whose sample names, is already used locally
+//! is left out and reported once with a warning. Bridged series get the
+//! same [constant labels](crate::labels) as the recorded ones.
+
+use crate::labels;
+use log::warn;
+use prometheus::{Encoder, Registry, TextEncoder, proto::MetricFamily, proto::MetricType};
+use std::{
+    collections::HashSet,
+    sync::{Mutex, OnceLock},
+};
+
+/// Registry whose families are appended to the exposition
+static REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+/// Names of bridged families already reported as conflicting
+static REPORTED_CONFLICTS: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+
+/// Sets the registry to bridge
+pub(crate) fn configure(registry: Option<&Registry>) {
+    if let Some(registry) = registry {
+        let _ = REGISTRY.set(registry.clone());
+    }
+}
+
+/// Appends the bridged registry to an exposition rendered by the `metrics`
+/// recorder
+pub(crate) fn append_registry(exposition: &mut String) {
+    if let Some(registry) = REGISTRY.get() {
+        let mut families = registry.gather();
+        labels::label_families(&mut families);
+        append_families(exposition, families);
+    }
+}
+
+fn append_families(exposition: &mut String, families: Vec<MetricFamily>) {
+    let local = local_sample_names(exposition);
+    let (families, conflicts): (Vec<MetricFamily>, Vec<MetricFamily>) =
+        families.into_iter().partition(|family| {
+            sample_names(family)
+                .iter()
+                .all(|name| !local.contains(name))
+        });
+    report_conflicts(&conflicts);
+
+    let mut bridged = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&families, &mut bridged) {
+        warn!("Failed to encode the prometheus registry: {}", e);
+        return;
+    }
+    if bridged.is_empty() {
+        return;
+    }
+    if !exposition.is_empty() && !exposition.ends_with("\n\n") {
+        exposition.push('\n');
+    }
+    exposition.push_str(&String::from_utf8_lossy(&bridged));
+}
+
+/// Collects the sample names used by the families of an exposition
+fn local_sample_names(exposition: &str) -> HashSet<String> {
+    let mut names = HashSet::new();
+    for line in exposition.lines() {
+        let Some(declaration) = line.strip_prefix("# TYPE ") else {
+            continue;
+        };
+        let mut parts = declaration.split_whitespace();
+        if let (Some(name), Some(metric_type)) = (parts.next(), parts.next()) {
+            names.extend(family_sample_names(
+                name,
+                matches!(metric_type, "histogram" | "summary"),
+            ));
+        }
+    }
+    names
+}
+
+/// Sample names produced by a bridged family
+fn sample_names(family: &MetricFamily) -> Vec<String> {
+    let aggregated = matches!(
+        family.get_field_type(),
+        MetricType::HISTOGRAM | MetricType::SUMMARY
+    );
+    family_sample_names(family.name(), aggregated)
+}
+
+fn family_sample_names(name: &str, aggregated: bool) -> Vec<String> {
+    if aggregated {
+        ["", "_bucket", "_sum", "_count"]
+            .iter()
+            .map(|suffix| format!("{}{}", name, suffix))
+            .collect()
+    } else {
+        vec![name.to_owned()]
+    }
+}
+
+/// Logs each conflicting family the first time it is left out
+fn report_conflicts(conflicts: &[MetricFamily]) {
+    if conflicts.is_empty() {
+        return;
+    }
+    let reported = REPORTED_CONFLICTS.get_or_init(|| Mutex::new(HashSet::new()));
+    let Ok(mut reported) = reported.lock() else {
+        return;
+    };
+    for family in conflicts {
+        if reported.insert(family.name().to_owned()) {
+            warn!(
+                "Metric '{}' of the prometheus registry conflicts with a metric of the same name and is not exported",
+                family.name()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::Snapshot;
+    use prometheus::{Histogram, HistogramOpts, IntCounterVec, Opts};
+
+    const LOCAL: &str = "# TYPE requests_total counter\nrequests_total 3\n\n\
+                         # TYPE latency histogram\nlatency_bucket{le=\"+Inf\"} 1\nlatency_sum 0.2\nlatency_count 1\n";
+
+    fn registry() -> Registry {
+        let registry = Registry::new();
+        let jobs =
+            IntCounterVec::new(Opts::new("jobs_total", "Jobs processed"), &["queue"]).unwrap();
+        jobs.with_label_values(&["default"]).inc_by(4);
+        let requests =
+            IntCounterVec::new(Opts::new("requests_total", "Requests"), &["path"]).unwrap();
+        requests.with_label_values(&["/a"]).inc();
+        let latency_count =
+            Histogram::with_opts(HistogramOpts::new("latency_count", "Clash")).unwrap();
+        latency_count.observe(1.0);
+        registry.register(Box::new(jobs)).unwrap();
+        registry.register(Box::new(requests)).unwrap();
+        registry.register(Box::new(latency_count)).unwrap();
+        registry
+    }
+
+    #[test]
+    fn test_bridged_families_are_appended() {
+        let mut exposition = LOCAL.to_string();
+        append_families(&mut exposition, registry().gather());
+
+        let snapshot = Snapshot::parse(&exposition);
+        let jobs = &snapshot.families["jobs_total"];
+        assert_eq!(jobs.help, "Jobs processed");
+        assert_eq!(jobs.series.len(), 1);
+        assert_eq!(snapshot.families["requests_total"].series.len(), 1);
+    }
+
+    #[test]
+    fn test_conflicting_families_are_left_out() {
+        let mut exposition = LOCAL.to_string();
+        append_families(&mut exposition, registry().gather());
+
+        assert!(!exposition.contains("path=\"/a\""));
+        assert!(!exposition.contains("latency_count_bucket"));
+        assert_eq!(exposition.matches("# TYPE requests_total").count(), 1);
+    }
+
+    #[test]
+    fn test_local_sample_names() {
+        let names = local_sample_names(LOCAL);
+
+        assert!(names.contains("requests_total"));
+        assert!(names.contains("latency_bucket"));
+        assert!(names.contains("latency_count"));
+        assert!(!names.contains("requests_total_count"));
+    }
+}
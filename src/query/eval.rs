@@ -0,0 +1,458 @@
+//! Evaluation of query expressions against the history
+//!
+//! Expressions evaluate to a scalar or to an instant vector of labelled
+//! samples, following the PromQL semantics for the supported subset.
+
+use super::parser::{AggregateOp, BinaryOp, Expr, Grouping, RangeFunction};
+use crate::{
+    history::{History, Point},
+    selector::Selector,
+};
+use anyhow::{Result, anyhow, bail};
+use chrono::{DateTime, Utc};
+use std::{
+    collections::{BTreeMap, HashMap, hash_map::Entry},
+    time::Duration,
+};
+
+/// How far back a selector looks for the latest value of a series
+pub(crate) const LOOKBACK: Duration = Duration::from_secs(300);
+
+/// Label holding the metric name of selected series
+pub(crate) const NAME_LABEL: &str = "__name__";
+
+pub(crate) type Labels = BTreeMap<String, String>;
+
+/// A labelled value of an instant vector
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Sample {
+    pub labels: Labels,
+    pub value: f64,
+}
+
+/// Result of evaluating an expression at one point in time
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Scalar(f64),
+    Vector(Vec<Sample>),
+}
+
+/// Evaluates `expr` at time `at`
+///
+/// # Errors
+///
+/// Returns an error if an operand has the wrong type, e.g. an aggregation
+/// over a scalar, or if vector arithmetic matches several series
+pub(crate) fn evaluate(expr: &Expr, history: &History, at: DateTime<Utc>) -> Result<Value> {
+    Ok(match expr {
+        Expr::Number(number) => Value::Scalar(*number),
+        Expr::Vector(selector) => Value::Vector(
+            select(history, selector, at, LOOKBACK)?
+                .filter_map(|(labels, points)| {
+                    points.last().map(|(_, value)| Sample {
+                        labels,
+                        value: *value,
+                    })
+                })
+                .collect(),
+        ),
+        Expr::Range {
+            function,
+            selector,
+            range,
+        } => Value::Vector(
+            select(history, selector, at, *range)?
+                .filter_map(|(mut labels, points)| {
+                    let rate = rate(&points)?;
+                    labels.remove(NAME_LABEL);
+                    let value = match function {
+                        RangeFunction::Rate => rate,
+                        RangeFunction::Increase => rate * range.as_secs_f64(),
+                    };
+                    Some(Sample { labels, value })
+                })
+                .collect(),
+        ),
+        Expr::HistogramQuantile { quantile, buckets } => {
+            let Value::Scalar(quantile) = evaluate(quantile, history, at)? else {
+                bail!("the quantile of histogram_quantile() must be a scalar");
+            };
+            let Value::Vector(buckets) = evaluate(buckets, history, at)? else {
+                bail!("histogram_quantile() needs a vector of buckets");
+            };
+            Value::Vector(histogram_quantile(quantile, buckets))
+        }
+        Expr::Aggregate { op, grouping, expr } => {
+            let Value::Vector(samples) = evaluate(expr, history, at)? else {
+                bail!("aggregations need a vector");
+            };
+            Value::Vector(aggregate(*op, grouping.as_ref(), samples))
+        }
+        Expr::Binary { op, lhs, rhs } => binary(
+            *op,
+            evaluate(lhs, history, at)?,
+            evaluate(rhs, history, at)?,
+        )?,
+        Expr::Negate(expr) => binary(
+            BinaryOp::Mul,
+            Value::Scalar(-1.0),
+            evaluate(expr, history, at)?,
+        )?,
+    })
+}
+
+/// Returns the labels and values within `(at - range, at]` of every series
+/// matching the selector, with the metric name as `__name__`
+///
+/// Fails if the start of the range is out of the supported time range.
+fn select<'a>(
+    history: &'a History,
+    selector: &'a Selector,
+    at: DateTime<Utc>,
+    range: Duration,
+) -> Result<impl Iterator<Item = (Labels, Vec<Point>)> + 'a> {
+    let from = chrono::Duration::from_std(range)
+        .ok()
+        .and_then(|range| at.checked_sub_signed(range))
+        .ok_or_else(|| anyhow!("range {:?} before {} is out of range", range, at))?;
+    Ok(history
+        .series()
+        .filter(|(id, _)| id.name == selector.metric && selector.matches_labels(&id.labels))
+        .filter_map(move |(id, points)| {
+            let points: Vec<Point> = points
+                .iter()
+                .filter(|(time, _)| *time > from && *time <= at)
+                .copied()
+                .collect();
+            if points.is_empty() {
+                return None;
+            }
+            let mut labels = id.labels.clone();
+            labels.insert(NAME_LABEL.to_owned(), id.name.clone());
+            Some((labels, points))
+        }))
+}
+
+/// Per-second increase between the first and last point, treating any
+/// decrease as a counter reset. Unlike Prometheus, no extrapolation to the
+/// edges of the range is done.
+fn rate(points: &[Point]) -> Option<f64> {
+    let (first, last) = (points.first()?, points.last()?);
+    let seconds = (last.0 - first.0).num_milliseconds() as f64 / 1000.0;
+    if seconds <= 0.0 {
+        return None;
+    }
+    let increase: f64 = points
+        .windows(2)
+        .map(|pair| {
+            let (previous, current) = (pair[0].1, pair[1].1);
+            if current < previous {
+                current
+            } else {
+                current - previous
+            }
+        })
+        .sum();
+    Some(increase / seconds)
+}
+
+/// Computes a quantile from cumulative `(upper bound, count)` buckets the
+/// way Prometheus does, interpolating linearly within the matching bucket
+///
+/// Returns NaN without observations or without a `+Inf` bucket.
+pub(crate) fn bucket_quantile(quantile: f64, buckets: &[(f64, f64)]) -> f64 {
+    if quantile < 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    if quantile > 1.0 {
+        return f64::INFINITY;
+    }
+    let mut buckets = buckets.to_vec();
+    buckets.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let Some(&(last_bound, observations)) = buckets.last() else {
+        return f64::NAN;
+    };
+    if last_bound != f64::INFINITY || buckets.len() < 2 || observations == 0.0 {
+        return f64::NAN;
+    }
+    // Counts of rates over buckets scraped at slightly different times may
+    // not be monotonic
+    for index in 1..buckets.len() {
+        buckets[index].1 = buckets[index].1.max(buckets[index - 1].1);
+    }
+
+    let rank = quantile * observations;
+    let index = buckets
+        .iter()
+        .position(|(_, count)| *count >= rank)
+        .unwrap_or(buckets.len() - 1);
+    if index == buckets.len() - 1 {
+        return buckets[index - 1].0;
+    }
+    let (upper, count) = buckets[index];
+    if index == 0 && upper <= 0.0 {
+        return upper;
+    }
+    let (lower, below) = match index {
+        0 => (0.0, 0.0),
+        _ => buckets[index - 1],
+    };
+    lower + (upper - lower) * ((rank - below) / (count - below))
+}
+
+/// Groups bucket samples by their labels without `le` and computes the
+/// quantile of each group
+fn histogram_quantile(quantile: f64, samples: Vec<Sample>) -> Vec<Sample> {
+    let mut groups: BTreeMap<Labels, Vec<(f64, f64)>> = BTreeMap::new();
+    for mut sample in samples {
+        let Some(bound) = sample
+            .labels
+            .remove("le")
+            .and_then(|le| le.parse::<f64>().ok())
+        else {
+            continue;
+        };
+        sample.labels.remove(NAME_LABEL);
+        groups
+            .entry(sample.labels)
+            .or_default()
+            .push((bound, sample.value));
+    }
+    groups
+        .into_iter()
+        .map(|(labels, buckets)| Sample {
+            labels,
+            value: bucket_quantile(quantile, &buckets),
+        })
+        .collect()
+}
+
+fn aggregate(op: AggregateOp, grouping: Option<&Grouping>, samples: Vec<Sample>) -> Vec<Sample> {
+    let mut groups: BTreeMap<Labels, Vec<f64>> = BTreeMap::new();
+    for sample in samples {
+        let labels = match grouping {
+            None => Labels::new(),
+            Some(Grouping::By(names)) => sample
+                .labels
+                .into_iter()
+                .filter(|(name, _)| names.contains(name))
+                .collect(),
+            Some(Grouping::Without(names)) => sample
+                .labels
+                .into_iter()
+                .filter(|(name, _)| name != NAME_LABEL && !names.contains(name))
+                .collect(),
+        };
+        groups.entry(labels).or_default().push(sample.value);
+    }
+    groups
+        .into_iter()
+        .map(|(labels, values)| {
+            let count = values.len() as f64;
+            let value = match op {
+                AggregateOp::Sum => values.iter().sum(),
+                AggregateOp::Avg => values.iter().sum::<f64>() / count,
+                AggregateOp::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+                AggregateOp::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+                AggregateOp::Count => count,
+            };
+            Sample { labels, value }
+        })
+        .collect()
+}
+
+/// Applies an arithmetic operator; vectors are matched one-to-one on their
+/// labels without the metric name, which is dropped from the result
+fn binary(op: BinaryOp, lhs: Value, rhs: Value) -> Result<Value> {
+    let without_name = |mut sample: Sample| {
+        sample.labels.remove(NAME_LABEL);
+        sample
+    };
+    Ok(match (lhs, rhs) {
+        (Value::Scalar(lhs), Value::Scalar(rhs)) => Value::Scalar(op.apply(lhs, rhs)),
+        (Value::Vector(lhs), Value::Scalar(rhs)) => Value::Vector(
+            lhs.into_iter()
+                .map(without_name)
+                .map(|sample| Sample {
+                    value: op.apply(sample.value, rhs),
+                    ..sample
+                })
+                .collect(),
+        ),
+        (Value::Scalar(lhs), Value::Vector(rhs)) => Value::Vector(
+            rhs.into_iter()
+                .map(without_name)
+                .map(|sample| Sample {
+                    value: op.apply(lhs, sample.value),
+                    ..sample
+                })
+                .collect(),
+        ),
+        (Value::Vector(lhs), Value::Vector(rhs)) => {
+            let mut right = HashMap::new();
+            for sample in rhs.into_iter().map(without_name) {
+                match right.entry(sample.labels) {
+                    Entry::Occupied(entry) => {
+                        bail!(
+                            "several series with labels {:?} on the right-hand side",
+                            entry.key()
+                        )
+                    }
+                    Entry::Vacant(entry) => entry.insert(sample.value),
+                };
+            }
+            let mut matched: BTreeMap<Labels, f64> = BTreeMap::new();
+            for sample in lhs.into_iter().map(without_name) {
+                let Some(rhs) = right.get(&sample.labels) else {
+                    continue;
+                };
+                if matched.contains_key(&sample.labels) {
+                    bail!(
+                        "several series with labels {:?} on the left-hand side",
+                        sample.labels
+                    );
+                }
+                matched.insert(sample.labels, op.apply(sample.value, *rhs));
+            }
+            Value::Vector(
+                matched
+                    .into_iter()
+                    .map(|(labels, value)| Sample { labels, value })
+                    .collect(),
+            )
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{query::parser::parse, snapshot::Snapshot};
+
+    /// History with a request counter growing by 10/s per path and a
+    /// latency histogram, sampled every 10 seconds for one minute
+    fn history(start: DateTime<Utc>) -> History {
+        let mut history = History::new(Duration::from_secs(3600));
+        for step in 0..=6 {
+            let requests = step * 100;
+            history.record(
+                Snapshot::parse(&format!(
+                    "# TYPE requests_total counter\n\
+                     requests_total{{path=\"/a\",service=\"api\"}} {requests}\n\
+                     requests_total{{path=\"/b\",service=\"api\"}} {requests}\n\
+                     # TYPE errors_total counter\n\
+                     errors_total{{path=\"/a\",service=\"api\"}} {}\n\
+                     # TYPE latency histogram\n\
+                     latency_bucket{{le=\"0.1\"}} {}\n\
+                     latency_bucket{{le=\"0.5\"}} {}\n\
+                     latency_bucket{{le=\"+Inf\"}} {requests}\n\
+                     latency_sum 0\n\
+                     latency_count {requests}\n",
+                    step * 10,
+                    step * 50,
+                    step * 90,
+                ))
+                .samples(),
+                start + chrono::Duration::seconds(step * 10),
+            );
+        }
+        history
+    }
+
+    fn query(text: &str) -> Value {
+        let start = Utc::now();
+        let history = history(start);
+        evaluate(
+            &parse(text, Duration::MAX).unwrap(),
+            &history,
+            start + chrono::Duration::seconds(60),
+        )
+        .unwrap()
+    }
+
+    fn values(value: Value) -> Vec<(Labels, f64)> {
+        let Value::Vector(samples) = value else {
+            panic!("expected a vector");
+        };
+        samples
+            .into_iter()
+            .map(|sample| (sample.labels, sample.value))
+            .collect()
+    }
+
+    fn labels(pairs: &[(&str, &str)]) -> Labels {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_selectors_and_aggregations() {
+        let mut selected = values(query(r#"requests_total{path="/a"}"#));
+        assert_eq!(selected.len(), 1);
+        let (series, value) = selected.remove(0);
+        assert_eq!(series[NAME_LABEL], "requests_total");
+        assert_eq!(value, 600.0);
+
+        assert_eq!(
+            values(query("sum by (service) (rate(requests_total[1m]))")),
+            vec![(labels(&[("service", "api")]), 20.0)]
+        );
+        assert_eq!(
+            values(query("count without (path) (requests_total)")),
+            vec![(labels(&[("service", "api")]), 2.0)]
+        );
+        assert_eq!(query("2 * (1 + 2) - 1"), Value::Scalar(5.0));
+    }
+
+    #[test]
+    fn test_arithmetic_between_series() {
+        assert_eq!(
+            values(query("errors_total / requests_total * 100")),
+            vec![(labels(&[("path", "/a"), ("service", "api")]), 10.0)]
+        );
+        assert_eq!(
+            values(query("-increase(errors_total[30s])")),
+            vec![(labels(&[("path", "/a"), ("service", "api")]), -30.0)]
+        );
+    }
+
+    #[test]
+    fn test_histogram_quantile() {
+        let quantiles = values(query("histogram_quantile(0.5, rate(latency_bucket[1m]))"));
+        // Half of the observations fall into the first bucket
+        assert_eq!(quantiles, vec![(Labels::new(), 0.1)]);
+
+        assert_eq!(
+            bucket_quantile(0.95, &[(1.0, 90.0), (f64::INFINITY, 100.0)]),
+            1.0
+        );
+        assert_eq!(
+            bucket_quantile(0.5, &[(1.0, 0.0), (2.0, 10.0), (f64::INFINITY, 10.0)]),
+            1.5
+        );
+        assert!(bucket_quantile(0.5, &[(1.0, 0.0)]).is_nan());
+        assert_eq!(bucket_quantile(1.5, &[]), f64::INFINITY);
+    }
+
+    #[test]
+    fn test_rate_handles_counter_resets() {
+        let start = Utc::now();
+        let points = [
+            (start, 50.0),
+            (start + chrono::Duration::seconds(10), 100.0),
+            (start + chrono::Duration::seconds(20), 20.0),
+        ];
+        assert_eq!(rate(&points), Some(3.5));
+        assert_eq!(rate(&points[..1]), None);
+    }
+
+    #[test]
+    fn test_type_errors() {
+        let history = History::new(Duration::from_secs(60));
+        let evaluate = |text| evaluate(&parse(text, Duration::MAX).unwrap(), &history, Utc::now());
+        assert!(evaluate("sum(1)").is_err());
+        assert!(evaluate("histogram_quantile(up, latency_bucket)").is_err());
+    }
+}
@@ -0,0 +1,417 @@
+//! # Query API
+//!
+//! Evaluates expressions in a small subset of PromQL against the server-side
+//! [history](crate::history), so that derived series can be charted without
+//! an external Prometheus. Supported are:
+//!
+//! - selectors with label matchers: `http_requests_total{status=~"5.."}`
+//! - `rate(selector[5m])` and `increase(selector[5m])`
+//! - `sum`, `avg`, `min`, `max` and `count`, optionally `by (...)` or `without (...)`
+//! - `histogram_quantile(0.99, rate(latency_bucket[5m]))`
+//! - `+`, `-`, `*` and `/` between numbers and series
+//!
+//! The endpoint at `/metrics/api/query` answers in the format of the
+//! Prometheus HTTP API: instant queries take an optional `time`, range
+//! queries take `start`, `end` and `step`. `/metrics/api/quantiles` builds
+//! on the same evaluation to serve percentiles of histograms over time.
+
+mod eval;
+mod parser;
+mod quantiles;
+
+pub(crate) use quantiles::{DEFAULT_QUANTILES, get_quantiles};
+
+use crate::history::{History, Point, with_history};
+use actix_web::{HttpResponse, Responder, web};
+use anyhow::{Result, anyhow, bail};
+use chrono::{DateTime, Utc};
+use eval::{Labels, Value};
+use parser::Expr;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, time::Duration};
+
+/// Maximum number of steps of a range query
+const MAX_STEPS: i64 = 11_000;
+
+/// Maximum number of steps times selected series of a range query
+const MAX_POINTS: i64 = 1_000_000;
+
+/// Parameters of a query request
+#[derive(Debug, Deserialize)]
+pub(crate) struct QueryParams {
+    query: String,
+    /// Evaluation time of an instant query, defaulting to now
+    time: Option<String>,
+    start: Option<String>,
+    end: Option<String>,
+    step: Option<String>,
+}
+
+/// A timestamped value, serialized as `[unix seconds, "value"]`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct TimedValue(f64, String);
+
+impl TimedValue {
+    fn new(at: DateTime<Utc>, value: f64) -> Self {
+        Self(at.timestamp_millis() as f64 / 1000.0, format_value(value))
+    }
+}
+
+/// A series of an instant or range query result
+#[derive(Debug, PartialEq, Serialize)]
+struct ResultSeries {
+    metric: Labels,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<TimedValue>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    values: Vec<TimedValue>,
+}
+
+/// Result of a query in the shape of the Prometheus HTTP API
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "resultType", content = "result", rename_all = "lowercase")]
+enum QueryData {
+    Scalar(TimedValue),
+    Vector(Vec<ResultSeries>),
+    Matrix(Vec<ResultSeries>),
+}
+
+/// Formats a value the way the Prometheus HTTP API does
+fn format_value(value: f64) -> String {
+    match value {
+        f64::INFINITY => "+Inf".to_string(),
+        f64::NEG_INFINITY => "-Inf".to_string(),
+        value if value.is_nan() => "NaN".to_string(),
+        value => value.to_string(),
+    }
+}
+
+/// Parses a timestamp given in (fractional) unix seconds or RFC 3339
+fn parse_time(text: &str) -> Result<DateTime<Utc>> {
+    if let Ok(seconds) = text.parse::<f64>() {
+        return DateTime::from_timestamp_millis((seconds * 1000.0) as i64)
+            .ok_or_else(|| anyhow!("timestamp '{}' out of range", text));
+    }
+    DateTime::parse_from_rfc3339(text)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| anyhow!("invalid timestamp '{}'", text))
+}
+
+/// Parses a step given in (fractional) seconds or as a duration like `15s`
+fn parse_step(text: &str) -> Result<Duration> {
+    let step = match text.parse::<f64>() {
+        Ok(seconds) if seconds > 0.0 => Duration::try_from_secs_f64(seconds)
+            .map_err(|_| anyhow!("step '{}' out of range", text))?,
+        Ok(_) => bail!("step must be positive"),
+        Err(_) => crate::alerts::parse_duration(text)?,
+    };
+    if step.is_zero() {
+        bail!("step must be positive");
+    }
+    Ok(step)
+}
+
+/// Evaluates an instant query at `at`
+fn instant_query(expr: &Expr, history: &History, at: DateTime<Utc>) -> Result<QueryData> {
+    Ok(match eval::evaluate(expr, history, at)? {
+        Value::Scalar(value) => QueryData::Scalar(TimedValue::new(at, value)),
+        Value::Vector(samples) => QueryData::Vector(
+            samples
+                .into_iter()
+                .map(|sample| ResultSeries {
+                    metric: sample.labels,
+                    value: Some(TimedValue::new(at, sample.value)),
+                    values: Vec::new(),
+                })
+                .collect(),
+        ),
+    })
+}
+
+/// Evaluates an expression at every step between `start` and `end`, both
+/// included, collecting the values of each series
+fn evaluate_range(
+    expr: &Expr,
+    history: &History,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    step: Duration,
+) -> Result<BTreeMap<Labels, Vec<Point>>> {
+    let step = chrono::Duration::from_std(step)?;
+    if end < start {
+        bail!("end must not be before start");
+    }
+    if (end - start).num_milliseconds() / step.num_milliseconds().max(1) > MAX_STEPS {
+        bail!("more than {} steps requested, increase the step", MAX_STEPS);
+    }
+    let steps = (end - start).num_milliseconds() / step.num_milliseconds().max(1) + 1;
+    let points = steps.saturating_mul(history.series().count().max(1) as i64);
+    if points > MAX_POINTS {
+        bail!(
+            "{} steps over the selected series exceed {} points, increase the step or narrow the selectors",
+            steps,
+            MAX_POINTS
+        );
+    }
+
+    let mut series: BTreeMap<Labels, Vec<Point>> = BTreeMap::new();
+    let mut at = start;
+    while at <= end {
+        match eval::evaluate(expr, history, at)? {
+            Value::Scalar(value) => series.entry(Labels::new()).or_default().push((at, value)),
+            Value::Vector(samples) => {
+                for sample in samples {
+                    series
+                        .entry(sample.labels)
+                        .or_default()
+                        .push((at, sample.value));
+                }
+            }
+        }
+        // The end is within the supported time range, so there are no
+        // more steps once the next one overflows
+        match at.checked_add_signed(step) {
+            Some(next) => at = next,
+            None => break,
+        }
+    }
+    Ok(series)
+}
+
+/// Evaluates a range query, returning a matrix
+fn range_query(
+    expr: &Expr,
+    history: &History,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    step: Duration,
+) -> Result<QueryData> {
+    Ok(QueryData::Matrix(
+        evaluate_range(expr, history, start, end, step)?
+            .into_iter()
+            .map(|(metric, points)| ResultSeries {
+                metric,
+                value: None,
+                values: points
+                    .into_iter()
+                    .map(|(at, value)| TimedValue::new(at, value))
+                    .collect(),
+            })
+            .collect(),
+    ))
+}
+
+/// Parses the query and copies the series it reads, so that it can be
+/// evaluated without holding the lock of the history
+fn prepare(query: &str, history: &History) -> Result<(Expr, History)> {
+    let expr = parser::parse(query, history.retention())?;
+    let selectors = expr.selectors();
+    let selected = history.filtered(|id| {
+        selectors
+            .iter()
+            .any(|selector| selector.metric == id.name && selector.matches_labels(&id.labels))
+    });
+    Ok((expr, selected))
+}
+
+/// Evaluates the query described by the request parameters
+fn run_query(params: &QueryParams, expr: &Expr, history: &History) -> Result<QueryData> {
+    match (&params.start, &params.end) {
+        (Some(start), Some(end)) => {
+            let step = params
+                .step
+                .as_deref()
+                .ok_or_else(|| anyhow!("range queries need a step"))?;
+            range_query(
+                expr,
+                history,
+                parse_time(start)?,
+                parse_time(end)?,
+                parse_step(step)?,
+            )
+        }
+        (None, None) => {
+            let at = match &params.time {
+                Some(time) => parse_time(time)?,
+                None => Utc::now(),
+            };
+            instant_query(expr, history, at)
+        }
+        _ => bail!("range queries need both start and end"),
+    }
+}
+
+/// Response envelope of the API endpoints, as used by the Prometheus HTTP API
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub(crate) enum ApiResponse<T> {
+    Success {
+        data: T,
+    },
+    Error {
+        #[serde(rename = "errorType")]
+        error_type: &'static str,
+        error: String,
+    },
+}
+
+impl<T: Serialize> ApiResponse<T> {
+    /// Responds with the data, or with `400 Bad Request` and the error
+    pub(crate) fn respond(result: Result<T>) -> HttpResponse {
+        match result {
+            Ok(data) => HttpResponse::Ok().json(ApiResponse::Success { data }),
+            Err(e) => HttpResponse::BadRequest().json(ApiResponse::<T>::Error {
+                error_type: "bad_data",
+                error: format!("{:#}", e),
+            }),
+        }
+    }
+}
+
+/// Endpoint evaluating a query over the recorded history
+///
+/// # Returns
+///
+/// The result as JSON in the format of the Prometheus HTTP API, or
+/// `400 Bad Request` with the error if the query is invalid
+#[actix_web::get("/api/query")]
+pub(crate) async fn get_query(params: web::Query<QueryParams>) -> impl Responder {
+    let params = params.into_inner();
+    // Evaluation may take a while, so it runs on the blocking thread pool
+    // and the history is only locked to copy the selected series
+    let result = web::block(move || {
+        let (expr, history) = with_history(|history| prepare(&params.query, history))?;
+        run_query(&params, &expr, &history)
+    })
+    .await;
+    ApiResponse::respond(
+        result
+            .map_err(anyhow::Error::from)
+            .and_then(|result| result),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::Snapshot;
+
+    fn query(params: &QueryParams, history: &History) -> Result<QueryData> {
+        let (expr, history) = prepare(&params.query, history)?;
+        run_query(params, &expr, &history)
+    }
+
+    fn params(query: &str) -> QueryParams {
+        QueryParams {
+            query: query.to_string(),
+            time: None,
+            start: None,
+            end: None,
+            step: None,
+        }
+    }
+
+    #[test]
+    fn test_range_query() {
+        let start = DateTime::from_timestamp(Utc::now().timestamp(), 0).unwrap();
+        let mut history = History::new(Duration::from_secs(3600));
+        for step in 0..3 {
+            history.record(
+                Snapshot::parse(&format!(
+                    "# TYPE queue gauge\nqueue{{job=\"a\"}} {}\n",
+                    step
+                ))
+                .samples(),
+                start + chrono::Duration::seconds(step * 10),
+            );
+        }
+
+        let data = query(
+            &QueryParams {
+                start: Some(start.timestamp().to_string()),
+                end: Some((start + chrono::Duration::seconds(20)).to_rfc3339()),
+                step: Some("10s".to_string()),
+                ..params("queue * 2")
+            },
+            &history,
+        )
+        .unwrap();
+        let QueryData::Matrix(series) = data else {
+            panic!("expected a matrix");
+        };
+        assert_eq!(series.len(), 1);
+        assert_eq!(series[0].metric["job"], "a");
+        let values: Vec<&str> = series[0].values.iter().map(|v| v.1.as_str()).collect();
+        assert_eq!(values, ["0", "2", "4"]);
+
+        let json = serde_json::to_value(ApiResponse::Success {
+            data: query(&params("1 / 0"), &history).unwrap(),
+        })
+        .unwrap();
+        assert_eq!(json["status"], "success");
+        assert_eq!(json["data"]["resultType"], "scalar");
+        assert_eq!(json["data"]["result"][1], "+Inf");
+    }
+
+    #[test]
+    fn test_invalid_requests() {
+        let history = History::new(Duration::from_secs(60));
+        assert!(query(&params("sum("), &history).is_err());
+        let missing_step = QueryParams {
+            start: Some("0".to_string()),
+            end: Some("100".to_string()),
+            ..params("up")
+        };
+        assert!(query(&missing_step, &history).is_err());
+        let too_many_steps = QueryParams {
+            step: Some("0.001".to_string()),
+            ..missing_step
+        };
+        assert!(query(&too_many_steps, &history).is_err());
+        for step in ["1e30", "inf", "NaN", "-1"] {
+            assert!(parse_step(step).is_err(), "step {}", step);
+        }
+
+        // Ranges longer than the retention and times at the edges of the
+        // supported range are refused instead of overflowing
+        assert!(query(&params("rate(up[4000000000d])"), &history).is_err());
+        let earliest = QueryParams {
+            time: Some(DateTime::<Utc>::MIN_UTC.timestamp().to_string()),
+            ..params("up")
+        };
+        assert!(query(&earliest, &history).is_err());
+        let latest = (DateTime::<Utc>::MAX_UTC.timestamp() - 1).to_string();
+        let last_steps = QueryParams {
+            start: Some(latest.clone()),
+            end: Some(latest),
+            step: Some("1000000000".to_string()),
+            ..params("up")
+        };
+        assert!(query(&last_steps, &history).is_ok());
+    }
+
+    #[test]
+    fn test_only_selected_series_are_evaluated() {
+        let now = Utc::now();
+        let mut history = History::new(Duration::from_secs(3600));
+        let mut exposition = "# TYPE queue gauge\n".to_string();
+        for job in 0..300 {
+            exposition.push_str(&format!("queue{{job=\"{}\"}} 1\n", job));
+        }
+        exposition.push_str("# TYPE up gauge\nup 1\n");
+        history.record(Snapshot::parse(&exposition).samples(), now);
+
+        let (_, selected) = prepare("up + queue{job=\"1\"}", &history).unwrap();
+        assert_eq!(selected.series().count(), 2);
+
+        // 3601 steps over 300 series are too many points
+        let range = |query: &str| QueryParams {
+            start: Some((now - chrono::Duration::seconds(3600)).to_rfc3339()),
+            end: Some(now.to_rfc3339()),
+            step: Some("1s".to_string()),
+            ..params(query)
+        };
+        assert!(query(&range("queue"), &history).is_err());
+        assert!(query(&range("up"), &history).is_ok());
+    }
+}
@@ -0,0 +1,500 @@
+//! Parser of query expressions
+//!
+//! A recursive descent parser over the expression text. `+`/`-` bind
+//! weaker than `*`/`/`, and both are left associative.
+
+use crate::{alerts::parse_duration, selector::Selector};
+use anyhow::{Result, anyhow, bail};
+use std::time::Duration;
+
+/// Deepest nesting of parentheses, negations and function arguments,
+/// keeping the recursive descent off the end of the stack
+const MAX_DEPTH: usize = 128;
+
+/// Aggregation operators
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AggregateOp {
+    Sum,
+    Avg,
+    Min,
+    Max,
+    Count,
+}
+
+impl AggregateOp {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "sum" => AggregateOp::Sum,
+            "avg" => AggregateOp::Avg,
+            "min" => AggregateOp::Min,
+            "max" => AggregateOp::Max,
+            "count" => AggregateOp::Count,
+            _ => return None,
+        })
+    }
+}
+
+/// Labels kept by an aggregation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Grouping {
+    By(Vec<String>),
+    Without(Vec<String>),
+}
+
+/// Functions over the values of a range selector
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RangeFunction {
+    Rate,
+    Increase,
+}
+
+/// Arithmetic operators
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl BinaryOp {
+    pub fn apply(self, lhs: f64, rhs: f64) -> f64 {
+        match self {
+            BinaryOp::Add => lhs + rhs,
+            BinaryOp::Sub => lhs - rhs,
+            BinaryOp::Mul => lhs * rhs,
+            BinaryOp::Div => lhs / rhs,
+        }
+    }
+}
+
+/// A parsed query expression
+#[derive(Debug, Clone)]
+pub(crate) enum Expr {
+    Number(f64),
+    /// Latest value of every matching series
+    Vector(Selector),
+    /// `rate(selector[range])` or `increase(selector[range])`
+    Range {
+        function: RangeFunction,
+        selector: Selector,
+        range: Duration,
+    },
+    /// `histogram_quantile(quantile, buckets)`
+    HistogramQuantile {
+        quantile: Box<Expr>,
+        buckets: Box<Expr>,
+    },
+    Aggregate {
+        op: AggregateOp,
+        grouping: Option<Grouping>,
+        expr: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+    Negate(Box<Expr>),
+}
+
+impl Expr {
+    /// Selectors of all series the expression reads
+    pub fn selectors(&self) -> Vec<&Selector> {
+        match self {
+            Expr::Number(_) => Vec::new(),
+            Expr::Vector(selector) | Expr::Range { selector, .. } => vec![selector],
+            Expr::HistogramQuantile {
+                quantile: lhs,
+                buckets: rhs,
+            }
+            | Expr::Binary { lhs, rhs, .. } => {
+                let mut selectors = lhs.selectors();
+                selectors.extend(rhs.selectors());
+                selectors
+            }
+            Expr::Aggregate { expr, .. } | Expr::Negate(expr) => expr.selectors(),
+        }
+    }
+}
+
+/// Parses a complete query expression whose range selectors look back at
+/// most `max_range`, i.e. the retention of the history
+pub(crate) fn parse(text: &str, max_range: Duration) -> Result<Expr> {
+    let mut parser = Parser {
+        text,
+        position: 0,
+        max_range,
+        depth: 0,
+    };
+    let expr = parser.expr()?;
+    parser.skip_whitespace();
+    if !parser.rest().is_empty() {
+        bail!("unexpected '{}'", parser.rest());
+    }
+    Ok(expr)
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+    max_range: Duration,
+    /// Number of [`Parser::unary`] calls in progress
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.position..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    /// Consumes `token` if it comes next
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<()> {
+        if self.eat(token) {
+            Ok(())
+        } else if self.rest().is_empty() {
+            bail!("expected '{}' at the end of the query", token)
+        } else {
+            bail!("expected '{}' before '{}'", token, self.rest())
+        }
+    }
+
+    /// Returns true if `token` comes next, without consuming it
+    fn peek(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        self.rest().starts_with(token)
+    }
+
+    /// Returns true if the keyword `word` comes next, without consuming it
+    fn peek_keyword(&mut self, word: &str) -> bool {
+        self.peek(word)
+            && !self.rest()[word.len()..]
+                .starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+    }
+
+    fn identifier(&mut self) -> Option<&'a str> {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let end = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == ':'))
+            .unwrap_or(rest.len());
+        if end == 0 || rest.starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+        self.position += end;
+        Some(&rest[..end])
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        let mut lhs = self.product()?;
+        loop {
+            let op = if self.eat("+") {
+                BinaryOp::Add
+            } else if self.eat("-") {
+                BinaryOp::Sub
+            } else {
+                return Ok(lhs);
+            };
+            let rhs = self.product()?;
+            lhs = Expr::Binary {
+                op,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            };
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = if self.eat("*") {
+                BinaryOp::Mul
+            } else if self.eat("/") {
+                BinaryOp::Div
+            } else {
+                return Ok(lhs);
+            };
+            let rhs = self.unary()?;
+            lhs = Expr::Binary {
+                op,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            };
+        }
+    }
+
+    /// Every nested operand goes through here, so this is where the nesting
+    /// depth is tracked
+    fn unary(&mut self) -> Result<Expr> {
+        if self.depth >= MAX_DEPTH {
+            bail!("the query is nested deeper than {} levels", MAX_DEPTH);
+        }
+        self.depth += 1;
+        let expr = if self.eat("-") {
+            self.unary().map(|expr| Expr::Negate(Box::new(expr)))
+        } else {
+            self.primary()
+        };
+        self.depth -= 1;
+        expr
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        if self.eat("(") {
+            let expr = self.expr()?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        if let Some(number) = self.number() {
+            return Ok(Expr::Number(number));
+        }
+
+        let start = self.position;
+        let name = self.identifier().ok_or_else(|| match self.rest() {
+            "" => anyhow!("unexpected end of the query"),
+            rest => anyhow!("unexpected '{}'", rest),
+        })?;
+
+        if let Some(op) = AggregateOp::from_name(name)
+            && (self.peek("(") || self.peek_keyword("by") || self.peek_keyword("without"))
+        {
+            return self.aggregate(op);
+        }
+        if self.peek("(") {
+            return self.call(name);
+        }
+
+        let selector = self.selector(start)?;
+        if self.peek("[") {
+            bail!("range selectors are only supported in rate() and increase()");
+        }
+        Ok(Expr::Vector(selector))
+    }
+
+    fn number(&mut self) -> Option<f64> {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let mut end = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        if end == 0 {
+            return None;
+        }
+        // Exponent such as `1e-3`
+        if let Some(exponent) = rest[end..].strip_prefix(['e', 'E']) {
+            let unsigned = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+            let digits = unsigned
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(unsigned.len());
+            if digits > 0 {
+                end += 1 + (exponent.len() - unsigned.len()) + digits;
+            }
+        }
+        let number = rest[..end].parse().ok()?;
+        self.position += end;
+        Some(number)
+    }
+
+    /// Parses the selector whose metric name starts at `start`
+    fn selector(&mut self, start: usize) -> Result<Selector> {
+        if self.peek("{") {
+            let mut in_quotes = false;
+            let mut escaped = false;
+            let close = self.rest().char_indices().find(|(_, c)| {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' => in_quotes = !in_quotes,
+                    '}' if !in_quotes => return true,
+                    _ => {}
+                }
+                false
+            });
+            let (close, _) = close.ok_or_else(|| anyhow!("unclosed '{{' in the query"))?;
+            self.position += close + 1;
+        }
+        Selector::parse(&self.text[start..self.position])
+    }
+
+    /// Parses `by (...)` or `without (...)` if present
+    fn grouping(&mut self) -> Result<Option<Grouping>> {
+        let by = if self.peek_keyword("by") {
+            true
+        } else if self.peek_keyword("without") {
+            false
+        } else {
+            return Ok(None);
+        };
+        self.identifier();
+
+        self.expect("(")?;
+        let mut labels = Vec::new();
+        while !self.eat(")") {
+            let label = self
+                .identifier()
+                .ok_or_else(|| anyhow!("expected a label name before '{}'", self.rest()))?;
+            labels.push(label.to_owned());
+            if !self.eat(",") {
+                self.expect(")")?;
+                break;
+            }
+        }
+        Ok(Some(if by {
+            Grouping::By(labels)
+        } else {
+            Grouping::Without(labels)
+        }))
+    }
+
+    fn aggregate(&mut self, op: AggregateOp) -> Result<Expr> {
+        let leading = self.grouping()?;
+        self.expect("(")?;
+        let expr = self.expr()?;
+        self.expect(")")?;
+        let trailing = self.grouping()?;
+        if leading.is_some() && trailing.is_some() {
+            bail!("an aggregation can only be grouped once");
+        }
+        Ok(Expr::Aggregate {
+            op,
+            grouping: leading.or(trailing),
+            expr: Box::new(expr),
+        })
+    }
+
+    fn call(&mut self, name: &str) -> Result<Expr> {
+        self.expect("(")?;
+        let expr = match name {
+            "rate" | "increase" => {
+                let start = self.position;
+                self.identifier()
+                    .ok_or_else(|| anyhow!("{}() needs a range selector", name))?;
+                let selector = self.selector(start)?;
+                self.expect("[")?;
+                let end = self
+                    .rest()
+                    .find(']')
+                    .ok_or_else(|| anyhow!("unclosed '[' in the query"))?;
+                let range = parse_duration(&self.rest()[..end])?;
+                if range > self.max_range {
+                    bail!(
+                        "range {:?} is longer than the history retention of {:?}",
+                        range,
+                        self.max_range
+                    );
+                }
+                self.position += end + 1;
+                Expr::Range {
+                    function: if name == "rate" {
+                        RangeFunction::Rate
+                    } else {
+                        RangeFunction::Increase
+                    },
+                    selector,
+                    range,
+                }
+            }
+            "histogram_quantile" => {
+                let quantile = self.expr()?;
+                self.expect(",")?;
+                let buckets = self.expr()?;
+                Expr::HistogramQuantile {
+                    quantile: Box::new(quantile),
+                    buckets: Box::new(buckets),
+                }
+            }
+            _ => bail!("unknown function '{}'", name),
+        };
+        self.expect(")")?;
+        Ok(expr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_expressions() {
+        let expr = parse(
+            r#"sum by (service) (rate(http_requests_total{status=~"5..", path!="/a}"}[5m])) / 2"#,
+            Duration::MAX,
+        )
+        .unwrap();
+        let Expr::Binary { op, lhs, rhs } = expr else {
+            panic!("expected a division");
+        };
+        assert_eq!(op, BinaryOp::Div);
+        assert!(matches!(*rhs, Expr::Number(2.0)));
+        let Expr::Aggregate { grouping, expr, .. } = *lhs else {
+            panic!("expected an aggregation");
+        };
+        assert_eq!(grouping, Some(Grouping::By(vec!["service".to_string()])));
+        let Expr::Range {
+            selector, range, ..
+        } = *expr
+        else {
+            panic!("expected rate()");
+        };
+        assert_eq!(selector.metric, "http_requests_total");
+        assert_eq!(selector.matchers.len(), 2);
+        assert_eq!(range, Duration::from_secs(300));
+
+        let expr = parse(
+            "histogram_quantile(0.9, sum(rate(latency_bucket[1m])) without (path))",
+            Duration::MAX,
+        )
+        .unwrap();
+        assert!(matches!(expr, Expr::HistogramQuantile { .. }));
+
+        // Multiplication binds stronger than subtraction
+        let expr = parse("a - b * 1e-3", Duration::MAX).unwrap();
+        assert!(matches!(
+            expr,
+            Expr::Binary {
+                op: BinaryOp::Sub,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        assert!(parse("", Duration::MAX).is_err());
+        assert!(parse("up[5m]", Duration::MAX).is_err());
+        assert!(parse("rate(up)", Duration::MAX).is_err());
+        assert!(parse("unknown(up)", Duration::MAX).is_err());
+        assert!(parse("sum by (job (up)", Duration::MAX).is_err());
+        assert!(parse("up{job=\"a\"", Duration::MAX).is_err());
+        assert!(parse("up +", Duration::MAX).is_err());
+        assert!(parse("up up", Duration::MAX).is_err());
+        assert!(parse("rate(up[2h])", Duration::from_secs(3600)).is_err());
+        assert!(parse("rate(up[4000000000d])", Duration::from_secs(3600)).is_err());
+    }
+
+    #[test]
+    fn test_nesting_depth_is_limited() {
+        let nested = format!("{}up{}", "(".repeat(100), ")".repeat(100));
+        assert!(parse(&nested, Duration::MAX).is_ok());
+        assert!(parse(&format!("{}1", "-".repeat(100)), Duration::MAX).is_ok());
+
+        assert!(parse(&"(".repeat(100_000), Duration::MAX).is_err());
+        assert!(parse(&format!("{}1", "-".repeat(100_000)), Duration::MAX).is_err());
+        assert!(parse(&"sum(".repeat(100_000), Duration::MAX).is_err());
+    }
+}
@@ -0,0 +1,350 @@
+//! Percentiles of histograms over time
+//!
+//! Serves p50/p90/p99 (or any other quantiles) of a histogram as time
+//! series. Quantiles recorded as series with a `quantile` label, either by
+//! Prometheus summaries or by the exact quantile recorder, are returned as
+//! recorded. Otherwise they are interpolated from the bucket rates over a
+//! sliding window, like `histogram_quantile(0.9, rate(metric_bucket[1m]))`.
+
+use super::{
+    ApiResponse,
+    eval::Labels,
+    evaluate_range,
+    parser::{Expr, RangeFunction},
+};
+use crate::{
+    alerts::parse_duration,
+    history::{History, with_history},
+    selector::Selector,
+};
+use actix_web::{Responder, web};
+use anyhow::{Result, anyhow, bail};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, time::Duration};
+
+/// Quantiles served when none are requested
+pub(crate) const DEFAULT_QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+/// Where the quantiles come from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum QuantileSource {
+    /// Recorded quantiles if there are any, interpolated ones otherwise
+    #[default]
+    Auto,
+    /// Interpolated from the histogram buckets
+    Buckets,
+    /// Recorded by summaries or the exact quantile recorder
+    Exact,
+}
+
+/// Parameters of a quantiles request
+#[derive(Debug, Deserialize)]
+pub(crate) struct QuantileParams {
+    /// Selector of the histogram, e.g. `request_latency{path="/a"}`
+    metric: String,
+    /// Comma separated quantiles, `0.5,0.9,0.99` by default
+    quantiles: Option<String>,
+    /// Time range ending now, `15m` by default
+    range: Option<String>,
+    /// Window of the bucket rates, `1m` by default
+    window: Option<String>,
+    /// Resolution of interpolated quantiles, `10s` by default
+    step: Option<String>,
+    #[serde(default)]
+    source: QuantileSource,
+}
+
+/// Values of each quantile as `[unix seconds, value]` pairs
+type QuantileValues = BTreeMap<String, Vec<(f64, f64)>>;
+
+/// Quantiles of one series
+#[derive(Debug, PartialEq, Serialize)]
+struct QuantileSeries {
+    labels: Labels,
+    quantiles: QuantileValues,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct QuantileData {
+    metric: String,
+    source: QuantileSource,
+    series: Vec<QuantileSeries>,
+}
+
+/// Parses a duration parameter, which may not exceed the history retention
+fn duration_param(
+    name: &str,
+    value: &Option<String>,
+    default: &str,
+    retention: Duration,
+) -> Result<Duration> {
+    let duration = parse_duration(value.as_deref().unwrap_or(default))?;
+    if duration > retention {
+        bail!(
+            "{} {:?} is longer than the history retention of {:?}",
+            name,
+            duration,
+            retention
+        );
+    }
+    Ok(duration)
+}
+
+/// Collects the recorded quantile series of the selected metric within the range
+fn recorded(
+    history: &History,
+    selector: &Selector,
+    quantiles: &[f64],
+    start: DateTime<Utc>,
+) -> BTreeMap<Labels, QuantileValues> {
+    let mut series: BTreeMap<Labels, QuantileValues> = BTreeMap::new();
+    for (id, points) in history.series() {
+        if id.name != selector.metric || !selector.matches_labels(&id.labels) {
+            continue;
+        }
+        let mut labels = id.labels.clone();
+        let Some(quantile) = labels
+            .remove("quantile")
+            .and_then(|quantile| quantile.parse::<f64>().ok())
+            .filter(|quantile| quantiles.contains(quantile))
+        else {
+            continue;
+        };
+        let values = points
+            .iter()
+            .filter(|(at, value)| *at >= start && !value.is_nan())
+            .map(|(at, value)| (at.timestamp_millis() as f64 / 1000.0, *value))
+            .collect();
+        series
+            .entry(labels)
+            .or_default()
+            .insert(quantile.to_string(), values);
+    }
+    series
+}
+
+/// Interpolates the quantiles from the bucket rates at every step of the range
+fn interpolated(
+    history: &History,
+    selector: &Selector,
+    quantiles: &[f64],
+    window: Duration,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    step: Duration,
+) -> Result<BTreeMap<Labels, QuantileValues>> {
+    let buckets = Selector {
+        metric: format!("{}_bucket", selector.metric),
+        matchers: selector.matchers.clone(),
+    };
+    let mut series: BTreeMap<Labels, QuantileValues> = BTreeMap::new();
+    for quantile in quantiles {
+        let expr = Expr::HistogramQuantile {
+            quantile: Box::new(Expr::Number(*quantile)),
+            buckets: Box::new(Expr::Range {
+                function: RangeFunction::Rate,
+                selector: buckets.clone(),
+                range: window,
+            }),
+        };
+        for (labels, points) in evaluate_range(&expr, history, start, end, step)? {
+            let values = points
+                .into_iter()
+                .filter(|(_, value)| !value.is_nan())
+                .map(|(at, value)| (at.timestamp_millis() as f64 / 1000.0, value))
+                .collect();
+            series
+                .entry(labels)
+                .or_default()
+                .insert(quantile.to_string(), values);
+        }
+    }
+    Ok(series)
+}
+
+/// Computes the requested quantiles of a histogram up to `now`
+fn quantile_data(
+    params: &QuantileParams,
+    history: &History,
+    now: DateTime<Utc>,
+) -> Result<QuantileData> {
+    let selector = Selector::parse(&params.metric)?;
+    let quantiles = match &params.quantiles {
+        Some(list) => list
+            .split(',')
+            .map(|quantile| match quantile.trim().parse::<f64>() {
+                Ok(quantile) if (0.0..=1.0).contains(&quantile) => Ok(quantile),
+                _ => bail!("invalid quantile '{}'", quantile),
+            })
+            .collect::<Result<Vec<_>>>()?,
+        None => DEFAULT_QUANTILES.to_vec(),
+    };
+    let retention = history.retention();
+    let range = duration_param("range", &params.range, "15m", retention)?;
+    let start = chrono::Duration::from_std(range)
+        .ok()
+        .and_then(|range| now.checked_sub_signed(range))
+        .ok_or_else(|| anyhow!("range {:?} before {} is out of range", range, now))?;
+
+    let mut source = params.source;
+    let mut series = BTreeMap::new();
+    if source != QuantileSource::Buckets {
+        series = recorded(history, &selector, &quantiles, start);
+        if !series.is_empty() {
+            source = QuantileSource::Exact;
+        }
+    }
+    if source != QuantileSource::Exact {
+        source = QuantileSource::Buckets;
+        series = interpolated(
+            history,
+            &selector,
+            &quantiles,
+            duration_param("window", &params.window, "1m", retention)?,
+            start,
+            now,
+            duration_param("step", &params.step, "10s", retention)?,
+        )?;
+    }
+
+    Ok(QuantileData {
+        metric: selector.metric,
+        source,
+        series: series
+            .into_iter()
+            .map(|(labels, quantiles)| QuantileSeries { labels, quantiles })
+            .collect(),
+    })
+}
+
+/// Endpoint serving quantiles of a histogram over time
+///
+/// # Returns
+///
+/// JSON with the quantile time series of every matching series, or
+/// `400 Bad Request` if a parameter is invalid
+#[actix_web::get("/api/quantiles")]
+pub(crate) async fn get_quantiles(params: web::Query<QuantileParams>) -> impl Responder {
+    let params = params.into_inner();
+    // Like queries, quantiles are computed on the blocking thread pool from a
+    // copy of the series of the histogram
+    let result = web::block(move || {
+        let selector = Selector::parse(&params.metric)?;
+        let buckets = format!("{}_bucket", selector.metric);
+        let history = with_history(|history| {
+            history.filtered(|id| {
+                (id.name == selector.metric || id.name == buckets)
+                    && selector.matches_labels(&id.labels)
+            })
+        });
+        quantile_data(&params, &history, Utc::now())
+    })
+    .await;
+    ApiResponse::respond(
+        result
+            .map_err(anyhow::Error::from)
+            .and_then(|result| result),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::Snapshot;
+
+    fn params(metric: &str, source: QuantileSource) -> QuantileParams {
+        QuantileParams {
+            metric: metric.to_string(),
+            quantiles: None,
+            range: Some("1m".to_string()),
+            window: Some("30s".to_string()),
+            step: Some("30s".to_string()),
+            source,
+        }
+    }
+
+    /// Latency histogram where 90% of the observations take up to 0.1s,
+    /// plus a summary recorded the way the exact quantile recorder does
+    fn history(start: DateTime<Utc>) -> History {
+        let mut history = History::new(Duration::from_secs(3600));
+        for step in 0..=6 {
+            let count = step * 100;
+            history.record(
+                Snapshot::parse(&format!(
+                    "# TYPE latency histogram\n\
+                     latency_bucket{{path=\"/a\",le=\"0.1\"}} {}\n\
+                     latency_bucket{{path=\"/a\",le=\"1\"}} {count}\n\
+                     latency_bucket{{path=\"/a\",le=\"+Inf\"}} {count}\n\
+                     latency_sum{{path=\"/a\"}} 0\n\
+                     latency_count{{path=\"/a\"}} {count}\n\
+                     # TYPE timing summary\n\
+                     timing{{quantile=\"0.5\"}} 3\n\
+                     timing{{quantile=\"0.99\"}} 8\n\
+                     timing_sum 0\n\
+                     timing_count {count}\n",
+                    step * 90,
+                ))
+                .samples(),
+                start + chrono::Duration::seconds(step * 10),
+            );
+        }
+        history
+    }
+
+    #[test]
+    fn test_quantiles_from_buckets() {
+        let start = DateTime::from_timestamp(Utc::now().timestamp(), 0).unwrap();
+        let history = history(start);
+        let now = start + chrono::Duration::seconds(60);
+
+        let data = quantile_data(&params("latency", QuantileSource::Auto), &history, now).unwrap();
+        assert_eq!(data.source, QuantileSource::Buckets);
+        assert_eq!(data.series.len(), 1);
+        let series = &data.series[0];
+        assert_eq!(series.labels["path"], "/a");
+        // Evaluated at the start and the end of the range
+        assert_eq!(series.quantiles["0.9"].len(), 2);
+        assert!((series.quantiles["0.9"][1].1 - 0.1).abs() < 1e-9);
+        assert!((series.quantiles["0.99"][1].1 - 0.91).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_recorded_quantiles() {
+        let start = Utc::now();
+        let history = history(start);
+        let now = start + chrono::Duration::seconds(60);
+
+        let data = quantile_data(&params("timing", QuantileSource::Auto), &history, now).unwrap();
+        assert_eq!(data.source, QuantileSource::Exact);
+        let quantiles = &data.series[0].quantiles;
+        // 0.9 isn't recorded by the summary
+        assert_eq!(quantiles.keys().collect::<Vec<_>>(), ["0.5", "0.99"]);
+        assert!(quantiles["0.99"].iter().all(|(_, value)| *value == 8.0));
+
+        let forced = quantile_data(&params("timing", QuantileSource::Buckets), &history, now);
+        assert!(forced.unwrap().series.is_empty());
+        let invalid = QuantileParams {
+            quantiles: Some("0.5,2".to_string()),
+            ..params("timing", QuantileSource::Auto)
+        };
+        assert!(quantile_data(&invalid, &history, now).is_err());
+        for too_long in [
+            QuantileParams {
+                range: Some("4000000000d".to_string()),
+                ..params("timing", QuantileSource::Auto)
+            },
+            QuantileParams {
+                window: Some("2h".to_string()),
+                ..params("latency", QuantileSource::Buckets)
+            },
+            QuantileParams {
+                step: Some("2h".to_string()),
+                ..params("latency", QuantileSource::Buckets)
+            },
+        ] {
+            assert!(quantile_data(&too_long, &history, now).is_err());
+        }
+    }
+}
@@ -0,0 +1,350 @@
+//! # Relabeling
+//!
+//! Rewrites or drops series before any recorder sees them. The relabeler
+//! wraps the whole recorder fanout, so metrics dropped here, e.g. noisy
+//! metrics of third-party crates, never reach `/metrics/prometheus`, the
+//! dashboard or an exporter.
+//!
+//! Rules are applied in order to the name and labels a metric is registered
+//! with; once a rule drops a series, the remaining rules are skipped. As in
+//! Prometheus, regular expressions must match the whole name or value, and
+//! a missing label has an empty value. Constant labels are added after
+//! relabeling.
+
+use crate::selector::anchored;
+use anyhow::{Context, Result};
+use metrics::{
+    Counter, Gauge, Histogram, Key, KeyName, Label, Metadata, Recorder, SharedString, Unit,
+};
+use regex::Regex;
+use std::{borrow::Cow, sync::Arc};
+
+/// A relabeling rule
+///
+/// # Example
+///
+/// ```
+/// use metrics_rs_dashboard_actix::{DashboardInput, RelabelRule};
+///
+/// let dashboard_input = DashboardInput {
+///     relabel_rules: vec![
+///         RelabelRule::DropMetric {
+///             name: "tokio_.*".to_string(),
+///         },
+///         RelabelRule::HashLabel {
+///             label: "user_id".to_string(),
+///             modulus: Some(16),
+///         },
+///     ],
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone)]
+pub enum RelabelRule {
+    /// Drops metrics whose name matches the regex
+    DropMetric { name: String },
+    /// Drops series whose value of `label` matches the regex
+    DropSeries { label: String, value: String },
+    /// Keeps only metrics whose name starts with one of the prefixes
+    AllowPrefixes(Vec<String>),
+    /// Renames a label, replacing a label that already has the new name
+    RenameLabel { from: String, to: String },
+    /// Replaces the values of a label with a hash, limited to `modulus`
+    /// distinct values if set, e.g. to keep user ids out of the series
+    HashLabel { label: String, modulus: Option<u64> },
+}
+
+/// A rule with its regular expressions compiled
+#[derive(Debug)]
+enum CompiledRule {
+    DropMetric(Regex),
+    DropSeries(String, Regex),
+    AllowPrefixes(Vec<String>),
+    RenameLabel(String, String),
+    HashLabel(String, Option<u64>),
+}
+
+impl CompiledRule {
+    fn compile(rule: &RelabelRule) -> Result<Self> {
+        Ok(match rule {
+            RelabelRule::DropMetric { name } => CompiledRule::DropMetric(anchored(name)?),
+            RelabelRule::DropSeries { label, value } => {
+                CompiledRule::DropSeries(label.clone(), anchored(value)?)
+            }
+            RelabelRule::AllowPrefixes(prefixes) => CompiledRule::AllowPrefixes(prefixes.clone()),
+            RelabelRule::RenameLabel { from, to } => {
+                CompiledRule::RenameLabel(from.clone(), to.clone())
+            }
+            RelabelRule::HashLabel { label, modulus } => {
+                CompiledRule::HashLabel(label.clone(), modulus.filter(|modulus| *modulus > 0))
+            }
+        })
+    }
+
+    /// Whether the rule drops all series of the metric
+    fn drops_name(&self, name: &str) -> bool {
+        match self {
+            CompiledRule::DropMetric(regex) => regex.is_match(name),
+            CompiledRule::AllowPrefixes(prefixes) => {
+                !prefixes.iter().any(|prefix| name.starts_with(prefix))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Applies relabeling rules to metric keys
+#[derive(Debug)]
+pub(crate) struct Relabeler {
+    rules: Vec<CompiledRule>,
+}
+
+impl Relabeler {
+    /// Compiles the rules
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a rule has an invalid regular expression
+    pub fn new(rules: &[RelabelRule]) -> Result<Self> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                CompiledRule::compile(rule)
+                    .with_context(|| format!("Invalid relabel rule {:?}", rule))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    /// Whether all series of the metric are dropped
+    fn drops_name(&self, name: &str) -> bool {
+        self.rules.iter().any(|rule| rule.drops_name(name))
+    }
+
+    /// Returns the relabeled key, or `None` if the series is dropped
+    pub fn relabel<'a>(&self, key: &'a Key) -> Option<Cow<'a, Key>> {
+        if self.rules.is_empty() {
+            return Some(Cow::Borrowed(key));
+        }
+        let name = key.name();
+        let mut labels: Vec<Label> = key.labels().cloned().collect();
+        let mut changed = false;
+        for rule in &self.rules {
+            if rule.drops_name(name) {
+                return None;
+            }
+            match rule {
+                CompiledRule::DropSeries(label, regex) => {
+                    let value = labels
+                        .iter()
+                        .find(|existing| existing.key() == label)
+                        .map_or("", |existing| existing.value());
+                    if regex.is_match(value) {
+                        return None;
+                    }
+                }
+                CompiledRule::RenameLabel(from, to) => {
+                    if let Some(index) = labels.iter().position(|label| label.key() == from) {
+                        let value = labels.remove(index).into_parts().1;
+                        labels.retain(|label| label.key() != to);
+                        labels.push(Label::new(to.clone(), value));
+                        changed = true;
+                    }
+                }
+                CompiledRule::HashLabel(label, modulus) => {
+                    if let Some(existing) =
+                        labels.iter_mut().find(|existing| existing.key() == label)
+                    {
+                        let hash = fnv1a(existing.value());
+                        let value = match modulus {
+                            Some(modulus) => (hash % modulus).to_string(),
+                            None => format!("{:016x}", hash),
+                        };
+                        *existing = Label::new(label.clone(), value);
+                        changed = true;
+                    }
+                }
+                CompiledRule::DropMetric(_) | CompiledRule::AllowPrefixes(_) => {}
+            }
+        }
+        if !changed {
+            return Some(Cow::Borrowed(key));
+        }
+        Some(Cow::Owned(Key::from_parts(name.to_owned(), labels)))
+    }
+}
+
+/// 64 bit FNV-1a, so that hashed values stay the same across restarts
+fn fnv1a(value: &str) -> u64 {
+    value.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Wraps the recorder fanout, relabeling keys before they are registered
+pub(crate) struct RelabelRecorder<R> {
+    pub inner: R,
+    /// Shared with the exemplar store, whose keys are relabeled the same way
+    pub relabeler: Arc<Relabeler>,
+}
+
+impl<R: Recorder> Recorder for RelabelRecorder<R> {
+    fn describe_counter(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        if !self.relabeler.drops_name(key.as_str()) {
+            self.inner.describe_counter(key, unit, description);
+        }
+    }
+
+    fn describe_gauge(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        if !self.relabeler.drops_name(key.as_str()) {
+            self.inner.describe_gauge(key, unit, description);
+        }
+    }
+
+    fn describe_histogram(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        if !self.relabeler.drops_name(key.as_str()) {
+            self.inner.describe_histogram(key, unit, description);
+        }
+    }
+
+    fn register_counter(&self, key: &Key, metadata: &Metadata<'_>) -> Counter {
+        match self.relabeler.relabel(key) {
+            Some(key) => self.inner.register_counter(&key, metadata),
+            None => Counter::noop(),
+        }
+    }
+
+    fn register_gauge(&self, key: &Key, metadata: &Metadata<'_>) -> Gauge {
+        match self.relabeler.relabel(key) {
+            Some(key) => self.inner.register_gauge(&key, metadata),
+            None => Gauge::noop(),
+        }
+    }
+
+    fn register_histogram(&self, key: &Key, metadata: &Metadata<'_>) -> Histogram {
+        match self.relabeler.relabel(key) {
+            Some(key) => self.inner.register_histogram(&key, metadata),
+            None => Histogram::noop(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metrics_util::debugging::DebuggingRecorder;
+
+    fn key(name: &str, labels: &[(&str, &str)]) -> Key {
+        let labels: Vec<Label> = labels
+            .iter()
+            .map(|(k, v)| Label::new(k.to_string(), v.to_string()))
+            .collect();
+        Key::from_parts(name.to_owned(), labels)
+    }
+
+    fn label_pairs(key: &Key) -> Vec<(&str, &str)> {
+        key.labels()
+            .map(|label| (label.key(), label.value()))
+            .collect()
+    }
+
+    #[test]
+    fn test_drop_rules() {
+        let relabeler = Relabeler::new(&[
+            RelabelRule::DropMetric {
+                name: "tokio_.*".to_string(),
+            },
+            RelabelRule::DropSeries {
+                label: "path".to_string(),
+                value: "/health|".to_string(),
+            },
+            RelabelRule::AllowPrefixes(vec!["http_".to_string(), "tokio_".to_string()]),
+        ])
+        .unwrap();
+
+        assert!(relabeler.relabel(&key("tokio_tasks", &[])).is_none());
+        assert!(
+            relabeler
+                .relabel(&key("db_queries", &[("path", "/a")]))
+                .is_none()
+        );
+        assert!(
+            relabeler
+                .relabel(&key("http_requests", &[("path", "/health")]))
+                .is_none()
+        );
+        // A missing label has an empty value
+        assert!(relabeler.relabel(&key("http_requests", &[])).is_none());
+        // Regexes match whole values
+        assert!(
+            relabeler
+                .relabel(&key("http_requests", &[("path", "/healthz")]))
+                .is_some()
+        );
+
+        let invalid = Relabeler::new(&[RelabelRule::DropMetric {
+            name: "(".to_string(),
+        }]);
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn test_rename_and_hash_labels() {
+        let relabeler = Relabeler::new(&[
+            RelabelRule::RenameLabel {
+                from: "uri".to_string(),
+                to: "path".to_string(),
+            },
+            RelabelRule::HashLabel {
+                label: "user".to_string(),
+                modulus: Some(8),
+            },
+            RelabelRule::HashLabel {
+                label: "session".to_string(),
+                modulus: None,
+            },
+        ])
+        .unwrap();
+
+        let key = key(
+            "requests",
+            &[
+                ("path", "old"),
+                ("uri", "/a"),
+                ("user", "42"),
+                ("session", "s"),
+            ],
+        );
+        let relabeled = relabeler.relabel(&key).unwrap();
+        let labels = label_pairs(&relabeled);
+        assert_eq!(labels[0], ("user", (fnv1a("42") % 8).to_string().as_str()));
+        assert_eq!(
+            labels[1],
+            ("session", format!("{:016x}", fnv1a("s")).as_str())
+        );
+        assert_eq!(labels[2], ("path", "/a"));
+        assert_eq!(labels.len(), 3);
+    }
+
+    #[test]
+    fn test_dropped_metrics_never_reach_recorders() {
+        let debugging = DebuggingRecorder::new();
+        let snapshotter = debugging.snapshotter();
+        let recorder = RelabelRecorder {
+            inner: debugging,
+            relabeler: Arc::new(
+                Relabeler::new(&[RelabelRule::AllowPrefixes(vec!["app_".to_string()])]).unwrap(),
+            ),
+        };
+        metrics::with_local_recorder(&recorder, || {
+            metrics::describe_counter!("hyper_connections", "Connections");
+            metrics::counter!("hyper_connections").increment(1);
+            metrics::gauge!("app_queue").set(3.0);
+            metrics::histogram!("other_latency").record(0.1);
+        });
+
+        let metrics = snapshotter.snapshot().into_vec();
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].0.key().name(), "app_queue");
+    }
+}
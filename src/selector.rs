@@ -0,0 +1,196 @@
+//! # Series Selectors
+//!
+//! Parses PromQL-style series selectors such as
+//! `http_requests_total{method="GET",status!~"2.."}`, shared by alert rules
+//! and SLO definitions. Label values are matched against the unescaped
+//! values of [`FlatSample`]s; a missing label matches the empty string.
+
+use crate::snapshot::FlatSample;
+use anyhow::{Result, anyhow, bail};
+use regex::Regex;
+use std::collections::BTreeMap;
+
+/// How a label value is compared
+#[derive(Debug, Clone)]
+pub(crate) enum MatchOp {
+    /// `=`
+    Equal(String),
+    /// `!=`
+    NotEqual(String),
+    /// `=~`, anchored at both ends
+    Regex(Regex),
+    /// `!~`, anchored at both ends
+    NotRegex(Regex),
+}
+
+/// A matcher on a single label
+#[derive(Debug, Clone)]
+pub(crate) struct LabelMatcher {
+    pub name: String,
+    pub op: MatchOp,
+}
+
+impl LabelMatcher {
+    fn matches(&self, value: &str) -> bool {
+        match &self.op {
+            MatchOp::Equal(expected) => value == expected,
+            MatchOp::NotEqual(expected) => value != expected,
+            MatchOp::Regex(regex) => regex.is_match(value),
+            MatchOp::NotRegex(regex) => !regex.is_match(value),
+        }
+    }
+}
+
+/// A metric name with optional label matchers
+#[derive(Debug, Clone)]
+pub(crate) struct Selector {
+    pub metric: String,
+    pub matchers: Vec<LabelMatcher>,
+}
+
+impl Selector {
+    /// Parses `metric` or `metric{label="value",...}`
+    pub fn parse(text: &str) -> Result<Self> {
+        let text = text.trim();
+        let (metric, labels) = match text.split_once('{') {
+            Some((metric, labels)) => (
+                metric.trim(),
+                labels
+                    .trim_end()
+                    .strip_suffix('}')
+                    .ok_or_else(|| anyhow!("unclosed '{{' in '{}'", text))?,
+            ),
+            None => (text, ""),
+        };
+        let valid_name = metric
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':');
+        if metric.is_empty() || !valid_name {
+            bail!("invalid metric name in '{}'", text);
+        }
+
+        Ok(Self {
+            metric: metric.to_owned(),
+            matchers: parse_matchers(labels)?,
+        })
+    }
+
+    /// Returns true if the sample has the selected name and matching labels
+    pub fn matches(&self, sample: &FlatSample) -> bool {
+        sample.name == self.metric && self.matches_labels(&sample.labels)
+    }
+
+    /// Returns true if all label matchers accept the labels
+    pub fn matches_labels(&self, labels: &BTreeMap<String, String>) -> bool {
+        self.matchers
+            .iter()
+            .all(|matcher| matcher.matches(labels.get(&matcher.name).map_or("", String::as_str)))
+    }
+}
+
+/// Parses the comma separated matchers between the braces of a selector
+fn parse_matchers(text: &str) -> Result<Vec<LabelMatcher>> {
+    let mut matchers = Vec::new();
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        let name_end = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .ok_or_else(|| anyhow!("missing operator after '{}'", rest))?;
+        let name = &rest[..name_end];
+        if name.is_empty() {
+            bail!("missing label name in '{}'", text);
+        }
+        rest = rest[name_end..].trim_start();
+
+        let (operator, after) = ["=~", "!~", "!=", "="]
+            .iter()
+            .find_map(|op| rest.strip_prefix(op).map(|after| (*op, after)))
+            .ok_or_else(|| anyhow!("invalid operator after label '{}'", name))?;
+        let (value, after) = parse_quoted(after.trim_start())
+            .ok_or_else(|| anyhow!("label '{}' needs a double quoted value", name))?;
+
+        let op = match operator {
+            "=" => MatchOp::Equal(value),
+            "!=" => MatchOp::NotEqual(value),
+            "=~" => MatchOp::Regex(anchored(&value)?),
+            _ => MatchOp::NotRegex(anchored(&value)?),
+        };
+        matchers.push(LabelMatcher {
+            name: name.to_owned(),
+            op,
+        });
+
+        rest = after.trim_start();
+        rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+    }
+    Ok(matchers)
+}
+
+/// Reads a double quoted string, returning its unescaped content and the rest
+fn parse_quoted(text: &str) -> Option<(String, &str)> {
+    let mut chars = text.strip_prefix('"')?.char_indices();
+    let mut value = String::new();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &text[index + 2..])),
+            '\\' => match chars.next()?.1 {
+                'n' => value.push('\n'),
+                other => value.push(other),
+            },
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// Compiles a regex that has to match the whole text, as in PromQL
+pub(crate) fn anchored(pattern: &str) -> Result<Regex> {
+    Regex::new(&format!("^(?:{})$", pattern))
+        .map_err(|e| anyhow!("invalid regex '{}': {}", pattern, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_selector() {
+        let selector =
+            Selector::parse(r#"http_requests_total{method="GET", path!="/a,b", status=~"5.."}"#)
+                .unwrap();
+
+        assert_eq!(selector.metric, "http_requests_total");
+        assert_eq!(selector.matchers.len(), 3);
+        assert!(selector.matches_labels(&labels(&[("method", "GET"), ("status", "503")])));
+        assert!(!selector.matches_labels(&labels(&[("method", "GET"), ("status", "200")])));
+        assert!(!selector.matches_labels(&labels(&[
+            ("method", "GET"),
+            ("path", "/a,b"),
+            ("status", "500")
+        ])));
+    }
+
+    #[test]
+    fn test_missing_labels_match_empty_string() {
+        let selector = Selector::parse(r#"up{job!~"", env=""}"#).unwrap();
+
+        assert!(selector.matches_labels(&labels(&[("job", "api")])));
+        assert!(!selector.matches_labels(&labels(&[("job", "api"), ("env", "prod")])));
+    }
+
+    #[test]
+    fn test_invalid_selectors() {
+        assert!(Selector::parse("").is_err());
+        assert!(Selector::parse("up{job=\"a\"").is_err());
+        assert!(Selector::parse("up{job=a}").is_err());
+        assert!(Selector::parse("up{job=~\"(\"}").is_err());
+        assert!(Selector::parse("up > 1").is_err());
+    }
+}
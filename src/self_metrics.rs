@@ -0,0 +1,128 @@
+//! # Self Metrics
+//!
+//! Metrics about the crate itself, so that its cost can be measured like
+//! that of any other component. All of them are named with the
+//! [`SELF_METRICS_PREFIX`], including the [cardinality](crate::cardinality)
+//! gauges:
+//!
+//! - `metrics_dashboard_render_duration_seconds`: time taken to render `/metrics/prometheus`
+//! - `metrics_dashboard_render_size_bytes`: size of the last rendered exposition
+//! - `metrics_dashboard_rate_trackers`: number of series tracked by the rate macros
+//! - `metrics_dashboard_rate_tracker_fallbacks_total`: rates calculated without
+//!   the tracker because its lock was poisoned
+//! - `metrics_dashboard_upkeep_runs_total`: upkeep runs of the Prometheus recorder
+//! - `metrics_dashboard_export_failures_total{exporter}`: failed periodic pushes
+//!
+//! They are exported like every other metric, but can be hidden from the
+//! dashboard with `DashboardInput::hide_self_metrics`.
+
+use metrics::{
+    Unit, counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram,
+};
+use std::time::Duration;
+
+/// Prefix of the names of all self metrics
+pub const SELF_METRICS_PREFIX: &str = "metrics_dashboard_";
+
+const RENDER_DURATION: &str = "metrics_dashboard_render_duration_seconds";
+const RENDER_SIZE: &str = "metrics_dashboard_render_size_bytes";
+const RATE_TRACKERS: &str = "metrics_dashboard_rate_trackers";
+const RATE_TRACKER_FALLBACKS: &str = "metrics_dashboard_rate_tracker_fallbacks_total";
+const UPKEEP_RUNS: &str = "metrics_dashboard_upkeep_runs_total";
+const EXPORT_FAILURES: &str = "metrics_dashboard_export_failures_total";
+
+/// Whether a metric is one of the self metrics
+pub(crate) fn is_self_metric(name: &str) -> bool {
+    name.starts_with(SELF_METRICS_PREFIX)
+}
+
+/// Registers the descriptions and units of the self metrics
+pub(crate) fn describe() {
+    describe_histogram!(
+        RENDER_DURATION,
+        Unit::Seconds,
+        "Time taken to render the Prometheus endpoint"
+    );
+    describe_gauge!(
+        RENDER_SIZE,
+        Unit::Bytes,
+        "Size of the last exposition rendered by the Prometheus endpoint"
+    );
+    describe_gauge!(
+        RATE_TRACKERS,
+        Unit::Count,
+        "Number of series tracked by the rate macros"
+    );
+    describe_counter!(
+        RATE_TRACKER_FALLBACKS,
+        Unit::Count,
+        "Rates calculated without the rate tracker because its lock was poisoned"
+    );
+    describe_counter!(
+        UPKEEP_RUNS,
+        Unit::Count,
+        "Upkeep runs of the Prometheus recorder"
+    );
+    describe_counter!(
+        EXPORT_FAILURES,
+        Unit::Count,
+        "Failed periodic pushes of the exporters"
+    );
+}
+
+/// Records a render of the Prometheus endpoint
+pub(crate) fn record_render(duration: Duration, size: usize) {
+    histogram!(RENDER_DURATION).record(duration);
+    gauge!(RENDER_SIZE).set(size as f64);
+}
+
+pub(crate) fn set_rate_trackers(count: usize) {
+    gauge!(RATE_TRACKERS).set(count as f64);
+}
+
+pub(crate) fn record_rate_tracker_fallback() {
+    counter!(RATE_TRACKER_FALLBACKS).increment(1);
+}
+
+pub(crate) fn record_upkeep() {
+    counter!(UPKEEP_RUNS).increment(1);
+}
+
+pub(crate) fn record_export_failure(exporter: &'static str) {
+    counter!(EXPORT_FAILURES, "exporter" => exporter).increment(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cardinality::{REFUSED_SERIES_METRIC, SERIES_METRIC};
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+    #[test]
+    fn test_self_metrics_are_namespaced() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        metrics::with_local_recorder(&recorder, || {
+            describe();
+            record_render(Duration::from_millis(5), 2048);
+            set_rate_trackers(3);
+            record_rate_tracker_fallback();
+            record_upkeep();
+            record_export_failure("OTLP");
+            record_export_failure("OTLP");
+        });
+
+        let metrics = snapshotter.snapshot().into_vec();
+        assert_eq!(metrics.len(), 6);
+        for (key, unit, description, value) in metrics {
+            let name = key.key().name();
+            assert!(is_self_metric(name), "{} is not namespaced", name);
+            assert!(unit.is_some() && description.is_some());
+            if name == EXPORT_FAILURES {
+                assert_eq!(value, DebugValue::Counter(2));
+            }
+        }
+        assert!(is_self_metric(SERIES_METRIC) && is_self_metric(REFUSED_SERIES_METRIC));
+        assert!(!is_self_metric("metrics_requests"));
+    }
+}
@@ -0,0 +1,576 @@
+//! # Service Level Objectives
+//!
+//! Tracks SLOs defined on counters or histograms. The service level
+//! indicator is either the ratio of two counters (good events over all
+//! events) or the share of histogram observations at or below a latency
+//! threshold. Cumulative values are sampled on a fixed tick, so the
+//! availability over any rolling window is the ratio of the increases
+//! within that window.
+//!
+//! Every SLO publishes gauges, labelled with `slo`:
+//! - `slo_objective`: the target availability
+//! - `slo_availability`: availability over the SLO window
+//! - `slo_error_budget_remaining`: share of the error budget left, negative once exceeded
+//! - `slo_burn_rate{window="1h"}`: rate of budget consumption per burn-rate window,
+//!   where `1` uses up the budget exactly at the end of the SLO window
+//!
+//! Windows reaching back before the process started use all data since
+//! the start. The current status of every SLO is served at `/metrics/slo`.
+
+use crate::{
+    selector::Selector,
+    snapshot::{FlatSample, Snapshot},
+};
+use actix_web::{HttpResponse, Responder};
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Utc};
+use log_once::warn_once;
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+use tokio::time::MissedTickBehavior;
+
+/// Interval between two samples of the SLO indicators
+const EVALUATION_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Number of samples kept for the whole SLO window
+const WINDOW_SAMPLES: u32 = 1000;
+
+/// Tracker of the configured SLOs
+static TRACKER: OnceLock<SloTracker> = OnceLock::new();
+
+/// How good and total events of an SLO are counted
+#[derive(Debug, Clone)]
+pub enum SloIndicator {
+    /// Ratio of two counters, given as selectors such as
+    /// `http_requests_total{status!~"5.."}`; matching series are summed
+    Ratio { good: String, total: String },
+    /// Share of observations of a histogram at or below `threshold`, which
+    /// must be one of the histogram's bucket bounds
+    Latency { histogram: String, threshold: f64 },
+}
+
+/// A service level objective
+///
+/// # Example
+///
+/// ```
+/// use metrics_rs_dashboard_actix::{DashboardInput, Slo, SloIndicator};
+/// use std::time::Duration;
+///
+/// let dashboard_input = DashboardInput {
+///     slos: vec![
+///         Slo {
+///             name: "api-availability".to_string(),
+///             indicator: SloIndicator::Ratio {
+///                 good: r#"http_requests_total{status!~"5.."}"#.to_string(),
+///                 total: "http_requests_total".to_string(),
+///             },
+///             objective: 0.999,
+///             ..Default::default()
+///         },
+///         Slo {
+///             name: "api-latency".to_string(),
+///             indicator: SloIndicator::Latency {
+///                 histogram: "request_latency".to_string(),
+///                 threshold: 250.0,
+///             },
+///             objective: 0.95,
+///             window: Duration::from_secs(7 * 24 * 3600),
+///             ..Default::default()
+///         },
+///     ],
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone)]
+pub struct Slo {
+    /// Value of the `slo` label of the published gauges
+    pub name: String,
+    pub indicator: SloIndicator,
+    /// Target share of good events, e.g. `0.999`
+    pub objective: f64,
+    /// Rolling window of the availability and the error budget
+    pub window: Duration,
+    /// Windows over which burn rates are published, e.g. pairs of a long
+    /// and a short window for multi-window burn-rate alerts
+    pub burn_rate_windows: Vec<Duration>,
+}
+
+impl Default for Slo {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            indicator: SloIndicator::Ratio {
+                good: String::new(),
+                total: String::new(),
+            },
+            objective: 0.99,
+            window: Duration::from_secs(30 * 24 * 3600),
+            burn_rate_windows: [300, 1800, 3600, 6 * 3600]
+                .into_iter()
+                .map(Duration::from_secs)
+                .collect(),
+        }
+    }
+}
+
+/// Parsed form of an [`SloIndicator`]
+#[derive(Debug)]
+enum Indicator {
+    Ratio { good: Selector, total: Selector },
+    Latency { histogram: Selector, threshold: f64 },
+}
+
+impl Indicator {
+    fn new(indicator: &SloIndicator) -> Result<Self> {
+        Ok(match indicator {
+            SloIndicator::Ratio { good, total } => Indicator::Ratio {
+                good: Selector::parse(good)?,
+                total: Selector::parse(total)?,
+            },
+            SloIndicator::Latency {
+                histogram,
+                threshold,
+            } => Indicator::Latency {
+                histogram: Selector::parse(histogram)?,
+                threshold: *threshold,
+            },
+        })
+    }
+
+    /// Returns the cumulative good and total event counts, or `None` if the
+    /// metrics don't exist (yet)
+    fn measure(&self, slo: &str, samples: &[FlatSample]) -> Option<(f64, f64)> {
+        match self {
+            Indicator::Ratio { good, total } => {
+                let good = sum(samples, |sample| good.matches(sample));
+                let total = sum(samples, |sample| total.matches(sample));
+                Some((good?, total?))
+            }
+            Indicator::Latency {
+                histogram,
+                threshold,
+            } => {
+                let selected = |sample: &FlatSample, suffix: &str| {
+                    sample.family == histogram.metric
+                        && sample.name.strip_prefix(histogram.metric.as_str()) == Some(suffix)
+                        && histogram.matches_labels(&sample.labels)
+                };
+                let total = sum(samples, |sample| selected(sample, "_count"))?;
+                let good = sum(samples, |sample| {
+                    selected(sample, "_bucket")
+                        && sample
+                            .labels
+                            .get("le")
+                            .and_then(|le| le.parse::<f64>().ok())
+                            == Some(*threshold)
+                });
+                if good.is_none() {
+                    warn_once!(
+                        "SLO '{}': histogram '{}' has no bucket with bound {}",
+                        slo,
+                        histogram.metric,
+                        threshold
+                    );
+                }
+                Some((good?, total))
+            }
+        }
+    }
+}
+
+/// Sums the values of the matching samples, `None` if nothing matches
+fn sum(samples: &[FlatSample], matches: impl Fn(&FlatSample) -> bool) -> Option<f64> {
+    samples
+        .iter()
+        .filter(|sample| matches(sample))
+        .map(|sample| sample.value)
+        .reduce(|a, b| a + b)
+}
+
+/// Cumulative event counts at a point in time
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Point {
+    at: DateTime<Utc>,
+    good: f64,
+    total: f64,
+}
+
+/// Samples of an indicator: every sample for the longest burn-rate window
+/// and a coarser series for the whole SLO window
+#[derive(Debug, Default)]
+struct History {
+    fine: VecDeque<Point>,
+    coarse: VecDeque<Point>,
+    fine_retention: Duration,
+    coarse_spacing: Duration,
+    window: Duration,
+}
+
+impl History {
+    fn new(slo: &Slo) -> Self {
+        let longest_burn_window = slo.burn_rate_windows.iter().max().copied();
+        Self {
+            fine_retention: longest_burn_window.unwrap_or_default().min(slo.window),
+            coarse_spacing: slo.window / WINDOW_SAMPLES,
+            window: slo.window,
+            ..Default::default()
+        }
+    }
+
+    fn push(&mut self, point: Point) {
+        // A decreasing counter means the source restarted, so earlier
+        // samples can't be compared with the new ones
+        let last = self.fine.back().or(self.coarse.back());
+        if last.is_some_and(|last| point.good < last.good || point.total < last.total) {
+            self.fine.clear();
+            self.coarse.clear();
+        }
+
+        if self.coarse.back().is_none_or(|last| {
+            (point.at - last.at).to_std().unwrap_or_default() >= self.coarse_spacing
+        }) {
+            self.coarse.push_back(point);
+        }
+        self.fine.push_back(point);
+
+        retain_since(&mut self.fine, point.at, self.fine_retention);
+        retain_since(&mut self.coarse, point.at, self.window);
+    }
+
+    /// Availability between the oldest sample within `window` and `now`
+    fn availability(&self, now: &Point, window: Duration) -> f64 {
+        let cutoff = now.at - chrono::Duration::from_std(window).unwrap_or_default();
+        let baseline = self
+            .coarse
+            .iter()
+            .chain(&self.fine)
+            .filter(|point| point.at >= cutoff)
+            .min_by_key(|point| point.at);
+        let Some(baseline) = baseline else {
+            return 1.0;
+        };
+
+        let total = now.total - baseline.total;
+        if total <= 0.0 {
+            1.0
+        } else {
+            ((now.good - baseline.good) / total).clamp(0.0, 1.0)
+        }
+    }
+}
+
+fn retain_since(points: &mut VecDeque<Point>, now: DateTime<Utc>, retention: Duration) {
+    let cutoff = now - chrono::Duration::from_std(retention).unwrap_or_default();
+    while points.front().is_some_and(|point| point.at < cutoff) {
+        points.pop_front();
+    }
+}
+
+/// Burn rate over one window
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct BurnRate {
+    pub window: String,
+    pub value: f64,
+}
+
+/// Current state of an SLO, as served at `/metrics/slo`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct SloStatus {
+    pub name: String,
+    pub objective: f64,
+    pub window: String,
+    pub availability: f64,
+    pub error_budget_remaining: f64,
+    pub burn_rates: Vec<BurnRate>,
+}
+
+/// Indicator history and latest status of one SLO
+struct TrackedSlo {
+    slo: Slo,
+    indicator: Indicator,
+    history: Mutex<History>,
+    status: Mutex<Option<SloStatus>>,
+}
+
+/// All configured SLOs
+pub(crate) struct SloTracker {
+    slos: Vec<TrackedSlo>,
+}
+
+impl SloTracker {
+    /// Validates the SLO definitions
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the first SLO with an invalid objective,
+    /// window or selector
+    pub fn new(slos: &[Slo]) -> Result<Self> {
+        let slos = slos
+            .iter()
+            .map(|slo| {
+                let context = || format!("Invalid SLO '{}'", slo.name);
+                if !(slo.objective > 0.0 && slo.objective < 1.0) {
+                    bail!("{}: objective must be between 0 and 1", context());
+                }
+                if slo.window.is_zero() {
+                    bail!("{}: window must not be zero", context());
+                }
+                Ok(TrackedSlo {
+                    indicator: Indicator::new(&slo.indicator).with_context(context)?,
+                    history: Mutex::new(History::new(slo)),
+                    status: Mutex::new(None),
+                    slo: slo.clone(),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { slos })
+    }
+
+    /// Samples the indicators from a snapshot taken at `now`, updating the
+    /// statuses and gauges
+    pub fn evaluate(&self, snapshot: &Snapshot, now: DateTime<Utc>) {
+        let samples = snapshot.samples();
+        for tracked in &self.slos {
+            let slo = &tracked.slo;
+            let Some((good, total)) = tracked.indicator.measure(&slo.name, &samples) else {
+                continue;
+            };
+            let Ok(mut history) = tracked.history.lock() else {
+                continue;
+            };
+            let point = Point {
+                at: now,
+                good,
+                total,
+            };
+            history.push(point);
+
+            let burn_rate =
+                |window| (1.0 - history.availability(&point, window)) / (1.0 - slo.objective);
+            let availability = history.availability(&point, slo.window);
+            let status = SloStatus {
+                name: slo.name.clone(),
+                objective: slo.objective,
+                window: format_duration(slo.window),
+                availability,
+                error_budget_remaining: 1.0 - burn_rate(slo.window),
+                burn_rates: slo
+                    .burn_rate_windows
+                    .iter()
+                    .map(|window| BurnRate {
+                        window: format_duration(*window),
+                        value: burn_rate(*window),
+                    })
+                    .collect(),
+            };
+            publish(&status);
+            if let Ok(mut current) = tracked.status.lock() {
+                *current = Some(status);
+            }
+        }
+    }
+
+    /// Returns the latest status of every SLO that has been measured
+    pub fn statuses(&self) -> Vec<SloStatus> {
+        self.slos
+            .iter()
+            .filter_map(|tracked| tracked.status.lock().ok().and_then(|status| status.clone()))
+            .collect()
+    }
+}
+
+/// Sets the gauges of an SLO
+fn publish(status: &SloStatus) {
+    let slo = status.name.clone();
+    metrics::gauge!("slo_objective", "slo" => slo.clone()).set(status.objective);
+    metrics::gauge!("slo_availability", "slo" => slo.clone()).set(status.availability);
+    metrics::gauge!("slo_error_budget_remaining", "slo" => slo.clone())
+        .set(status.error_budget_remaining);
+    for burn_rate in &status.burn_rates {
+        metrics::gauge!("slo_burn_rate", "slo" => slo.clone(), "window" => burn_rate.window.clone())
+            .set(burn_rate.value);
+    }
+}
+
+/// Formats a duration with its largest whole unit, e.g. `30d`, `5m` or `250ms`
+fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    let units = [
+        (86_400_000, "d"),
+        (3_600_000, "h"),
+        (60_000, "m"),
+        (1000, "s"),
+    ];
+    units
+        .iter()
+        .find(|(unit, _)| millis >= *unit && millis.is_multiple_of(*unit))
+        .map(|(unit, suffix)| format!("{}{}", millis / unit, suffix))
+        .unwrap_or_else(|| format!("{}ms", millis))
+}
+
+/// Starts sampling the indicators of the tracked SLOs
+///
+/// Must be called from within a Tokio runtime.
+pub(crate) fn start_slo_tracking(tracker: SloTracker) {
+    if TRACKER.set(tracker).is_err() {
+        return;
+    }
+
+    tokio::spawn(async {
+        let mut ticks = tokio::time::interval(EVALUATION_INTERVAL);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            if let Some(tracker) = TRACKER.get() {
+                tracker.evaluate(&Snapshot::capture_with_targets(), Utc::now());
+            }
+        }
+    });
+}
+
+/// Response of the SLO endpoint
+#[derive(Serialize)]
+struct SloResponse {
+    slos: Vec<SloStatus>,
+}
+
+/// Endpoint listing the current status of every SLO
+///
+/// # Returns
+///
+/// JSON object with an `slos` array
+#[actix_web::get("/slo")]
+pub(crate) async fn get_slo_status() -> impl Responder {
+    let slos = TRACKER.get().map(SloTracker::statuses).unwrap_or_default();
+    HttpResponse::Ok().json(SloResponse { slos })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exposition(good: u64, total: u64) -> Snapshot {
+        Snapshot::parse(&format!(
+            "# TYPE requests_total counter\n\
+             requests_total{{status=\"200\"}} {}\n\
+             requests_total{{status=\"500\"}} {}\n\
+             # TYPE latency histogram\n\
+             latency_bucket{{le=\"0.1\"}} {}\n\
+             latency_bucket{{le=\"+Inf\"}} {}\n\
+             latency_sum 1\n\
+             latency_count {}\n",
+            good,
+            total - good,
+            good,
+            total,
+            total
+        ))
+    }
+
+    fn slo(indicator: SloIndicator) -> Slo {
+        Slo {
+            name: "api".to_string(),
+            indicator,
+            objective: 0.9,
+            window: Duration::from_secs(3600),
+            burn_rate_windows: vec![Duration::from_secs(900)],
+        }
+    }
+
+    fn ratio() -> SloIndicator {
+        SloIndicator::Ratio {
+            good: r#"requests_total{status!~"5.."}"#.to_string(),
+            total: "requests_total".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_availability_budget_and_burn_rate() {
+        let tracker = SloTracker::new(&[slo(ratio())]).unwrap();
+        let start = Utc::now();
+
+        tracker.evaluate(&exposition(0, 0), start);
+        tracker.evaluate(&exposition(100, 100), start + chrono::Duration::minutes(10));
+        // 20 of the last 40 requests failed
+        tracker.evaluate(&exposition(120, 140), start + chrono::Duration::minutes(20));
+
+        let status = &tracker.statuses()[0];
+        assert_eq!(status.window, "1h");
+        assert!((status.availability - 120.0 / 140.0).abs() < 1e-9);
+        // 14.3% errors against a 10% budget
+        assert!((status.error_budget_remaining - (1.0 - (20.0 / 140.0) / 0.1)).abs() < 1e-9);
+        assert_eq!(status.burn_rates[0].window, "15m");
+        assert!((status.burn_rates[0].value - 5.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_latency_indicator() {
+        let indicator = Indicator::new(&SloIndicator::Latency {
+            histogram: "latency".to_string(),
+            threshold: 0.1,
+        })
+        .unwrap();
+
+        let samples = exposition(90, 100).samples();
+        assert_eq!(indicator.measure("api", &samples), Some((90.0, 100.0)));
+
+        let missing_bucket = Indicator::new(&SloIndicator::Latency {
+            histogram: "latency".to_string(),
+            threshold: 0.25,
+        })
+        .unwrap();
+        assert_eq!(missing_bucket.measure("api", &samples), None);
+    }
+
+    #[test]
+    fn test_history_resets_and_windows() {
+        let mut history = History::new(&slo(ratio()));
+        let start = Utc::now();
+        let point = |minutes, good, total| Point {
+            at: start + chrono::Duration::minutes(minutes),
+            good,
+            total,
+        };
+
+        history.push(point(0, 0.0, 0.0));
+        history.push(point(30, 50.0, 100.0));
+        history.push(point(90, 150.0, 200.0));
+        // The first sample fell out of the one hour window
+        assert_eq!(
+            history.availability(&point(90, 150.0, 200.0), history.window),
+            1.0
+        );
+
+        history.push(point(91, 1.0, 2.0));
+        assert_eq!(history.coarse.len(), 1);
+        assert_eq!(history.fine.len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_slos() {
+        let invalid_objective = Slo {
+            objective: 1.0,
+            ..slo(ratio())
+        };
+        assert!(SloTracker::new(&[invalid_objective]).is_err());
+
+        let invalid_selector = slo(SloIndicator::Ratio {
+            good: "requests_total{".to_string(),
+            total: "requests_total".to_string(),
+        });
+        let error = SloTracker::new(&[invalid_selector]).err().unwrap();
+        assert!(format!("{:#}", error).contains("Invalid SLO 'api'"));
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(30 * 86400)), "30d");
+        assert_eq!(format_duration(Duration::from_secs(5400)), "90m");
+        assert_eq!(format_duration(Duration::from_millis(1500)), "1500ms");
+    }
+}
@@ -0,0 +1,728 @@
+//! # Metrics Snapshot
+//!
+//! A structured view of the rendered Prometheus exposition. The text produced
+//! by `PrometheusHandle::render()` is parsed into metric families and series,
+//! which can then be compared with an earlier snapshot so that only changed
+//! series are sent to live dashboard clients.
+
+use crate::{
+    UNITS_FOR_METRICS,
+    exemplars::{self, Exemplar},
+    federation, render_prometheus,
+};
+use serde::{Serialize, Serializer, ser::SerializeMap};
+use std::collections::{BTreeMap, HashMap};
+
+/// The type of a metric family, serialized the same way the dashboard's
+/// Prometheus text parser reports it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub(crate) enum MetricType {
+    Counter,
+    Gauge,
+    Histogram,
+    Summary,
+    Untyped,
+}
+
+/// The value of a single series
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub(crate) enum SeriesValue {
+    /// Counter, gauge and untyped values
+    Single { value: f64 },
+    /// Cumulative histogram buckets as `(upper bound, count)` pairs
+    Histogram {
+        #[serde(serialize_with = "serialize_bounds")]
+        buckets: Vec<(f64, f64)>,
+        count: f64,
+        sum: f64,
+        /// Most recent exemplar of each bucket, keyed by its upper bound
+        #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+        exemplars: BTreeMap<String, Exemplar>,
+    },
+    /// Summary quantiles as `(quantile, value)` pairs
+    Summary {
+        #[serde(serialize_with = "serialize_bounds")]
+        quantiles: Vec<(f64, f64)>,
+        count: f64,
+        sum: f64,
+    },
+}
+
+/// A single labelled series of a metric family
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Series {
+    pub labels: BTreeMap<String, String>,
+    #[serde(flatten)]
+    pub value: SeriesValue,
+}
+
+/// All series sharing a metric name
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Family {
+    pub name: String,
+    pub help: String,
+    pub metric_type: MetricType,
+    /// Series keyed by their rendered label set
+    pub series: BTreeMap<String, Series>,
+}
+
+/// A point-in-time view of every metric family
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Snapshot {
+    pub families: BTreeMap<String, Family>,
+}
+
+/// Family entry of a [`SnapshotDelta`], containing only changed series
+#[derive(Debug, Serialize)]
+pub(crate) struct FamilyDelta<'a> {
+    pub name: &'a str,
+    pub help: &'a str,
+    #[serde(rename = "type")]
+    pub metric_type: MetricType,
+    pub unit: String,
+    pub metrics: Vec<&'a Series>,
+}
+
+/// Series that disappeared since the previous snapshot
+#[derive(Debug, Serialize)]
+pub(crate) struct RemovedSeries<'a> {
+    pub name: &'a str,
+    pub labels: &'a BTreeMap<String, String>,
+}
+
+/// Difference between two snapshots
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct SnapshotDelta<'a> {
+    pub families: Vec<FamilyDelta<'a>>,
+    pub removed: Vec<RemovedSeries<'a>>,
+}
+
+impl MetricType {
+    fn parse(name: &str) -> Self {
+        match name {
+            "counter" => MetricType::Counter,
+            "gauge" => MetricType::Gauge,
+            "histogram" => MetricType::Histogram,
+            "summary" => MetricType::Summary,
+            _ => MetricType::Untyped,
+        }
+    }
+}
+
+impl SnapshotDelta<'_> {
+    /// Returns true if nothing changed between the two snapshots
+    pub fn is_empty(&self) -> bool {
+        self.families.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Serializes `(bound, value)` pairs as a JSON object keyed by the bound
+///
+/// Matches the `buckets`/`quantiles` shape produced by the dashboard's
+/// Prometheus text parser, including `+Inf` for the last histogram bucket.
+fn serialize_bounds<S: Serializer>(pairs: &[(f64, f64)], serializer: S) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(pairs.len()))?;
+    for (bound, value) in pairs {
+        map.serialize_entry(&format_bound(*bound), value)?;
+    }
+    map.end()
+}
+
+/// Formats a bucket bound or quantile the way the exposition format does
+pub(crate) fn format_bound(bound: f64) -> String {
+    if bound == f64::INFINITY {
+        "+Inf".to_string()
+    } else {
+        bound.to_string()
+    }
+}
+
+/// A single sample of a snapshot, as Prometheus stores it after a scrape
+///
+/// Histogram and summary series are split into `_bucket`/quantile, `_sum`
+/// and `_count` samples. Label values are unescaped.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct FlatSample {
+    pub name: String,
+    /// Name of the family the sample belongs to
+    pub family: String,
+    pub labels: BTreeMap<String, String>,
+    pub value: f64,
+}
+
+/// Reverts the escaping of label values in the text exposition
+pub(crate) fn unescape_label_value(value: &str) -> String {
+    let mut output = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => output.push('\n'),
+            Some(other) => output.push(other),
+            None => output.push('\\'),
+        }
+    }
+    output
+}
+
+/// Builds the key identifying a series within its family
+pub(crate) fn series_key(labels: &BTreeMap<String, String>) -> String {
+    labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, v))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl Snapshot {
+    /// Captures the current state of all recorded metrics, including the
+    /// exemplars of bucketed histograms
+    pub fn capture() -> Self {
+        let mut snapshot = Self::parse(&render_prometheus());
+        for (name, family) in &mut snapshot.families {
+            for (key, series) in &mut family.series {
+                if let SeriesValue::Histogram { exemplars, .. } = &mut series.value {
+                    *exemplars = exemplars::for_series(name, key);
+                }
+            }
+        }
+        snapshot
+    }
+
+    /// Captures the local metrics together with the latest scrape of every
+    /// configured target, as shown in the dashboard
+    pub fn capture_with_targets() -> Self {
+        let mut snapshot = Self::capture();
+        federation::merge_targets(&mut snapshot);
+        snapshot
+    }
+
+    /// Parses a Prometheus text exposition into a snapshot
+    ///
+    /// Histogram and summary `_sum`/`_count` samples are folded into the
+    /// series of their parent family. Lines that cannot be parsed are skipped.
+    /// Label values are kept escaped as in the exposition.
+    pub fn parse(text: &str) -> Self {
+        let mut docs: HashMap<String, String> = HashMap::new();
+        let mut types: HashMap<String, MetricType> = HashMap::new();
+        let mut families: BTreeMap<String, Family> = BTreeMap::new();
+        // Buckets and quantiles of a series are spread over several lines
+        let mut grouped: BTreeMap<(String, String), (BTreeMap<String, String>, SeriesValue)> =
+            BTreeMap::new();
+        let mut untyped = Vec::new();
+
+        for line in text.lines() {
+            let line = line.trim();
+            if let Some(comment) = line.strip_prefix('#') {
+                match parse_comment(comment) {
+                    Some(("HELP", name, help)) => {
+                        docs.insert(name.to_owned(), help.to_owned());
+                    }
+                    Some(("TYPE", name, metric_type)) => {
+                        types.insert(name.to_owned(), MetricType::parse(metric_type));
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            let Some((metric, mut labels, value)) = parse_sample(line) else {
+                continue;
+            };
+
+            let histogram = metric
+                .strip_suffix("_bucket")
+                .filter(|base| types.get(*base) == Some(&MetricType::Histogram));
+            let (family, bound_label) = match (histogram, types.get(metric)) {
+                (Some(base), _) => (base, "le"),
+                (None, Some(MetricType::Summary)) => (metric, "quantile"),
+                (None, Some(metric_type @ (MetricType::Counter | MetricType::Gauge))) => {
+                    insert_series(
+                        &mut families,
+                        &docs,
+                        metric.to_owned(),
+                        *metric_type,
+                        labels,
+                        SeriesValue::Single { value },
+                    );
+                    continue;
+                }
+                (None, _) => {
+                    untyped.push((metric.to_owned(), labels, value));
+                    continue;
+                }
+            };
+
+            let Some(bound) = labels
+                .remove(bound_label)
+                .and_then(|bound| bound.parse::<f64>().ok())
+            else {
+                continue;
+            };
+            let (_, series) = grouped
+                .entry((family.to_owned(), series_key(&labels)))
+                .or_insert_with(|| {
+                    let value = match bound_label {
+                        "le" => SeriesValue::Histogram {
+                            buckets: Vec::new(),
+                            count: 0.0,
+                            sum: 0.0,
+                            exemplars: BTreeMap::new(),
+                        },
+                        _ => SeriesValue::Summary {
+                            quantiles: Vec::new(),
+                            count: 0.0,
+                            sum: 0.0,
+                        },
+                    };
+                    (labels, value)
+                });
+            match series {
+                SeriesValue::Histogram { buckets: pairs, .. }
+                | SeriesValue::Summary {
+                    quantiles: pairs, ..
+                } => pairs.push((bound, value)),
+                SeriesValue::Single { .. } => {}
+            }
+        }
+
+        for ((family, _), (labels, mut value)) in grouped {
+            let metric_type = match &mut value {
+                SeriesValue::Histogram { buckets: pairs, .. } => {
+                    pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+                    MetricType::Histogram
+                }
+                SeriesValue::Summary {
+                    quantiles: pairs, ..
+                } => {
+                    pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+                    MetricType::Summary
+                }
+                SeriesValue::Single { .. } => MetricType::Untyped,
+            };
+            insert_series(&mut families, &docs, family, metric_type, labels, value);
+        }
+
+        // `_sum` and `_count` samples of histograms and summaries are untyped,
+        // so attach them to their parent series where possible
+        for (metric, labels, value) in untyped {
+            if attach_aggregate(&mut families, &metric, &labels, value) {
+                continue;
+            }
+            insert_series(
+                &mut families,
+                &docs,
+                metric,
+                MetricType::Untyped,
+                labels,
+                SeriesValue::Single { value },
+            );
+        }
+
+        Self { families }
+    }
+
+    /// Flattens all series into individual samples
+    pub fn samples(&self) -> Vec<FlatSample> {
+        let mut samples = Vec::new();
+
+        for (name, family) in &self.families {
+            for series in family.series.values() {
+                let mut push = |suffix: &str, extra: Option<(&str, f64)>, value: f64| {
+                    let mut labels: BTreeMap<String, String> = series
+                        .labels
+                        .iter()
+                        .map(|(k, v)| (k.clone(), unescape_label_value(v)))
+                        .collect();
+                    if let Some((k, bound)) = extra {
+                        labels.insert(k.to_owned(), format_bound(bound));
+                    }
+                    samples.push(FlatSample {
+                        name: format!("{}{}", name, suffix),
+                        family: name.clone(),
+                        labels,
+                        value,
+                    });
+                };
+                match &series.value {
+                    SeriesValue::Single { value } => push("", None, *value),
+                    SeriesValue::Histogram {
+                        buckets,
+                        count,
+                        sum,
+                        ..
+                    } => {
+                        for (bound, bucket_count) in buckets {
+                            push("_bucket", Some(("le", *bound)), *bucket_count);
+                        }
+                        push("_sum", None, *sum);
+                        push("_count", None, *count);
+                    }
+                    SeriesValue::Summary {
+                        quantiles,
+                        count,
+                        sum,
+                    } => {
+                        for (quantile, value) in quantiles {
+                            push("", Some(("quantile", *quantile)), *value);
+                        }
+                        push("_sum", None, *sum);
+                        push("_count", None, *count);
+                    }
+                }
+            }
+        }
+
+        samples
+    }
+
+    /// Computes the series that changed compared to `previous`
+    ///
+    /// Passing `None` yields every series, which is used for the first
+    /// message sent to a newly connected client.
+    pub fn delta<'a>(&'a self, previous: Option<&'a Snapshot>) -> SnapshotDelta<'a> {
+        self.delta_filtered(previous, |_| true)
+    }
+
+    /// Same as [`Snapshot::delta`], restricted to families accepted by `filter`
+    pub fn delta_filtered<'a>(
+        &'a self,
+        previous: Option<&'a Snapshot>,
+        filter: impl Fn(&str) -> bool,
+    ) -> SnapshotDelta<'a> {
+        let units = UNITS_FOR_METRICS
+            .get()
+            .and_then(|units| units.lock().ok().map(|units| units.clone()))
+            .unwrap_or_default();
+        let mut delta = SnapshotDelta::default();
+
+        for (name, family) in &self.families {
+            if !filter(name) {
+                continue;
+            }
+            let previous_family = previous.and_then(|p| p.families.get(name));
+            let metrics: Vec<&Series> = family
+                .series
+                .iter()
+                .filter(|(key, series)| {
+                    previous_family.and_then(|f| f.series.get(*key)) != Some(*series)
+                })
+                .map(|(_, series)| series)
+                .collect();
+
+            if !metrics.is_empty() {
+                delta.families.push(FamilyDelta {
+                    name,
+                    help: &family.help,
+                    metric_type: family.metric_type,
+                    unit: units
+                        .get(name)
+                        .cloned()
+                        .unwrap_or_else(|| "count".to_string()),
+                    metrics,
+                });
+            }
+        }
+
+        if let Some(previous) = previous {
+            for (name, family) in &previous.families {
+                if !filter(name) {
+                    continue;
+                }
+                let current = self.families.get(name);
+                for (key, series) in &family.series {
+                    if current.is_none_or(|f| !f.series.contains_key(key)) {
+                        delta.removed.push(RemovedSeries {
+                            name,
+                            labels: &series.labels,
+                        });
+                    }
+                }
+            }
+        }
+
+        delta
+    }
+}
+
+/// Splits a `# HELP` or `# TYPE` line, without the `#`, into its keyword,
+/// metric name and the rest of the line
+fn parse_comment(comment: &str) -> Option<(&str, &str, &str)> {
+    let (keyword, rest) = comment.trim_start().split_once(char::is_whitespace)?;
+    let (name, rest) = rest.trim_start().split_once(char::is_whitespace)?;
+    let rest = rest.trim_start();
+    (!rest.is_empty()).then_some((keyword, name, rest))
+}
+
+/// Parses a sample line into its metric name, escaped label values and value
+///
+/// Label values are read up to their closing unescaped quote, so they may
+/// contain `,`, `=` or `}`. Timestamps and exemplars are ignored.
+fn parse_sample(line: &str) -> Option<(&str, BTreeMap<String, String>, f64)> {
+    let name_end = line
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == ':'))
+        .unwrap_or(line.len());
+    let (name, mut rest) = line.split_at(name_end);
+    if name.is_empty() {
+        return None;
+    }
+
+    let mut labels = BTreeMap::new();
+    if let Some(mut inner) = rest.strip_prefix('{') {
+        loop {
+            inner = inner.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+            if let Some(after) = inner.strip_prefix('}') {
+                rest = after;
+                break;
+            }
+            let (label, after) = inner.split_once('=')?;
+            let after = after.trim_start().strip_prefix('"')?;
+            let mut escaped = false;
+            let close = after.char_indices().find_map(|(i, c)| match c {
+                _ if escaped => {
+                    escaped = false;
+                    None
+                }
+                '\\' => {
+                    escaped = true;
+                    None
+                }
+                '"' => Some(i),
+                _ => None,
+            })?;
+            labels.insert(label.trim().to_owned(), after[..close].to_owned());
+            inner = &after[close + 1..];
+        }
+    }
+
+    let value = rest.split_whitespace().next()?.parse().ok()?;
+    Some((name, labels, value))
+}
+
+/// Inserts a series into its family, creating the family if needed
+fn insert_series(
+    families: &mut BTreeMap<String, Family>,
+    docs: &HashMap<String, String>,
+    name: String,
+    metric_type: MetricType,
+    labels: BTreeMap<String, String>,
+    value: SeriesValue,
+) {
+    let family = families.entry(name.clone()).or_insert_with(|| Family {
+        help: docs.get(&name).cloned().unwrap_or_default(),
+        name,
+        metric_type,
+        series: BTreeMap::new(),
+    });
+    family
+        .series
+        .insert(series_key(&labels), Series { labels, value });
+}
+
+/// Attaches a `_sum`/`_count` sample to its histogram or summary series
+///
+/// Returns false if the sample does not belong to a known parent series.
+fn attach_aggregate(
+    families: &mut BTreeMap<String, Family>,
+    metric: &str,
+    labels: &BTreeMap<String, String>,
+    sample_value: f64,
+) -> bool {
+    let (base, is_sum) = if let Some(base) = metric.strip_suffix("_sum") {
+        (base, true)
+    } else if let Some(base) = metric.strip_suffix("_count") {
+        (base, false)
+    } else {
+        return false;
+    };
+
+    let Some(series) = families
+        .get_mut(base)
+        .and_then(|family| family.series.get_mut(&series_key(labels)))
+    else {
+        return false;
+    };
+
+    match &mut series.value {
+        SeriesValue::Histogram { count, sum, .. } | SeriesValue::Summary { count, sum, .. } => {
+            if is_sum {
+                *sum = sample_value;
+            } else {
+                *count = sample_value;
+            }
+            true
+        }
+        SeriesValue::Single { .. } => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPOSITION: &str = r#"# HELP requests_total Total requests
+# TYPE requests_total counter
+requests_total{endpoint="/a"} 3
+requests_total{endpoint="/b"} 5
+
+# TYPE temperature gauge
+temperature 21.5
+
+# HELP latency Request latency
+# TYPE latency histogram
+latency_bucket{le="0.1"} 1
+latency_bucket{le="0.5"} 4
+latency_bucket{le="+Inf"} 6
+latency_sum 2.5
+latency_count 6
+
+# TYPE payload summary
+payload{quantile="0.5"} 10
+payload{quantile="0.99"} 40
+payload_sum 120
+payload_count 8
+"#;
+
+    #[test]
+    fn test_snapshot_parse_families() {
+        let snapshot = Snapshot::parse(EXPOSITION);
+
+        let requests = &snapshot.families["requests_total"];
+        assert_eq!(requests.metric_type, MetricType::Counter);
+        assert_eq!(requests.help, "Total requests");
+        assert_eq!(requests.series.len(), 2);
+        assert_eq!(
+            requests.series["endpoint=\"/b\""].value,
+            SeriesValue::Single { value: 5.0 }
+        );
+
+        assert_eq!(
+            snapshot.families["temperature"].metric_type,
+            MetricType::Gauge
+        );
+        assert!(!snapshot.families.contains_key("latency_sum"));
+        assert!(!snapshot.families.contains_key("payload_count"));
+    }
+
+    #[test]
+    fn test_snapshot_parse_histogram_and_summary() {
+        let snapshot = Snapshot::parse(EXPOSITION);
+
+        assert_eq!(
+            snapshot.families["latency"].series[""].value,
+            SeriesValue::Histogram {
+                buckets: vec![(0.1, 1.0), (0.5, 4.0), (f64::INFINITY, 6.0)],
+                count: 6.0,
+                sum: 2.5,
+                exemplars: BTreeMap::new(),
+            }
+        );
+        assert_eq!(
+            snapshot.families["payload"].series[""].value,
+            SeriesValue::Summary {
+                quantiles: vec![(0.5, 10.0), (0.99, 40.0)],
+                count: 8.0,
+                sum: 120.0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_snapshot_delta_only_changed_series() {
+        let previous = Snapshot::parse(EXPOSITION);
+        let current = Snapshot::parse(
+            &EXPOSITION
+                .replace(
+                    "requests_total{endpoint=\"/b\"} 5",
+                    "requests_total{endpoint=\"/b\"} 6",
+                )
+                .replace("temperature 21.5\n", ""),
+        );
+
+        let delta = current.delta(Some(&previous));
+
+        assert_eq!(delta.families.len(), 1);
+        assert_eq!(delta.families[0].name, "requests_total");
+        assert_eq!(delta.families[0].metrics.len(), 1);
+        assert_eq!(delta.removed.len(), 1);
+        assert_eq!(delta.removed[0].name, "temperature");
+
+        assert!(current.delta(Some(&current)).is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_samples() {
+        let samples = Snapshot::parse(EXPOSITION).samples();
+
+        let bucket = samples
+            .iter()
+            .find(|s| s.name == "latency_bucket" && s.labels["le"] == "+Inf")
+            .unwrap();
+        assert_eq!(bucket.value, 6.0);
+        assert!(samples.iter().any(|s| s.name == "latency_count"));
+        assert!(
+            samples
+                .iter()
+                .any(|s| s.name == "payload" && s.labels["quantile"] == "0.99")
+        );
+        assert_eq!(samples.len(), 12);
+    }
+
+    #[test]
+    fn test_unescape_label_values() {
+        assert_eq!(unescape_label_value(r#"a\"b\\c\nd"#), "a\"b\\c\nd");
+    }
+
+    #[test]
+    fn test_label_values_with_separators() {
+        let snapshot = Snapshot::parse(
+            "# TYPE requests_total counter\n\
+             requests_total{path=\"/a,b=c\",agent=\"x}y \\\"z\\\"\"} 3 1700000000000\n\
+             # TYPE latency histogram\n\
+             latency_bucket{path=\"/a,b=c\",le=\"1\"} 1\n\
+             latency_bucket{path=\"/a,b=c\",le=\"+Inf\"} 2\n\
+             latency_sum{path=\"/a,b=c\"} 0.5\n\
+             latency_count{path=\"/a,b=c\"} 2\n",
+        );
+
+        let requests = &snapshot.families["requests_total"];
+        let series = requests.series.values().next().unwrap();
+        assert_eq!(series.labels["path"], "/a,b=c");
+        assert_eq!(series.labels["agent"], r#"x}y \"z\""#);
+        assert_eq!(series.value, SeriesValue::Single { value: 3.0 });
+
+        let latency = &snapshot.families["latency"].series["path=\"/a,b=c\""];
+        assert_eq!(
+            latency.value,
+            SeriesValue::Histogram {
+                buckets: vec![(1.0, 1.0), (f64::INFINITY, 2.0)],
+                count: 2.0,
+                sum: 0.5,
+                exemplars: BTreeMap::new(),
+            }
+        );
+        let flat = snapshot.samples();
+        assert!(flat.iter().all(|sample| sample.labels["path"] == "/a,b=c"));
+    }
+
+    #[test]
+    fn test_snapshot_delta_serializes_dashboard_shape() {
+        let snapshot = Snapshot::parse(EXPOSITION);
+        let delta = snapshot.delta(None);
+        let json = serde_json::to_value(&delta).unwrap();
+
+        let latency = json["families"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|f| f["name"] == "latency")
+            .unwrap();
+        assert_eq!(latency["type"], "HISTOGRAM");
+        assert_eq!(latency["metrics"][0]["buckets"]["+Inf"], 6.0);
+        assert_eq!(latency["metrics"][0]["count"], 6.0);
+    }
+}
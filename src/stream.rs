@@ -0,0 +1,264 @@
+//! # Live Metrics Stream
+//!
+//! Server-Sent Events endpoint used by the dashboard. Instead of polling and
+//! re-parsing the full Prometheus text, clients receive a JSON delta with only
+//! the series that changed since the previous event.
+//!
+//! Metrics are rendered and parsed once per tick by a single task, whose
+//! snapshots are shared by every connected client through a watch channel.
+//! Each client only computes its own delta.
+
+use crate::{self_metrics, snapshot::Snapshot};
+use actix_web::{
+    HttpResponse, Responder,
+    http::header::{CacheControl, CacheDirective, ContentEncoding},
+    web::{self, Bytes},
+};
+use futures_util::stream;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::watch,
+    time::{Interval, MissedTickBehavior},
+};
+
+/// Default minimum interval between two stream events, matching the
+/// smallest refresh rate offered by the dashboard
+pub(crate) const DEFAULT_STREAM_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Interval after which an idle stream sends a comment, so that proxies
+/// don't close the connection
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// SSE comment sent on idle streams
+const KEEPALIVE_EVENT: &[u8] = b": keepalive\n\n";
+
+/// Latest snapshot of a feed, `None` until the first capture
+pub(crate) type SnapshotFeed = watch::Receiver<Option<Arc<Snapshot>>>;
+
+/// Publishing side of a [`SnapshotFeed`]
+type FeedSender = watch::Sender<Option<Arc<Snapshot>>>;
+
+/// Running snapshot feeds, one for each stream configuration
+static FEEDS: OnceLock<Mutex<HashMap<StreamSettings, FeedSender>>> = OnceLock::new();
+
+/// Per-scope settings of the live metrics stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct StreamSettings {
+    /// Shortest interval a client may request
+    pub min_interval: Duration,
+    /// Whether the crate's own metrics are left out
+    pub hide_self_metrics: bool,
+}
+
+/// Query parameters accepted by the stream endpoint
+#[derive(Debug, Deserialize)]
+struct StreamQuery {
+    /// Requested interval between events in milliseconds
+    interval_ms: Option<u64>,
+}
+
+impl StreamSettings {
+    /// Resolves the interval for a client, never going below the configured minimum
+    pub fn interval_for(&self, requested_ms: Option<u64>) -> Duration {
+        requested_ms
+            .map(Duration::from_millis)
+            .unwrap_or(self.min_interval)
+            .max(self.min_interval)
+    }
+
+    /// Captures the metrics sent to dashboards
+    pub fn capture(&self) -> Snapshot {
+        let mut snapshot = Snapshot::capture_with_targets();
+        if self.hide_self_metrics {
+            snapshot
+                .families
+                .retain(|name, _| !self_metrics::is_self_metric(name));
+        }
+        snapshot
+    }
+
+    /// Subscribes to the snapshots captured every `min_interval` for these
+    /// settings, starting the capturing task if no client is subscribed yet
+    pub fn subscribe(&self) -> SnapshotFeed {
+        let feeds = FEEDS.get_or_init(|| Mutex::new(HashMap::new()));
+        let Ok(mut feeds) = feeds.lock() else {
+            // Without the registry the client gets a feed of its own
+            let (sender, receiver) = watch::channel(None);
+            tokio::spawn(capture_snapshots(*self, sender));
+            return receiver;
+        };
+        if let Some(sender) = feeds.get(self) {
+            return sender.subscribe();
+        }
+        let (sender, receiver) = watch::channel(None);
+        feeds.insert(*self, sender.clone());
+        tokio::spawn(capture_snapshots(*self, sender));
+        receiver
+    }
+}
+
+/// Captures a snapshot every `min_interval` and publishes it to the feed,
+/// until the last client is gone
+async fn capture_snapshots(settings: StreamSettings, sender: FeedSender) {
+    let mut interval = client_interval(settings.min_interval);
+    loop {
+        interval.tick().await;
+        if sender.receiver_count() == 0 {
+            // Checked again under the lock, so that no client subscribes
+            // to a feed that is going away
+            let mut feeds = FEEDS.get().and_then(|feeds| feeds.lock().ok());
+            if sender.receiver_count() == 0 {
+                if let Some(feeds) = feeds.as_mut()
+                    && feeds
+                        .get(&settings)
+                        .is_some_and(|registered| registered.same_channel(&sender))
+                {
+                    feeds.remove(&settings);
+                }
+                return;
+            }
+        }
+        sender.send_replace(Some(Arc::new(settings.capture())));
+    }
+}
+
+/// Creates a ticking interval that delays missed ticks instead of bursting
+pub(crate) fn client_interval(period: Duration) -> Interval {
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    interval
+}
+
+/// Waits for the next tick of a client and returns the latest shared
+/// snapshot, or `None` if the feed has stopped
+pub(crate) async fn next_snapshot(
+    interval: &mut Interval,
+    feed: &mut SnapshotFeed,
+) -> Option<Arc<Snapshot>> {
+    interval.tick().await;
+    if feed.borrow().is_none() {
+        feed.changed().await.ok()?;
+    }
+    feed.borrow_and_update().clone()
+}
+
+/// Formats a snapshot delta as a single SSE `metrics` event, or returns
+/// `None` if nothing changed since the previous snapshot
+fn delta_event(current: &Snapshot, previous: Option<&Snapshot>) -> Option<Bytes> {
+    let delta = current.delta(previous);
+    if previous.is_some() && delta.is_empty() {
+        return None;
+    }
+    let data = serde_json::to_string(&delta).unwrap_or_else(|_| "{}".to_string());
+    Some(Bytes::from(format!("event: metrics\ndata: {}\n\n", data)))
+}
+
+/// Server-Sent Events endpoint streaming metric changes
+///
+/// The first event contains every series; each following event contains only
+/// the series whose value changed and the series that disappeared. Metrics are
+/// checked at the interval requested with `?interval_ms=`, which is clamped to
+/// the minimum configured on the server, and no event is sent while nothing
+/// changes apart from an occasional keepalive comment.
+///
+/// # Returns
+///
+/// A `text/event-stream` response emitting `metrics` events
+#[actix_web::get("/stream")]
+pub(crate) async fn get_metrics_stream(
+    settings: web::Data<StreamSettings>,
+    query: web::Query<StreamQuery>,
+) -> impl Responder {
+    let interval = client_interval(settings.interval_for(query.interval_ms));
+    let feed = settings.subscribe();
+
+    let events = stream::unfold(
+        (interval, feed, None::<Arc<Snapshot>>, Instant::now()),
+        move |(mut interval, mut feed, mut previous, last_event)| async move {
+            loop {
+                let current = next_snapshot(&mut interval, &mut feed).await?;
+                let event = match delta_event(&current, previous.as_deref()) {
+                    Some(event) => event,
+                    None if last_event.elapsed() >= KEEPALIVE_INTERVAL => {
+                        Bytes::from_static(KEEPALIVE_EVENT)
+                    }
+                    None => {
+                        previous = Some(current);
+                        continue;
+                    }
+                };
+                let state = (interval, feed, Some(current), Instant::now());
+                return Some((Ok::<_, actix_web::Error>(event), state));
+            }
+        },
+    );
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        // Compressors buffer output, which would hold events back
+        .insert_header(ContentEncoding::Identity)
+        .streaming(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_interval_is_clamped_to_minimum() {
+        let settings = StreamSettings {
+            min_interval: Duration::from_millis(500),
+            hide_self_metrics: false,
+        };
+
+        assert_eq!(settings.interval_for(None), Duration::from_millis(500));
+        assert_eq!(settings.interval_for(Some(100)), Duration::from_millis(500));
+        assert_eq!(
+            settings.interval_for(Some(2000)),
+            Duration::from_millis(2000)
+        );
+    }
+
+    #[test]
+    fn test_delta_event_format() {
+        let snapshot = Snapshot::parse("# TYPE up gauge\nup 1\n");
+        let event = delta_event(&snapshot, None).unwrap();
+        let event = std::str::from_utf8(&event).unwrap();
+
+        assert!(event.starts_with("event: metrics\ndata: {"));
+        assert!(event.ends_with("\n\n"));
+        assert!(event.contains("\"name\":\"up\""));
+
+        // Unchanged metrics produce no event
+        assert!(delta_event(&snapshot, Some(&snapshot)).is_none());
+        let changed = Snapshot::parse("# TYPE up gauge\nup 0\n");
+        assert!(delta_event(&changed, Some(&snapshot)).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_clients_share_one_feed() {
+        let settings = StreamSettings {
+            min_interval: Duration::from_millis(13),
+            hide_self_metrics: true,
+        };
+        let mut first = settings.subscribe();
+        let second = settings.subscribe();
+
+        let mut interval = client_interval(settings.min_interval);
+        let snapshot = next_snapshot(&mut interval, &mut first).await.unwrap();
+        let shared = second.borrow().clone().unwrap();
+        assert!(Arc::ptr_eq(&snapshot, &shared));
+
+        // The capturing task stops once every client is gone
+        drop((first, second));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let feeds = FEEDS.get().unwrap().lock().unwrap();
+        assert!(!feeds.contains_key(&settings));
+    }
+}
@@ -0,0 +1,165 @@
+//! # Exact Quantiles
+//!
+//! Histograms only tell which bucket an observation fell into, so quantiles
+//! computed from them are interpolated. For histograms matched by
+//! `DashboardInput::exact_quantiles`, this recorder additionally collects
+//! every observation and, at every sample of the [history](crate::history),
+//! feeds the observations made since the previous sample into a
+//! [`metrics_util::storage::Summary`].
+//!
+//! The resulting quantiles are stored in the history as series of the
+//! histogram's own name with a `quantile` label, the way Prometheus
+//! summaries look. They are not part of the Prometheus exposition.
+
+use crate::{query::DEFAULT_QUANTILES, snapshot::FlatSample};
+use metrics::{Counter, Gauge, Histogram, Key, KeyName, Metadata, Recorder, SharedString, Unit};
+use metrics_exporter_prometheus::Matcher;
+use metrics_util::{
+    registry::{AtomicStorage, Registry},
+    storage::Summary,
+};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, OnceLock},
+};
+
+/// Registry of the recorder installed in the fanout, drained by the history
+static STATE: OnceLock<Arc<SummaryState>> = OnceLock::new();
+
+/// Observations of the matched histograms since the last drain
+struct SummaryState {
+    matchers: Vec<Matcher>,
+    registry: Registry<Key, AtomicStorage>,
+}
+
+/// Recorder collecting the observations of the matched histograms
+pub(crate) struct SummaryRecorder {
+    state: Arc<SummaryState>,
+}
+
+impl SummaryRecorder {
+    /// Creates the recorder and makes its observations available to
+    /// [`drain_quantiles`], or returns `None` without matchers
+    pub fn install(matchers: &[Matcher]) -> Option<Self> {
+        if matchers.is_empty() {
+            return None;
+        }
+        let state = STATE.get_or_init(|| {
+            Arc::new(SummaryState {
+                matchers: matchers.to_vec(),
+                registry: Registry::atomic(),
+            })
+        });
+        Some(Self {
+            state: state.clone(),
+        })
+    }
+}
+
+impl Recorder for SummaryRecorder {
+    fn describe_counter(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+    fn describe_gauge(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+    fn describe_histogram(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+    fn register_counter(&self, _key: &Key, _metadata: &Metadata<'_>) -> Counter {
+        Counter::noop()
+    }
+
+    fn register_gauge(&self, _key: &Key, _metadata: &Metadata<'_>) -> Gauge {
+        Gauge::noop()
+    }
+
+    fn register_histogram(&self, key: &Key, _metadata: &Metadata<'_>) -> Histogram {
+        let matched = self
+            .state
+            .matchers
+            .iter()
+            .any(|matcher| matcher.matches(key.name()));
+        if !matched {
+            return Histogram::noop();
+        }
+        self.state
+            .registry
+            .get_or_create_histogram(key, |histogram| Histogram::from_arc(histogram.clone()))
+    }
+}
+
+impl SummaryState {
+    /// Computes the quantiles of the observations made since the last call,
+    /// skipping histograms without new observations
+    fn drain_quantiles(&self) -> Vec<FlatSample> {
+        let mut samples = Vec::new();
+        self.registry.visit_histograms(|key, bucket| {
+            let mut summary = Summary::with_defaults();
+            bucket.clear_with(|values| {
+                for value in values {
+                    summary.add(*value);
+                }
+            });
+            if summary.is_empty() {
+                return;
+            }
+
+            let labels: BTreeMap<String, String> = key
+                .labels()
+                .map(|label| (label.key().to_owned(), label.value().to_owned()))
+                .collect();
+            for quantile in DEFAULT_QUANTILES {
+                let Some(value) = summary.quantile(quantile) else {
+                    continue;
+                };
+                let mut labels = labels.clone();
+                labels.insert("quantile".to_owned(), quantile.to_string());
+                samples.push(FlatSample {
+                    name: key.name().to_owned(),
+                    family: key.name().to_owned(),
+                    labels,
+                    value,
+                });
+            }
+        });
+        samples
+    }
+}
+
+/// Quantiles of the observations of matched histograms since the last call
+pub(crate) fn drain_quantiles() -> Vec<FlatSample> {
+    STATE
+        .get()
+        .map(|state| state.drain_quantiles())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantiles_of_matched_histograms() {
+        let recorder = SummaryRecorder {
+            state: Arc::new(SummaryState {
+                matchers: vec![Matcher::Prefix("latency".to_string())],
+                registry: Registry::atomic(),
+            }),
+        };
+        metrics::with_local_recorder(&recorder, || {
+            for value in 1..=100 {
+                metrics::histogram!("latency", "path" => "/a").record(value as f64);
+            }
+            metrics::histogram!("other").record(1.0);
+        });
+
+        let samples = recorder.state.drain_quantiles();
+        assert_eq!(samples.len(), DEFAULT_QUANTILES.len());
+        let median = &samples[0];
+        assert_eq!(median.name, "latency");
+        assert_eq!(median.labels["path"], "/a");
+        assert_eq!(median.labels["quantile"], "0.5");
+        assert!((median.value - 50.0).abs() <= 1.0);
+
+        // Observations are only counted once
+        assert!(recorder.state.drain_quantiles().is_empty());
+    }
+}
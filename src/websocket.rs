@@ -0,0 +1,206 @@
+//! # WebSocket Subscriptions
+//!
+//! WebSocket endpoint for remote dashboards on slow links. A client subscribes
+//! to a metric name prefix or regular expression and receives only the deltas
+//! of matching series, computed from the same snapshots as the SSE stream.
+//!
+//! Client messages are JSON objects such as:
+//!
+//! ```json
+//! {"subscribe": {"prefix": "http_"}, "interval_ms": 2000}
+//! {"subscribe": {"regex": "^(http|db)_.*_total$"}}
+//! ```
+
+use crate::{
+    snapshot::Snapshot,
+    stream::{self, SnapshotFeed, StreamSettings},
+};
+use actix_web::{HttpRequest, HttpResponse, web};
+use actix_ws::{AggregatedMessage, AggregatedMessageStream, Session};
+use futures_util::StreamExt;
+use regex::Regex;
+use serde::Deserialize;
+use std::sync::Arc;
+
+/// Metric name filter requested by a client
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Subscription {
+    /// Matches metric names starting with the given prefix
+    Prefix(String),
+    /// Matches metric names against a regular expression
+    Regex(String),
+}
+
+/// Message sent by a WebSocket client
+#[derive(Debug, Deserialize)]
+struct ClientMessage {
+    subscribe: Subscription,
+    /// Requested interval between updates in milliseconds
+    interval_ms: Option<u64>,
+}
+
+/// Compiled form of a [`Subscription`]
+#[derive(Debug)]
+enum SeriesFilter {
+    Prefix(String),
+    Regex(Regex),
+}
+
+impl Subscription {
+    /// Compiles the subscription into a filter
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the regular expression is invalid
+    fn compile(self) -> Result<SeriesFilter, regex::Error> {
+        match self {
+            Subscription::Prefix(prefix) => Ok(SeriesFilter::Prefix(prefix)),
+            Subscription::Regex(pattern) => Regex::new(&pattern).map(SeriesFilter::Regex),
+        }
+    }
+}
+
+impl SeriesFilter {
+    /// Returns true if the metric name matches this filter
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            SeriesFilter::Prefix(prefix) => name.starts_with(prefix.as_str()),
+            SeriesFilter::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+/// Formats an error message for the client
+fn error_message(message: impl std::fmt::Display) -> String {
+    serde_json::json!({ "error": message.to_string() }).to_string()
+}
+
+/// WebSocket endpoint streaming deltas of subscribed series
+///
+/// Nothing is sent until the client subscribes. After each subscription the
+/// client receives all matching series once, followed by deltas containing
+/// only changed or removed series. Intervals without changes send nothing.
+///
+/// # Returns
+///
+/// The WebSocket handshake response
+#[actix_web::get("/ws")]
+pub(crate) async fn get_metrics_ws(
+    req: HttpRequest,
+    body: web::Payload,
+    settings: web::Data<StreamSettings>,
+) -> actix_web::Result<HttpResponse> {
+    let (response, session, messages) = actix_ws::handle(&req, body)?;
+    let messages = messages.aggregate_continuations();
+    actix_web::rt::spawn(run_session(session, messages, *settings.get_ref()));
+    Ok(response)
+}
+
+/// Drives a single WebSocket connection until either side closes it
+async fn run_session(
+    mut session: Session,
+    mut messages: AggregatedMessageStream,
+    settings: StreamSettings,
+) {
+    let mut filter: Option<SeriesFilter> = None;
+    // Subscribed to the shared snapshots only once the client subscribes
+    let mut feed: Option<SnapshotFeed> = None;
+    let mut previous: Option<Arc<Snapshot>> = None;
+    let mut interval = stream::client_interval(settings.min_interval);
+
+    loop {
+        tokio::select! {
+            message = messages.next() => match message {
+                Some(Ok(AggregatedMessage::Text(text))) => {
+                    let reply = match serde_json::from_str::<ClientMessage>(&text) {
+                        Ok(request) => match request.subscribe.compile() {
+                            Ok(compiled) => {
+                                filter = Some(compiled);
+                                previous = None;
+                                interval = stream::client_interval(settings.interval_for(request.interval_ms));
+                                feed.get_or_insert_with(|| settings.subscribe());
+                                None
+                            }
+                            Err(e) => Some(error_message(format!("Invalid regex: {}", e))),
+                        },
+                        Err(e) => Some(error_message(format!("Invalid message: {}", e))),
+                    };
+                    if let Some(reply) = reply
+                        && session.text(reply).await.is_err()
+                    {
+                        return;
+                    }
+                }
+                Some(Ok(AggregatedMessage::Ping(bytes))) => {
+                    if session.pong(&bytes).await.is_err() {
+                        return;
+                    }
+                }
+                Some(Ok(AggregatedMessage::Close(reason))) => {
+                    let _ = session.close(reason).await;
+                    return;
+                }
+                Some(Ok(_)) => {}
+                Some(Err(_)) | None => break,
+            },
+            current = async {
+                match feed.as_mut() {
+                    Some(feed) => stream::next_snapshot(&mut interval, feed).await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                let (Some(filter), Some(current)) = (&filter, current) else {
+                    break;
+                };
+                let delta = current.delta_filtered(previous.as_deref(), |name| filter.matches(name));
+                if previous.is_none() || !delta.is_empty() {
+                    let data = serde_json::to_string(&delta).unwrap_or_else(|_| "{}".to_string());
+                    if session.text(data).await.is_err() {
+                        return;
+                    }
+                }
+                previous = Some(current);
+            }
+        }
+    }
+
+    let _ = session.close(None).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefix_subscription() {
+        let message: ClientMessage =
+            serde_json::from_str(r#"{"subscribe": {"prefix": "http_"}}"#).unwrap();
+        let filter = message.subscribe.compile().unwrap();
+
+        assert!(message.interval_ms.is_none());
+        assert!(filter.matches("http_requests_total"));
+        assert!(!filter.matches("db_queries_total"));
+    }
+
+    #[test]
+    fn test_regex_subscription() {
+        let message: ClientMessage = serde_json::from_str(
+            r#"{"subscribe": {"regex": "^(http|db)_.*_total$"}, "interval_ms": 2000}"#,
+        )
+        .unwrap();
+        let filter = message.subscribe.compile().unwrap();
+
+        assert_eq!(message.interval_ms, Some(2000));
+        assert!(filter.matches("db_queries_total"));
+        assert!(!filter.matches("db_queries_total_rate_per_sec"));
+    }
+
+    #[test]
+    fn test_invalid_regex_subscription() {
+        let message: ClientMessage =
+            serde_json::from_str(r#"{"subscribe": {"regex": "("}}"#).unwrap();
+
+        assert!(message.subscribe.compile().is_err());
+    }
+}